@@ -0,0 +1,31 @@
+use std::path::Path;
+use std::process::Command;
+
+/// Records the commit the binary is built from as `AUDIO_ROUTER_COMMIT`: its
+/// short hash, or `unknown` when building outside a git checkout.
+fn main() {
+    let commit = git(&["rev-parse", "--short", "HEAD"]);
+    println!(
+        "cargo:rustc-env=AUDIO_ROUTER_COMMIT={}",
+        commit.as_deref().unwrap_or("unknown")
+    );
+
+    // HEAD changes on checkout; the index is rewritten by every commit.
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        for file in ["HEAD", "index"] {
+            let path = Path::new(&git_dir).join(file);
+            if path.exists() {
+                println!("cargo:rerun-if-changed={}", path.display());
+            }
+        }
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
@@ -1,266 +1,2901 @@
-use anyhow::Result;
 use cpal::traits::{DeviceTrait, StreamTrait};
-use cpal::{BufferSize, Stream, StreamConfig};
+use cpal::{
+    BufferSize, Device, FromSample, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig,
+    SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange,
+};
 use log::{debug, error, info, warn};
-use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicBool, Ordering};
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb, Rb};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::config::{Config, DeviceType};
+use crate::bus::{MixBus, MixBuses};
+use crate::config::{
+    Config, DeviceConfig, DeviceType, DitherMode, NetworkConfig, RouteConfig, TestSignalConfig,
+};
+use crate::device_watch::DeviceWatch;
 use crate::devices::AudioDevices;
+use crate::dsp::{
+    process_frame, AudioSettings, DelayLine, FadeIn, FilterChain, FrameAligner, GainControl,
+    Resampler, UnderrunFill, MAX_FRAME_CHANNELS,
+};
+use crate::error::{Result, RouterError};
+use crate::hosts::AudioHosts;
+use crate::instance_lock::InstanceLock;
+use crate::live_view::{LiveView, RouteCommand};
+use crate::rt_log::{self, RtEvent, RtLog};
+use crate::sinks::{NullSink, RouteOutput, UdpSink};
+use crate::sources::{RouteInput, SignalGenerator, SyntheticSource, UdpSource};
+use crate::state::{RouteState, RuntimeState};
+use crate::status::{self, BlockStats, RouteStats, RouteStatus, ServiceStatus};
+use crate::wav::Wav;
+use crate::ReadyCallback;
 
 const NO_GAIN: f32 = 1.0;
-
-struct AudioSettings {
-    mix_ratio: f32,
-    sample_min: f32,
-    sample_max: f32,
-}
+const TEST_SOURCE_SAMPLE_RATE: u32 = 48000;
+const NETWORK_SAMPLE_RATE: u32 = 48000;
+const NULL_SINK_SAMPLE_RATE: u32 = 48000;
+const NULL_SINK_CHANNELS: u16 = 2;
+const SELF_TEST_FREQUENCY_HZ: f32 = 1000.0;
+const SELF_TEST_AMPLITUDE: f32 = 0.2;
+const SELF_TEST_DURATION: Duration = Duration::from_secs(1);
+const CLIP_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const CLIP_WARN_THRESHOLD: u64 = 100;
+const CALLBACK_PROFILE_INTERVAL: Duration = Duration::from_secs(10);
+/// Ring buffers larger than this (10 s of 8-channel 48 kHz audio) are
+/// assumed to be a config typo and replaced by the fallback size.
+const MAX_RING_BUFFER_SAMPLES: usize = 4_800_000;
+const FALLBACK_RING_BUFFER_SAMPLES: usize = 9600;
+const SILENCE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+const MAX_CONSECUTIVE_STREAM_ERRORS: u32 = 5;
+const RECOVERY_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const STATUS_WRITE_INTERVAL: Duration = Duration::from_secs(1);
 
 struct AudioRoute {
+    name: String,
     from_device: String,
     to_device: String,
-    input_stream: Stream,
-    output_stream: Stream,
+    input_stream: RouteInput,
+    output_stream: RouteOutput,
+    from_device_name: String,
+    to_device_name: String,
+    gain: Arc<GainControl>,
+    stats: Arc<RouteStats>,
+    buffered: Arc<AtomicUsize>,
+    needs_recovery: Arc<AtomicBool>,
+    enabled: Arc<AtomicBool>,
+    /// Set from a live view; independent of the schedule's `enabled`.
+    muted: Arc<AtomicBool>,
+    /// Input and output rates of a route running without resampling between
+    /// them, allowed by `audio.allow_rate_mismatch`.
+    rate_mismatch: Option<(u32, u32)>,
 }
 
-pub fn run_audio_routing(config: Config, running: Arc<AtomicBool>) -> Result<()> {
-    let host = cpal::default_host();
-    let devices = AudioDevices::find_all(&config, &host)?;
+enum InputKind<'a> {
+    Device {
+        device: &'a Device,
+        stream_cfg: SupportedStreamConfig,
+    },
+    Test(TestSignalConfig),
+    Udp(NetworkConfig),
+    Bus(Arc<MixBus>),
+}
 
-    validate_routing(&config)?;
+impl InputKind<'_> {
+    fn channels(&self) -> u16 {
+        match self {
+            InputKind::Device { stream_cfg, .. } => stream_cfg.channels(),
+            InputKind::Test(signal) => signal.channels,
+            InputKind::Udp(network) => network.channels,
+            InputKind::Bus(bus) => bus.channels(),
+        }
+    }
 
-    let mut routes = Vec::new();
+    fn sample_rate(&self, device_config: &DeviceConfig) -> SampleRate {
+        match self {
+            InputKind::Device { stream_cfg, .. } => stream_cfg.sample_rate(),
+            InputKind::Test(_) => {
+                SampleRate(device_config.sample_rate.unwrap_or(TEST_SOURCE_SAMPLE_RATE))
+            }
+            InputKind::Udp(_) => {
+                SampleRate(device_config.sample_rate.unwrap_or(NETWORK_SAMPLE_RATE))
+            }
+            InputKind::Bus(bus) => SampleRate(bus.sample_rate()),
+        }
+    }
 
-    for (buffer_index, (route_name, route_config)) in config.routing.iter().enumerate() {
-        info!(
-            "Setting up route: {} ({} -> {})",
-            route_name, route_config.from, route_config.to
-        );
+    /// The format the router receives samples in. Device input streams are
+    /// always opened as F32, whatever the device's default format.
+    fn format_name(&self) -> String {
+        match self {
+            InputKind::Device { .. } => "F32".to_string(),
+            InputKind::Test(_) => "synthetic".to_string(),
+            InputKind::Udp(_) => "RTP L16".to_string(),
+            InputKind::Bus(_) => "F32".to_string(),
+        }
+    }
+}
 
-        let from_device = devices.get(&route_config.from)?;
-        let to_device = devices.get(&route_config.to)?;
+enum OutputKind<'a> {
+    Device {
+        device: &'a Device,
+        stream_cfg: SupportedStreamConfig,
+    },
+    Udp(NetworkConfig),
+    Bus(Arc<MixBus>),
+    Null,
+}
 
-        let from_device_config = config
-            .devices
-            .get(&route_config.from)
-            .ok_or_else(|| anyhow::anyhow!("Device '{}' not found in config", route_config.from))?;
-        let to_device_config = config
-            .devices
-            .get(&route_config.to)
-            .ok_or_else(|| anyhow::anyhow!("Device '{}' not found in config", route_config.to))?;
+impl OutputKind<'_> {
+    fn channels(&self) -> u16 {
+        match self {
+            OutputKind::Device { stream_cfg, .. } => stream_cfg.channels(),
+            OutputKind::Udp(network) => network.channels,
+            OutputKind::Bus(bus) => bus.channels(),
+            OutputKind::Null => NULL_SINK_CHANNELS,
+        }
+    }
 
-        if from_device_config.device_type != DeviceType::Input {
-            return Err(anyhow::anyhow!(
-                "Route source '{}' must be an input device",
-                route_config.from
-            ));
+    fn sample_rate(&self, device_config: &DeviceConfig) -> SampleRate {
+        match self {
+            OutputKind::Device { stream_cfg, .. } => stream_cfg.sample_rate(),
+            OutputKind::Udp(_) => {
+                SampleRate(device_config.sample_rate.unwrap_or(NETWORK_SAMPLE_RATE))
+            }
+            OutputKind::Bus(bus) => SampleRate(bus.sample_rate()),
+            OutputKind::Null => {
+                SampleRate(device_config.sample_rate.unwrap_or(NULL_SINK_SAMPLE_RATE))
+            }
         }
-        if to_device_config.device_type != DeviceType::Output {
-            return Err(anyhow::anyhow!(
-                "Route destination '{}' must be an output device",
-                route_config.to
-            ));
+    }
+
+    /// UDP sinks always send 16-bit PCM; buses mix in `f32`, and null sinks
+    /// take it as is.
+    fn sample_format(&self) -> SampleFormat {
+        match self {
+            OutputKind::Device { stream_cfg, .. } => stream_cfg.sample_format(),
+            OutputKind::Udp(_) => SampleFormat::I16,
+            OutputKind::Bus(_) | OutputKind::Null => SampleFormat::F32,
         }
+    }
+}
+
+/// Shared between a stream's data and error callbacks so errors can be
+/// reported with route context and escalated to route recovery.
+struct StreamMonitor {
+    route: String,
+    direction: DeviceType,
+    device: String,
+    error_count: AtomicU64,
+    consecutive_errors: AtomicU32,
+    buffered: Arc<AtomicUsize>,
+    needs_recovery: Arc<AtomicBool>,
+}
 
-        let input_cfg = from_device.default_input_config()?;
-        let output_cfg = to_device.default_output_config()?;
+impl StreamMonitor {
+    fn on_data(&self) {
+        self.consecutive_errors.store(0, Ordering::Relaxed);
+    }
 
-        info!(
-            "  {} ({}): {} channels, {} Hz, format: {:?}",
-            route_config.from,
-            from_device_config.name,
-            input_cfg.channels(),
-            input_cfg.sample_rate().0,
-            input_cfg.sample_format()
-        );
-        info!(
-            "  {} ({}): {} channels, {} Hz, format: {:?}",
-            route_config.to,
-            to_device_config.name,
-            output_cfg.channels(),
-            output_cfg.sample_rate().0,
-            output_cfg.sample_format()
+    fn on_error(&self, err: cpal::StreamError) {
+        let total = self.error_count.fetch_add(1, Ordering::Relaxed) + 1;
+        let consecutive = self.consecutive_errors.fetch_add(1, Ordering::Relaxed) + 1;
+
+        error!(
+            "Route '{}' {} error #{} on '{}' (buffer fill: {} samples): {}",
+            self.route,
+            self.direction,
+            total,
+            self.device,
+            self.buffered.load(Ordering::Relaxed),
+            err
         );
 
-        if input_cfg.sample_rate() != output_cfg.sample_rate() {
+        if consecutive == MAX_CONSECUTIVE_STREAM_ERRORS {
             warn!(
-                "Sample rate mismatch in route '{}': {} Hz -> {} Hz",
-                route_name,
-                input_cfg.sample_rate().0,
-                output_cfg.sample_rate().0
+                "Route '{}' hit {} consecutive {} errors, scheduling recovery",
+                self.route, consecutive, self.direction
             );
+            self.needs_recovery.store(true, Ordering::SeqCst);
         }
+    }
+}
 
-        let buffer_size = from_device_config.primary_buffer;
+pub fn run_audio_routing(
+    config: Config,
+    running: Arc<AtomicBool>,
+    require_all_routes: bool,
+    live_view: Option<LiveView>,
+    on_ready: Option<ReadyCallback>,
+) -> Result<()> {
+    for field in &config.unknown_fields {
+        warn!("Unknown config field '{}' ignored, check for a typo", field);
+    }
 
-        let rb = HeapRb::<f32>::new(buffer_size);
-        let (mut producer, mut consumer): (HeapProducer<f32>, HeapConsumer<f32>) = rb.split();
+    if config.routing.is_empty() {
+        if !config.allow_empty_routing {
+            return Err(RouterError::NoRoutesConfigured);
+        }
+        warn!("==================================================");
+        warn!("No routes configured, running idle (allow_empty_routing=true)");
+        warn!("==================================================");
+    }
 
-        if buffer_index > 0 && config.audio.prefill_samples > 0 {
-            debug!(
-                "Pre-filling buffer for route '{}' with {} silence samples",
-                route_name, config.audio.prefill_samples
-            );
-            for _ in 0..config.audio.prefill_samples {
-                producer.push(0.0).ok();
-            }
+    if let Some(max_routes) = config.audio.max_routes {
+        if config.routing.len() > max_routes {
+            return Err(RouterError::TooManyRoutes {
+                configured: config.routing.len(),
+                max: max_routes,
+            });
         }
+    }
+    log_resource_estimate(&config);
 
-        let buffer_size_config = BufferSize::Fixed(from_device_config.buffer_size);
+    // Held until routing stops; dropping it removes the lock file.
+    let _instance_lock = if config.instance_lock {
+        InstanceLock::acquire()?
+    } else {
+        None
+    };
 
-        let gain = from_device_config.gain;
+    let hosts = AudioHosts::open(&config)?;
+    let mut devices = AudioDevices::find_all(&config, &hosts)?;
 
-        if gain != NO_GAIN {
-            info!("  Applying gain of {} to input", gain);
-        }
+    validate_routing(&config)?;
 
-        let in_channels = input_cfg.channels();
-        let out_channels = output_cfg.channels();
+    let mut buses = MixBuses::new();
+    for (name, bus_config) in &config.buses {
+        let bus =
+            MixBus::start(name, bus_config, &config).map_err(|e| RouterError::StreamStart {
+                device: name.clone(),
+                source: e.into(),
+            })?;
+        buses.insert(name.clone(), bus);
+    }
 
-        let from_name = route_config.from.clone();
-        let to_name = route_config.to.clone();
-        let audio_settings = AudioSettings {
-            mix_ratio: config.audio.stereo_to_mono_mix_ratio,
-            sample_min: config.audio.audio_sample_min,
-            sample_max: config.audio.audio_sample_max,
-        };
+    let mut routes = Vec::new();
+    let mut pending = Vec::new();
 
-        let input_stream = from_device.build_input_stream(
-            &StreamConfig {
-                channels: input_cfg.channels(),
-                sample_rate: input_cfg.sample_rate(),
-                buffer_size: buffer_size_config,
-            },
-            move |data: &[f32], _| {
-                handle_input_data(
-                    data,
-                    &mut producer,
-                    in_channels,
-                    out_channels,
-                    gain,
-                    &audio_settings,
-                );
-            },
-            move |err| error!("Input error on '{}': {}", from_name, err),
-            None,
-        )?;
+    // Higher priorities first; equal priorities keep name order. Buffer
+    // indices still follow name order.
+    let mut setup_order: Vec<_> = config.routing.iter().enumerate().collect();
+    setup_order.sort_by_key(|(_, (_, route_config))| Reverse(route_config.priority));
 
-        let output_stream = to_device.build_output_stream(
-            &StreamConfig {
-                channels: output_cfg.channels(),
-                sample_rate: output_cfg.sample_rate(),
-                buffer_size: buffer_size_config,
-            },
-            move |data: &mut [f32], _| {
-                for sample in data {
-                    *sample = consumer.pop().unwrap_or(0.0);
-                }
-            },
-            move |err| error!("Output error on '{}': {}", to_name, err),
-            None,
-        )?;
+    for (buffer_index, (route_name, route_config)) in setup_order {
+        let missing = missing_devices(&config, &devices, route_config);
+        if !missing.is_empty() {
+            warn!(
+                "Route '{}' will start when its devices appear: {:?}",
+                route_name, missing
+            );
+            pending.push(route_name.clone());
+            continue;
+        }
 
-        routes.push(AudioRoute {
-            from_device: route_config.from.clone(),
-            to_device: route_config.to.clone(),
-            input_stream,
-            output_stream,
+        match setup_route_on_outputs(
+            &config,
+            &devices,
+            &buses,
+            buffer_index,
+            route_name,
+            route_config,
+        ) {
+            Ok(route) => routes.push(route),
+            Err(e) => error!(
+                "Skipping route '{}' (priority {}): {:#}",
+                route_name, route_config.priority, e
+            ),
+        }
+    }
+
+    if routes.is_empty() && pending.is_empty() && !config.routing.is_empty() {
+        return Err(RouterError::NoRoutesStarted);
+    }
+    if require_all_routes && routes.len() < config.routing.len() {
+        return Err(RouterError::RoutesNotStarted {
+            started: routes.len(),
+            configured: config.routing.len(),
         });
     }
+    if routes.len() < config.routing.len() {
+        warn!(
+            "Continuing with {} of {} routes",
+            routes.len(),
+            config.routing.len()
+        );
+    }
+
+    if config.persist_runtime_state {
+        restore_runtime_state(&routes);
+    }
 
+    // Outputs start before any input, so every ring buffer is already being
+    // drained when the first input callback produces audio.
+    for route in &routes {
+        play_output(route)?;
+    }
     for route in &routes {
-        route.input_stream.play()?;
-        info!("Started input stream: {}", route.from_device);
-        route.output_stream.play()?;
-        info!("Started output stream: {}", route.to_device);
+        play_input(route)?;
     }
 
-    info!("Audio routing active with {} routes:", routes.len());
+    if config.audio.warmup_ms > 0 {
+        info!(
+            "Audio routing started with {} routes, stabilizing for {} ms:",
+            routes.len(),
+            config.audio.warmup_ms
+        );
+    } else {
+        info!("Audio routing active with {} routes:", routes.len());
+    }
     for route in &routes {
         info!("  {} → {}", route.from_device, route.to_device);
     }
+    if let Some(ready) = on_ready {
+        ready();
+    }
+
+    keep_alive(
+        RunControl { running, live_view },
+        &mut routes,
+        pending,
+        &config,
+        &mut devices,
+        &buses,
+        &hosts,
+    );
+
+    shutdown(
+        routes,
+        Duration::from_millis(config.audio.shutdown_drain_ms),
+    );
 
-    keep_alive(running, routes, config.audio.keep_alive_sleep_ms);
+    if let Ok(path) = status::status_path() {
+        let _ = fs::remove_file(path);
+    }
 
+    rt_log::drain();
     info!("Audio routing stopped");
     Ok(())
 }
 
-fn validate_routing(config: &Config) -> Result<()> {
-    for (route_name, route) in &config.routing {
-        if !config.devices.contains_key(&route.from) {
-            return Err(anyhow::anyhow!(
-                "Route '{}' references unknown source device: '{}'",
-                route_name,
-                route.from
-            ));
+fn log_resampler(
+    route_name: &str,
+    side: &str,
+    from_rate: u32,
+    to_rate: u32,
+    resampler: &Resampler,
+) {
+    info!(
+        "  Route '{}' resampling on {}: {}→{} Hz, {}, adds {} samples of latency",
+        route_name,
+        side,
+        from_rate,
+        to_rate,
+        resampler.describe(),
+        resampler.latency_frames()
+    );
+}
+
+/// Logs how many device streams the routes will open and roughly how much
+/// memory their ring buffers take.
+fn log_resource_estimate(config: &Config) {
+    let mut streams = 0;
+    let mut buffer_bytes = 0;
+
+    for route in config.routing.values() {
+        for alias in [&route.from, &route.to] {
+            if config
+                .devices
+                .get(alias)
+                .is_some_and(|device| device.is_hardware())
+            {
+                streams += 1;
+            }
         }
-        if !config.devices.contains_key(&route.to) {
-            return Err(anyhow::anyhow!(
-                "Route '{}' references unknown destination device: '{}'",
-                route_name,
-                route.to
-            ));
+        let buffer_size = route
+            .buffer_samples
+            .or_else(|| config.endpoint(&route.from).map(|from| from.primary_buffer));
+        let buffer_size = match buffer_size {
+            Some(size) if size == 0 || size > MAX_RING_BUFFER_SAMPLES => {
+                FALLBACK_RING_BUFFER_SAMPLES
+            }
+            size => size.unwrap_or(0),
+        };
+        buffer_bytes += buffer_size * std::mem::size_of::<f32>();
+    }
+
+    info!(
+        "{} routes will open {} device streams, ring buffers use about {} KiB",
+        config.routing.len(),
+        streams,
+        buffer_bytes.div_ceil(1024)
+    );
+}
+
+/// Allocates a route's ring buffer, falling back to a small default instead
+/// of aborting when the configured size is zero, absurdly large or cannot
+/// be allocated.
+fn allocate_ring_buffer(route_name: &str, buffer_size: usize) -> HeapRb<f32> {
+    if buffer_size == 0 || buffer_size > MAX_RING_BUFFER_SAMPLES {
+        warn!(
+            "Route '{}' asks for a {} sample ring buffer (limit {}), using {} instead",
+            route_name, buffer_size, MAX_RING_BUFFER_SAMPLES, FALLBACK_RING_BUFFER_SAMPLES
+        );
+        return HeapRb::new(FALLBACK_RING_BUFFER_SAMPLES);
+    }
+
+    HeapRb::try_new(buffer_size).unwrap_or_else(|e| {
+        warn!(
+            "Route '{}' could not allocate a {} sample ring buffer ({}), using {} instead",
+            route_name, buffer_size, e, FALLBACK_RING_BUFFER_SAMPLES
+        );
+        HeapRb::new(FALLBACK_RING_BUFFER_SAMPLES)
+    })
+}
+
+fn lookup_device<'a>(
+    config: &Config,
+    devices: &'a AudioDevices,
+    alias: &str,
+) -> Result<&'a Device> {
+    devices
+        .get(alias)
+        .ok_or_else(|| RouterError::DeviceNotFound {
+            device: alias.to_string(),
+            identifier: config
+                .devices
+                .get(alias)
+                .map(DeviceConfig::identifier)
+                .unwrap_or_default(),
+        })
+}
+
+fn lookup_bus(buses: &MixBuses, name: &str) -> Result<Arc<MixBus>> {
+    buses
+        .get(name)
+        .cloned()
+        .ok_or_else(|| RouterError::DeviceNotConfigured(name.to_string()))
+}
+
+fn start_route(route: &AudioRoute) -> Result<()> {
+    play_output(route)?;
+    play_input(route)
+}
+
+fn play_output(route: &AudioRoute) -> Result<()> {
+    route
+        .output_stream
+        .play()
+        .map_err(|e| RouterError::StreamStart {
+            device: route.to_device.clone(),
+            source: e.into(),
+        })?;
+    info!("Started output stream: {}", route.to_device);
+    Ok(())
+}
+
+fn play_input(route: &AudioRoute) -> Result<()> {
+    route
+        .input_stream
+        .play()
+        .map_err(|e| RouterError::StreamStart {
+            device: route.from_device.clone(),
+            source: e.into(),
+        })?;
+    info!("Started input stream: {}", route.from_device);
+    Ok(())
+}
+
+/// Sets up a route, retrying with a doubling delay when a stream fails to
+/// build, which drivers sometimes do for a moment after a device appears.
+fn setup_route_with_retry(
+    config: &Config,
+    devices: &AudioDevices,
+    buses: &MixBuses,
+    buffer_index: usize,
+    route_name: &str,
+    route_config: &RouteConfig,
+) -> Result<AudioRoute> {
+    let retries = config.audio.stream_build_retries;
+    let mut delay = Duration::from_millis(config.audio.stream_build_retry_ms);
+
+    let mut attempt = 0;
+    loop {
+        match setup_route(
+            config,
+            devices,
+            buses,
+            buffer_index,
+            route_name,
+            route_config,
+        ) {
+            Err(e @ RouterError::StreamBuild { .. }) if attempt < retries => {
+                attempt += 1;
+                warn!(
+                    "Route '{}': {:#}; retrying in {}ms (attempt {} of {})",
+                    route_name,
+                    e,
+                    delay.as_millis(),
+                    attempt,
+                    retries
+                );
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
         }
     }
+}
+
+/// Sets up a route on the first of its outputs that is present and whose
+/// streams build: `to`, then each `to_fallback` entry in order.
+fn setup_route_on_outputs(
+    config: &Config,
+    devices: &AudioDevices,
+    buses: &MixBuses,
+    buffer_index: usize,
+    route_name: &str,
+    route_config: &RouteConfig,
+) -> Result<AudioRoute> {
+    if route_config.to_fallback.is_empty() {
+        return setup_route_with_retry(
+            config,
+            devices,
+            buses,
+            buffer_index,
+            route_name,
+            route_config,
+        );
+    }
 
-    let mut seen_routes = HashMap::new();
-    for (route_name, route) in &config.routing {
-        let key = format!("{}->{}", route.from, route.to);
-        if let Some(existing) = seen_routes.get(&key) {
+    let mut last_error = None;
+    for output in route_config.outputs() {
+        let present = config
+            .devices
+            .get(output)
+            .is_none_or(|device_config| !device_config.is_hardware())
+            || devices.contains(output);
+        if !present {
+            debug!("Route '{}': output '{}' is not present", route_name, output);
+            continue;
+        }
+
+        let candidate = RouteConfig {
+            to: output.clone(),
+            to_fallback: Vec::new(),
+            ..route_config.clone()
+        };
+        match setup_route_with_retry(config, devices, buses, buffer_index, route_name, &candidate) {
+            Ok(route) => {
+                if *output != route_config.to {
+                    warn!(
+                        "Route '{}' using fallback output '{}' instead of '{}'",
+                        route_name, output, route_config.to
+                    );
+                }
+                return Ok(route);
+            }
+            Err(e) => {
+                warn!(
+                    "Route '{}' could not use output '{}': {:#}",
+                    route_name, output, e
+                );
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| RouterError::DevicesNotFound {
+        devices: route_config.outputs().cloned().collect(),
+        waited: None,
+    }))
+}
+
+fn setup_route(
+    config: &Config,
+    devices: &AudioDevices,
+    buses: &MixBuses,
+    buffer_index: usize,
+    route_name: &str,
+    route_config: &RouteConfig,
+) -> Result<AudioRoute> {
+    info!(
+        "Setting up route: {} ({} -> {})",
+        route_name, route_config.from, route_config.to
+    );
+
+    let from_device_config = config
+        .endpoint(&route_config.from)
+        .ok_or_else(|| RouterError::DeviceNotConfigured(route_config.from.clone()))?;
+    let to_device_config = config
+        .endpoint(&route_config.to)
+        .ok_or_else(|| RouterError::DeviceNotConfigured(route_config.to.clone()))?;
+    let from_device_config = match route_config.input_rate {
+        Some(rate) => Cow::Owned(DeviceConfig {
+            sample_rate: Some(rate),
+            ..from_device_config.into_owned()
+        }),
+        None => from_device_config,
+    };
+    let (from_device_config, to_device_config) = (&*from_device_config, &*to_device_config);
+
+    if !from_device_config.is_capture_source() {
+        return Err(RouterError::WrongDeviceType {
+            device: route_config.from.clone(),
+            reason: format!(
+                "Route source '{}' must be an input device or an output device with loopback enabled",
+                route_config.from
+            ),
+        });
+    }
+    if from_device_config.loopback {
+        if cfg!(windows) {
+            info!("  Capturing '{}' in loopback mode", route_config.from);
+        } else {
             warn!(
-                "Duplicate route detected: '{}' and '{}' both route {} -> {}",
-                existing, route_name, route.from, route.to
+                "  Loopback capture of '{}' is only supported on the Windows WASAPI host",
+                route_config.from
             );
         }
-        seen_routes.insert(key, route_name);
+    }
+    if !to_device_config.is_sink() {
+        return Err(RouterError::WrongDeviceType {
+            device: route_config.to.clone(),
+            reason: format!(
+                "Route destination '{}' must be an output, udp or null device or a bus",
+                route_config.to
+            ),
+        });
     }
 
-    Ok(())
-}
+    let input = match (&from_device_config.device_type, &from_device_config.network) {
+        (DeviceType::Test, _) => InputKind::Test(from_device_config.test_signal()),
+        (DeviceType::Udp, Some(network)) => InputKind::Udp(network.clone()),
+        (DeviceType::Bus, _) => InputKind::Bus(lookup_bus(buses, &route_config.from)?),
+        _ => {
+            let device = lookup_device(config, devices, &route_config.from)?;
+            let stream_cfg = select_stream_config(device, from_device_config, &route_config.from)?;
+            InputKind::Device { device, stream_cfg }
+        }
+    };
+    let output = match &to_device_config.network {
+        Some(network) if to_device_config.device_type == DeviceType::Udp => {
+            OutputKind::Udp(network.clone())
+        }
+        _ if to_device_config.device_type == DeviceType::Bus => {
+            OutputKind::Bus(lookup_bus(buses, &route_config.to)?)
+        }
+        _ if to_device_config.device_type == DeviceType::Null => OutputKind::Null,
+        _ => {
+            let device = lookup_device(config, devices, &route_config.to)?;
+            let stream_cfg = select_stream_config(device, to_device_config, &route_config.to)?;
+            OutputKind::Device { device, stream_cfg }
+        }
+    };
 
-fn handle_input_data(
-    data: &[f32],
-    producer: &mut HeapProducer<f32>,
-    in_channels: u16,
-    out_channels: u16,
-    gain: f32,
-    audio_settings: &AudioSettings,
-) {
-    if in_channels == 1 && out_channels == 2 {
-        for &sample in data {
-            if !producer.is_full() {
-                let boosted =
-                    (sample * gain).clamp(audio_settings.sample_min, audio_settings.sample_max);
-                producer.push(boosted).ok();
-                producer.push(boosted).ok();
+    let source_channels = input.channels();
+    let input_rate = input.sample_rate(from_device_config);
+    let device_channels = output.channels();
+    let output_rate = output.sample_rate(to_device_config);
+    let output_format = output.sample_format();
+
+    info!(
+        "  {} ({}): {} channels, {} Hz, format: {}",
+        route_config.from,
+        from_device_config.identifier(),
+        source_channels,
+        input_rate.0,
+        input.format_name()
+    );
+    info!(
+        "  {} ({}): {} channels, {} Hz, format: {:?}",
+        route_config.to,
+        to_device_config.identifier(),
+        device_channels,
+        output_rate.0,
+        output_format
+    );
+
+    // The rate the route's processing and buffer run at.
+    let route_rate = route_config.target_rate.unwrap_or(input_rate.0);
+    let resample_input = route_rate != input_rate.0;
+    let resample_output = route_config.target_rate.is_some() && route_rate != output_rate.0;
+    let rate_mismatch = (route_config.target_rate.is_none() && input_rate != output_rate)
+        .then_some((input_rate.0, output_rate.0));
+    if let Some((from_rate, to_rate)) = rate_mismatch {
+        if !config.audio.allow_rate_mismatch {
+            return Err(RouterError::InvalidRoute {
+                route: route_name.to_string(),
+                reason: format!(
+                    "reads {} Hz from '{}' but '{}' plays {} Hz; set target_rate to resample, \
+                     or audio.allow_rate_mismatch to play it at the wrong speed",
+                    from_rate, route_config.from, route_config.to, to_rate
+                ),
+            });
+        }
+        warn_rate_mismatch(route_name, from_rate, to_rate);
+    }
+    if route_config.passthrough && (resample_input || resample_output) {
+        return Err(RouterError::InvalidRoute {
+            route: route_name.to_string(),
+            reason: "is a passthrough route, but needs resampling for its target_rate".to_string(),
+        });
+    }
+
+    let in_channels = match &route_config.input_channels {
+        Some(channel_map) => {
+            if channel_map.is_empty() {
+                return Err(RouterError::InvalidRoute {
+                    route: route_name.to_string(),
+                    reason: "has an empty input_channels list".to_string(),
+                });
+            }
+            if let Some(&invalid) = channel_map
+                .iter()
+                .find(|&&channel| channel >= source_channels as usize)
+            {
+                return Err(RouterError::InvalidRoute {
+                    route: route_name.to_string(),
+                    reason: format!(
+                        "selects input channel {}, but '{}' only has {} channels",
+                        invalid, route_config.from, source_channels
+                    ),
+                });
             }
+            info!("  Selecting input channels {:?}", channel_map);
+            channel_map.len() as u16
         }
-    } else if in_channels == 2 && out_channels == 1 {
-        for chunk in data.chunks(2) {
-            if chunk.len() == 2 && !producer.is_full() {
-                let mixed = ((chunk[0] + chunk[1]) * audio_settings.mix_ratio * gain)
-                    .clamp(audio_settings.sample_min, audio_settings.sample_max);
-                producer.push(mixed).ok();
+        None => source_channels,
+    };
+
+    let out_channels = match &route_config.output_channels {
+        Some(channel_map) => {
+            if channel_map.is_empty() {
+                return Err(RouterError::InvalidRoute {
+                    route: route_name.to_string(),
+                    reason: "has an empty output_channels list".to_string(),
+                });
+            }
+            if let Some(&invalid) = channel_map
+                .iter()
+                .find(|&&channel| channel >= device_channels as usize)
+            {
+                return Err(RouterError::InvalidRoute {
+                    route: route_name.to_string(),
+                    reason: format!(
+                        "maps to output channel {}, but '{}' only has {} channels",
+                        invalid, route_config.to, device_channels
+                    ),
+                });
             }
+            info!(
+                "  Mapping route channels to output channels {:?}",
+                channel_map
+            );
+            channel_map.len() as u16
         }
-    } else {
-        for &sample in data {
-            if !producer.is_full() {
-                let boosted =
-                    (sample * gain).clamp(audio_settings.sample_min, audio_settings.sample_max);
-                producer.push(boosted).ok();
+        None => device_channels,
+    };
+
+    if route_config.passthrough {
+        if in_channels != out_channels {
+            return Err(RouterError::InvalidRoute {
+                route: route_name.to_string(),
+                reason: format!(
+                    "is a passthrough route, but has {} input and {} output channels",
+                    in_channels, out_channels
+                ),
+            });
+        }
+        info!("  Passthrough: copying samples without processing");
+    }
+
+    let mut transforms = Vec::new();
+    match (in_channels, out_channels) {
+        (from, to) if from == to => {}
+        (2, 1) => transforms.push("downmix 2→1".to_string()),
+        (1, 2) => transforms.push("upmix 1→2".to_string()),
+        (from, to) => transforms.push(format!("no conversion for {}→{} channels", from, to)),
+    }
+    if resample_input {
+        transforms.push(format!("resample {}→{} on input", input_rate.0, route_rate));
+    }
+    if resample_output {
+        transforms.push(format!(
+            "resample {}→{} on output",
+            route_rate, output_rate.0
+        ));
+    }
+    if rate_mismatch.is_some() {
+        transforms.push(format!(
+            "no resampling for {}→{} Hz",
+            input_rate.0, output_rate.0
+        ));
+    }
+    if let InputKind::Udp(_) = input {
+        transforms.push(format!("format-convert {}→F32", input.format_name()));
+    }
+    if output_format != SampleFormat::F32 {
+        transforms.push(format!("format-convert F32→{:?}", output_format));
+    }
+    info!(
+        "  Route '{}' conversions: {}",
+        route_name,
+        if transforms.is_empty() {
+            "none".to_string()
+        } else {
+            transforms.join(", ")
+        }
+    );
+
+    let buffer_size = route_config
+        .buffer_samples
+        .unwrap_or(from_device_config.primary_buffer);
+    let rb = allocate_ring_buffer(route_name, buffer_size);
+    info!("  Using a {} sample ring buffer", rb.capacity());
+    let (mut producer, consumer): (HeapProducer<f32>, HeapConsumer<f32>) = rb.split();
+
+    // The rate the ring buffer drains at.
+    let buffer_rate = route_config.target_rate.unwrap_or(output_rate.0);
+    let prefill_samples = match config.audio.prefill_ms {
+        Some(prefill_ms) => {
+            let samples = prefill_ms as usize * buffer_rate as usize / 1000 * out_channels as usize;
+            debug!(
+                "Using prefill_ms={} for route '{}' ({} samples)",
+                prefill_ms, route_name, samples
+            );
+            samples
+        }
+        None => {
+            debug!(
+                "Using prefill_samples={} for route '{}'",
+                config.audio.prefill_samples, route_name
+            );
+            config.audio.prefill_samples
+        }
+    };
+
+    if buffer_index > 0 && prefill_samples > 0 {
+        debug!(
+            "Pre-filling buffer for route '{}' with {} silence samples",
+            route_name, prefill_samples
+        );
+        for _ in 0..prefill_samples {
+            producer.push(0.0).ok();
+        }
+    }
+    let prefilled = producer.len();
+
+    let gain = from_device_config.gain;
+    let gain_control = Arc::new(GainControl::new(gain));
+    let input_gain = gain_control.clone();
+
+    if gain != NO_GAIN {
+        info!("  Applying gain of {} to input", gain);
+    }
+
+    if route_config.stereo_width != 1.0 {
+        if in_channels == 2 && out_channels == 2 {
+            info!("  Applying stereo width {}", route_config.stereo_width);
+        } else {
+            warn!(
+                "  Route '{}' has stereo_width set, but is not a 2 -> 2 channel route; ignoring",
+                route_name
+            );
+        }
+    }
+
+    let channel_gains = route_config.channel_gains();
+    if channel_gains != [NO_GAIN, NO_GAIN] {
+        if in_channels == 2 && out_channels == 2 {
+            info!(
+                "  Applying channel gains L={} R={}",
+                channel_gains[0], channel_gains[1]
+            );
+        } else {
+            warn!(
+                "  Route '{}' has left/right gain or balance set, but is not a 2 -> 2 channel route; ignoring",
+                route_name
+            );
+        }
+    }
+
+    let buffered = Arc::new(AtomicUsize::new(0));
+    let needs_recovery = Arc::new(AtomicBool::new(false));
+    let input_monitor = Arc::new(StreamMonitor {
+        route: route_name.to_string(),
+        direction: DeviceType::Input,
+        device: route_config.from.clone(),
+        error_count: AtomicU64::new(0),
+        consecutive_errors: AtomicU32::new(0),
+        buffered: buffered.clone(),
+        needs_recovery: needs_recovery.clone(),
+    });
+    let output_monitor = Arc::new(StreamMonitor {
+        route: route_name.to_string(),
+        direction: DeviceType::Output,
+        device: route_config.to.clone(),
+        error_count: AtomicU64::new(0),
+        consecutive_errors: AtomicU32::new(0),
+        buffered: buffered.clone(),
+        needs_recovery: needs_recovery.clone(),
+    });
+    let input_error_monitor = input_monitor.clone();
+    let (mut audio_settings, mut filters) = route_processing(
+        config,
+        route_config,
+        gain,
+        in_channels.max(out_channels),
+        route_rate,
+    );
+    if !route_config.clamp && !route_config.passthrough {
+        if output_format.is_float() {
+            info!("  Passing samples unclamped to {:?} output", output_format);
+        } else {
+            warn!(
+                "  Route '{}' has clamp: false, but its output takes {:?} samples; clamping anyway",
+                route_name, output_format
+            );
+            audio_settings.clamp = true;
+        }
+    }
+    let clamp = audio_settings.clamp;
+    if config.audio.dc_block {
+        info!("  Applying DC blocking filter");
+    }
+    if let Some(highpass_hz) = route_config.highpass_hz {
+        info!("  Applying {} Hz high-pass filter", highpass_hz);
+    }
+    if !route_config.eq.is_empty() {
+        info!("  Applying {}-band EQ", route_config.eq.len());
+    }
+    if let Some(agc) = &route_config.agc {
+        info!(
+            "  Applying AGC (target {}, max gain {})",
+            agc.target_level, agc.max_gain
+        );
+    }
+
+    let stats = Arc::new(RouteStats::default());
+    stats.record_pushed(prefilled as u64);
+    let input_stats = stats.clone();
+
+    let enabled = Arc::new(AtomicBool::new(true));
+    if let Some(schedule) = &route_config.schedule {
+        let active = schedule.is_active(chrono::Local::now().time());
+        enabled.store(active, Ordering::SeqCst);
+        info!(
+            "  Scheduled from {} to {} (currently {})",
+            schedule.enable,
+            schedule.disable,
+            if active { "enabled" } else { "disabled" }
+        );
+    }
+    let input_enabled = enabled.clone();
+    let muted = Arc::new(AtomicBool::new(false));
+    let input_muted = muted.clone();
+    let input_channel_map = route_config.input_channels.clone();
+    let mut aligner = FrameAligner::new(source_channels as usize);
+    let mut selected = Vec::new();
+    let filter_taps = config.audio.resampling.filter_taps();
+    let mut input_resampler = resample_input.then(|| {
+        let resampler = Resampler::new(input_rate.0, route_rate, in_channels as usize, filter_taps);
+        log_resampler(route_name, "input", input_rate.0, route_rate, &resampler);
+        resampler
+    });
+    let mut resampled = Vec::new();
+
+    let profile_callbacks = config.audio.profile_callbacks;
+    let passthrough = route_config.passthrough;
+    let mut input_rt_log = RtLog::open(format!("Route '{}' input", route_name));
+    let mut overflow_reported = false;
+    let on_input = move |data: &[f32]| {
+        let callback_started = profile_callbacks.then(Instant::now);
+        let data = aligner.align(data);
+        input_stats.record_input_frames(data.len() / source_channels.max(1) as usize);
+        let data = match &input_channel_map {
+            Some(channel_map) => {
+                selected.clear();
+                for frame in data.chunks_exact(source_channels.max(1) as usize) {
+                    selected.extend(channel_map.iter().map(|&channel| frame[channel]));
+                }
+                &selected[..]
+            }
+            None => data,
+        };
+        let data = match input_resampler.as_mut() {
+            Some(resampler) => {
+                resampled.clear();
+                resampler.push(data, &mut resampled);
+                &resampled[..]
+            }
+            None => data,
+        };
+
+        let active = input_enabled.load(Ordering::Relaxed) && !input_muted.load(Ordering::Relaxed);
+        let dropped = if active && passthrough {
+            copy_input_data(data, &mut producer, in_channels, &input_stats)
+        } else if active {
+            audio_settings.gain = input_gain.get();
+            handle_input_data(
+                data,
+                &mut producer,
+                in_channels,
+                out_channels,
+                &audio_settings,
+                &mut filters,
+                &input_stats,
+            )
+        } else {
+            // Keep the output fed while disabled so it does not underrun.
+            let frames = data.len() / in_channels.max(1) as usize;
+            let samples = frames * out_channels as usize;
+            for _ in 0..samples {
+                if producer.push(0.0).is_err() {
+                    break;
+                }
             }
+            input_stats.record_pushed(samples as u64);
+            0
+        };
+        if dropped > 0 && !overflow_reported {
+            overflow_reported = true;
+            input_rt_log.report(RtEvent::FirstOverflow);
         }
+
+        if let Some(started) = callback_started {
+            input_stats.input_callbacks.record(started.elapsed());
+        }
+    };
+
+    let (input_stream, from_device_name) = match input {
+        InputKind::Device { device, stream_cfg } => {
+            let input_buffer_size = negotiate_buffer_size(
+                device,
+                &stream_cfg,
+                from_device_config.device_type.clone(),
+                from_device_config.buffer_size,
+                &route_config.from,
+            );
+            let mut on_input = on_input;
+            let stream_config = StreamConfig {
+                channels: source_channels,
+                sample_rate: input_rate,
+                buffer_size: input_buffer_size,
+            };
+            let stream = device
+                .build_input_stream(
+                    &stream_config,
+                    move |data: &[f32], _| {
+                        input_monitor.on_data();
+                        on_input(data);
+                    },
+                    move |err| input_error_monitor.on_error(err),
+                    None,
+                )
+                .map_err(|e| RouterError::StreamBuild {
+                    details: stream_build_error(
+                        device,
+                        DeviceType::Input,
+                        &route_config.from,
+                        &stream_config,
+                        SampleFormat::F32,
+                    ),
+                    source: e.into(),
+                })?;
+            info!(
+                "  {} stream opened: {}",
+                route_config.from,
+                describe_stream_config(&stream_config, SampleFormat::F32)
+            );
+            (
+                RouteInput::Device(stream),
+                device.name().unwrap_or_default(),
+            )
+        }
+        InputKind::Test(signal) => {
+            let generator = SignalGenerator::new(signal, input_rate.0);
+            let source = SyntheticSource::new(&route_config.from, generator, Box::new(on_input));
+            (
+                RouteInput::Synthetic(source),
+                from_device_config.identifier(),
+            )
+        }
+        InputKind::Udp(network) => {
+            let source = UdpSource::new(
+                &route_config.from,
+                &network.address,
+                input_rate.0,
+                source_channels,
+                Box::new(on_input),
+            )
+            .map_err(|e| RouterError::Network {
+                device: route_config.from.clone(),
+                source: e.into(),
+            })?;
+            (RouteInput::Udp(source), from_device_config.identifier())
+        }
+        InputKind::Bus(bus) => (
+            RouteInput::Bus(bus.source(Box::new(on_input))),
+            from_device_config.identifier(),
+        ),
+    };
+
+    let dither = match config.audio.dither {
+        _ if route_config.passthrough => None,
+        DitherMode::Tpdf => TpdfDither::for_format(output_format),
+        DitherMode::None => None,
+    };
+
+    if dither.is_some() {
+        info!("  Applying TPDF dither to {:?} output", output_format);
     }
-}
 
-fn keep_alive(running: Arc<AtomicBool>, _routes: Vec<AudioRoute>, sleep_ms: u64) {
-    while running.load(Ordering::SeqCst) {
-        thread::sleep(Duration::from_millis(sleep_ms));
+    let output_gain = if route_config.passthrough {
+        NO_GAIN
+    } else {
+        to_device_config.output_gain
+    };
+    if output_gain != NO_GAIN {
+        info!("  Applying gain of {} to output", output_gain);
+    }
+
+    let fade_frames = if route_config.passthrough {
+        0
+    } else {
+        config.audio.startup_fade_ms as usize * output_rate.0 as usize / 1000
+    };
+    if fade_frames > 0 {
+        debug!(
+            "  Fading in over {}ms ({} frames)",
+            config.audio.startup_fade_ms, fade_frames
+        );
+    }
+
+    let delay = route_config.delay_ms.filter(|&ms| ms > 0).map(|delay_ms| {
+        let frames = delay_ms as usize * output_rate.0 as usize / 1000;
+        info!("  Delaying output by {} ms ({} frames)", delay_ms, frames);
+        DelayLine::new(frames, out_channels as usize)
+    });
+
+    let feed = OutputFeed {
+        consumer,
+        fade_in: FadeIn::new(fade_frames),
+        underrun_fill: UnderrunFill::new(config.audio.underrun_strategy, route_rate),
+        resampler: resample_output.then(|| {
+            let resampler = Resampler::new(
+                route_rate,
+                output_rate.0,
+                out_channels as usize,
+                filter_taps,
+            );
+            log_resampler(route_name, "output", route_rate, output_rate.0, &resampler);
+            resampler
+        }),
+        delay,
+        profile: config.audio.profile_callbacks,
+        output_gain,
+        sample_min: config.audio.audio_sample_min,
+        sample_max: config.audio.audio_sample_max,
+        clamp,
+        dither,
+        channel_map: route_config.output_channels.clone(),
+        device_channels: device_channels as usize,
+        buffered: buffered.clone(),
+        stats: stats.clone(),
+        rt_log: RtLog::open(format!("Route '{}' output", route_name)),
+        underrun_reported: false,
+    };
+
+    let (output_stream, to_device_name) = match output {
+        OutputKind::Device { device, stream_cfg } => {
+            let output_buffer_size = negotiate_buffer_size(
+                device,
+                &stream_cfg,
+                DeviceType::Output,
+                from_device_config.buffer_size,
+                &route_config.to,
+            );
+            let stream_config = StreamConfig {
+                channels: device_channels,
+                sample_rate: output_rate,
+                buffer_size: output_buffer_size,
+            };
+
+            let stream = match output_format {
+                SampleFormat::F32 => {
+                    build_output_stream::<f32>(device, &stream_config, feed, output_monitor)?
+                }
+                SampleFormat::I16 => {
+                    build_output_stream::<i16>(device, &stream_config, feed, output_monitor)?
+                }
+                SampleFormat::I32 => {
+                    build_output_stream::<i32>(device, &stream_config, feed, output_monitor)?
+                }
+                SampleFormat::U16 => {
+                    build_output_stream::<u16>(device, &stream_config, feed, output_monitor)?
+                }
+                other => {
+                    return Err(RouterError::StreamConfig {
+                        device: route_config.to.clone(),
+                        reason: format!("unsupported output sample format {:?}", other),
+                        source: None,
+                    });
+                }
+            };
+            info!(
+                "  {} stream opened: {}",
+                route_config.to,
+                describe_stream_config(&stream_config, output_format)
+            );
+            (
+                RouteOutput::Device(stream),
+                device.name().unwrap_or_default(),
+            )
+        }
+        OutputKind::Udp(network) => {
+            let mut feed = feed;
+            let sink = UdpSink::new(
+                &route_config.to,
+                &network.address,
+                output_rate.0,
+                device_channels,
+                Box::new(move |block: &mut [f32]| feed.fill(block)),
+            )
+            .map_err(|e| RouterError::Network {
+                device: route_config.to.clone(),
+                source: e.into(),
+            })?;
+            info!("  Sending RTP/L16 to {}", network.address);
+            (RouteOutput::Udp(sink), to_device_config.identifier())
+        }
+        OutputKind::Bus(bus) => {
+            let mut feed = feed;
+            let link = bus.sink(Box::new(move |block: &mut [f32]| feed.fill(block)));
+            (RouteOutput::Bus(link), to_device_config.identifier())
+        }
+        OutputKind::Null => {
+            let mut feed = feed;
+            let sink = NullSink::new(
+                &route_config.to,
+                output_rate.0,
+                device_channels,
+                Box::new(move |block: &mut [f32]| feed.fill(block)),
+            );
+            info!("  Discarding output");
+            (RouteOutput::Null(sink), to_device_config.identifier())
+        }
+    };
+
+    Ok(AudioRoute {
+        name: route_name.to_string(),
+        from_device: route_config.from.clone(),
+        to_device: route_config.to.clone(),
+        input_stream,
+        output_stream,
+        from_device_name,
+        to_device_name,
+        gain: gain_control,
+        stats,
+        buffered,
+        needs_recovery,
+        enabled,
+        muted,
+        rate_mismatch,
+    })
+}
+
+/// Warns that a route allowed by `audio.allow_rate_mismatch` plays its input
+/// at the output's rate, and so at the wrong speed and pitch.
+fn warn_rate_mismatch(route_name: &str, from_rate: u32, to_rate: u32) {
+    let speed = (to_rate as f64 / from_rate as f64 - 1.0) * 100.0;
+    warn!(
+        "Route '{}' plays {} Hz audio at {} Hz ({:.1}% {})",
+        route_name,
+        from_rate,
+        to_rate,
+        speed.abs(),
+        if speed > 0.0 { "fast" } else { "slow" }
+    );
+}
+
+/// The input-side processing state of a route, shared by live routes and
+/// `render`.
+fn route_processing(
+    config: &Config,
+    route_config: &RouteConfig,
+    gain: f32,
+    channels: u16,
+    sample_rate: u32,
+) -> (AudioSettings, FilterChain) {
+    let audio_settings = AudioSettings {
+        gain,
+        polarity: if route_config.invert_phase { -1.0 } else { 1.0 },
+        mix_ratio: config.audio.downmix_ratio(),
+        channel_gains: route_config.channel_gains(),
+        stereo_width: route_config.stereo_width,
+        sample_min: config.audio.audio_sample_min,
+        sample_max: config.audio.audio_sample_max,
+        clamp: route_config.clamp,
+    };
+    let filters = FilterChain::new(
+        config.audio.dc_block,
+        route_config.highpass_hz,
+        &route_config.eq,
+        sample_rate as f32,
+        channels as usize,
+    )
+    .with_agc(route_config.agc.as_ref(), sample_rate as f32);
+
+    (audio_settings, filters)
+}
+
+/// Runs a WAV file through a route's processing offline: input channel
+/// selection, gain, AGC, filters, channel conversion, clamping and the
+/// output device's `output_gain`. Dither, the startup fade and `delay_ms`
+/// are left out so the result is deterministic and lines up with the input.
+/// Without `output_channels` on the route, the output has `channels`
+/// channels, or as many as the selected input. A route `target_rate`
+/// resamples the input the way a live route would.
+pub fn render_route(
+    config: &Config,
+    route_name: &str,
+    input: &Wav,
+    channels: Option<u16>,
+) -> Result<Wav> {
+    let route_config = config
+        .routing
+        .get(route_name)
+        .ok_or_else(|| RouterError::RouteNotFound(route_name.to_string()))?;
+    let from_device_config = config
+        .endpoint(&route_config.from)
+        .ok_or_else(|| RouterError::DeviceNotConfigured(route_config.from.clone()))?;
+    let to_device_config = config
+        .endpoint(&route_config.to)
+        .ok_or_else(|| RouterError::DeviceNotConfigured(route_config.to.clone()))?;
+    let (from_device_config, to_device_config) = (&*from_device_config, &*to_device_config);
+
+    let source_channels = input.channels as usize;
+    let selected: Vec<f32> = match &route_config.input_channels {
+        Some(channel_map) => {
+            if let Some(&invalid) = channel_map
+                .iter()
+                .find(|&&channel| channel >= source_channels)
+            {
+                return Err(RouterError::InvalidRoute {
+                    route: route_name.to_string(),
+                    reason: format!(
+                        "selects input channel {}, but the WAV only has {} channels",
+                        invalid, source_channels
+                    ),
+                });
+            }
+            input
+                .samples
+                .chunks_exact(source_channels)
+                .flat_map(|frame| channel_map.iter().map(|&channel| frame[channel]))
+                .collect()
+        }
+        None => input.samples.clone(),
+    };
+    let in_channels = route_config
+        .input_channels
+        .as_ref()
+        .map_or(input.channels, |channel_map| channel_map.len() as u16);
+    let out_channels = route_config
+        .output_channels
+        .as_ref()
+        .map(|channel_map| channel_map.len() as u16)
+        .or(channels)
+        .unwrap_or(in_channels);
+
+    if route_config.passthrough {
+        if in_channels != out_channels {
+            return Err(RouterError::InvalidRoute {
+                route: route_name.to_string(),
+                reason: format!(
+                    "is a passthrough route, but has {} input and {} output channels",
+                    in_channels, out_channels
+                ),
+            });
+        }
+        return Ok(Wav {
+            sample_rate: input.sample_rate,
+            channels: out_channels,
+            samples: selected,
+        });
+    }
+
+    let sample_rate = route_config.target_rate.unwrap_or(input.sample_rate);
+    let selected = if sample_rate != input.sample_rate {
+        let mut resampler = Resampler::new(
+            input.sample_rate,
+            sample_rate,
+            in_channels as usize,
+            config.audio.resampling.filter_taps(),
+        );
+        let mut resampled = Vec::new();
+        resampler.push(&selected, &mut resampled);
+        resampled
+    } else {
+        selected
+    };
+
+    let (audio_settings, mut filters) = route_processing(
+        config,
+        route_config,
+        from_device_config.gain,
+        in_channels.max(out_channels),
+        sample_rate,
+    );
+    let output_gain = to_device_config.output_gain;
+    let mut block = BlockStats::default();
+    let mut samples =
+        Vec::with_capacity(selected.len() / in_channels.max(1) as usize * out_channels as usize);
+
+    for frame in selected.chunks_exact(in_channels.max(1) as usize) {
+        let frame = process_frame(
+            frame,
+            in_channels,
+            out_channels,
+            &audio_settings,
+            &mut filters,
+            &mut block,
+        );
+        samples.extend(frame.iter().map(|&sample| {
+            if output_gain == NO_GAIN {
+                sample
+            } else if !route_config.clamp {
+                sample * output_gain
+            } else {
+                (sample * output_gain)
+                    .clamp(config.audio.audio_sample_min, config.audio.audio_sample_max)
+            }
+        }));
+    }
+
+    Ok(Wav {
+        sample_rate,
+        channels: out_channels,
+        samples,
+    })
+}
+
+pub fn run_self_test(config: &Config, route_name: Option<&str>) -> Result<()> {
+    let outputs: Vec<&String> = match route_name {
+        Some(name) => {
+            let route = config
+                .routing
+                .get(name)
+                .ok_or_else(|| RouterError::RouteNotFound(name.to_string()))?;
+            if config
+                .endpoint(&route.to)
+                .is_some_and(|device_config| !device_config.is_hardware())
+            {
+                return Err(RouterError::InvalidRoute {
+                    route: name.to_string(),
+                    reason: "does not end at an output device".to_string(),
+                });
+            }
+            vec![&route.to]
+        }
+        None => config
+            .devices
+            .iter()
+            .filter(|(_, device_config)| device_config.device_type == DeviceType::Output)
+            .map(|(alias, _)| alias)
+            .collect(),
+    };
+
+    if outputs.is_empty() {
+        return Err(RouterError::NoOutputDevices);
+    }
+
+    let hosts = AudioHosts::open(config)?;
+    let devices = AudioDevices::find_all(config, &hosts)?;
+
+    for alias in outputs {
+        let device_config = config
+            .devices
+            .get(alias)
+            .ok_or_else(|| RouterError::DeviceNotConfigured(alias.clone()))?;
+        let device = lookup_device(config, &devices, alias)?;
+        let output_cfg = select_stream_config(device, device_config, alias)?;
+
+        println!(
+            "Testing {} ({}): {} Hz tone, {} channels, {} Hz",
+            alias,
+            device_config.identifier(),
+            SELF_TEST_FREQUENCY_HZ,
+            output_cfg.channels(),
+            output_cfg.sample_rate().0
+        );
+
+        let channels = output_cfg.channels() as usize;
+        let phase_step =
+            2.0 * std::f32::consts::PI * SELF_TEST_FREQUENCY_HZ / output_cfg.sample_rate().0 as f32;
+        let mut phase = 0.0f32;
+        let device_name = alias.clone();
+
+        let stream_config = StreamConfig {
+            channels: output_cfg.channels(),
+            sample_rate: output_cfg.sample_rate(),
+            buffer_size: BufferSize::Fixed(device_config.buffer_size),
+        };
+        let stream = device
+            .build_output_stream(
+                &stream_config,
+                move |data: &mut [f32], _| {
+                    for frame in data.chunks_mut(channels) {
+                        let value = phase.sin() * SELF_TEST_AMPLITUDE;
+                        frame.fill(value);
+                        phase = (phase + phase_step) % (2.0 * std::f32::consts::PI);
+                    }
+                },
+                move |err| error!("Output error on '{}': {}", device_name, err),
+                None,
+            )
+            .map_err(|e| RouterError::StreamBuild {
+                details: stream_build_error(
+                    device,
+                    DeviceType::Output,
+                    alias,
+                    &stream_config,
+                    SampleFormat::F32,
+                ),
+                source: e.into(),
+            })?;
+
+        stream.play().map_err(|e| RouterError::StreamStart {
+            device: alias.clone(),
+            source: e.into(),
+        })?;
+        thread::sleep(SELF_TEST_DURATION);
+        drop(stream);
+    }
+
+    println!("Self-test complete");
+    Ok(())
+}
+
+/// Picks the stream config for a device: the host default, or the closest
+/// supported config at the configured `sample_rate` when one is requested.
+fn select_stream_config(
+    device: &Device,
+    device_config: &DeviceConfig,
+    alias: &str,
+) -> Result<SupportedStreamConfig> {
+    let is_output = match device_config.device_type {
+        DeviceType::Input => false,
+        DeviceType::Output => true,
+        DeviceType::Test | DeviceType::Udp | DeviceType::Null | DeviceType::Bus => {
+            return Err(RouterError::StreamConfig {
+                device: alias.to_string(),
+                reason: format!(
+                    "{} devices have no stream config",
+                    device_config.device_type
+                ),
+                source: None,
+            })
+        }
+    };
+
+    let default_cfg = if is_output {
+        device.default_output_config()
+    } else {
+        device.default_input_config()
+    }
+    .map_err(|e| RouterError::StreamConfig {
+        device: alias.to_string(),
+        reason: format!("failed to get default {} config", device_config.device_type),
+        source: Some(e.into()),
+    })?;
+
+    if device_config.exclusive {
+        // cpal's WASAPI backend always opens endpoints in shared mode and
+        // has no API for requesting exclusive access.
+        warn!(
+            "  {}: exclusive mode requested but not supported by the audio backend; using shared mode",
+            alias
+        );
+    }
+
+    let Some(rate) = device_config.sample_rate else {
+        return Ok(default_cfg);
+    };
+
+    if default_cfg.sample_rate().0 == rate {
+        return Ok(default_cfg);
+    }
+
+    let ranges: Vec<SupportedStreamConfigRange> = if is_output {
+        device.supported_output_configs().map(|r| r.collect())
+    } else {
+        device.supported_input_configs().map(|r| r.collect())
+    }
+    .map_err(|e| RouterError::StreamConfig {
+        device: alias.to_string(),
+        reason: format!(
+            "failed to query supported {} configs",
+            device_config.device_type
+        ),
+        source: Some(e.into()),
+    })?;
+
+    let candidates: Vec<&SupportedStreamConfigRange> = ranges
+        .iter()
+        .filter(|range| range.min_sample_rate().0 <= rate && range.max_sample_rate().0 >= rate)
+        .collect();
+
+    let best = candidates
+        .iter()
+        .find(|range| {
+            range.channels() == default_cfg.channels()
+                && range.sample_format() == default_cfg.sample_format()
+        })
+        .or_else(|| {
+            candidates
+                .iter()
+                .find(|range| range.channels() == default_cfg.channels())
+        })
+        .or_else(|| candidates.first())
+        .ok_or_else(|| RouterError::StreamConfig {
+            device: alias.to_string(),
+            reason: format!("sample rate of {} Hz is not supported", rate),
+            source: None,
+        })?;
+
+    Ok((**best).with_sample_rate(SampleRate(rate)))
+}
+
+/// Clamps the configured buffer size into the range the device reports for
+/// the chosen stream config, falling back to the host default when the
+/// device does not report a range.
+fn negotiate_buffer_size(
+    device: &Device,
+    stream_cfg: &SupportedStreamConfig,
+    direction: DeviceType,
+    requested: u32,
+    alias: &str,
+) -> BufferSize {
+    let matches_config = |range: &SupportedStreamConfigRange| {
+        range.channels() == stream_cfg.channels()
+            && range.sample_format() == stream_cfg.sample_format()
+            && range.min_sample_rate() <= stream_cfg.sample_rate()
+            && range.max_sample_rate() >= stream_cfg.sample_rate()
+    };
+
+    let supported = match direction {
+        DeviceType::Input => device
+            .supported_input_configs()
+            .ok()
+            .and_then(|mut ranges| ranges.find(matches_config)),
+        DeviceType::Output => device
+            .supported_output_configs()
+            .ok()
+            .and_then(|mut ranges| ranges.find(matches_config)),
+        DeviceType::Test | DeviceType::Udp | DeviceType::Null | DeviceType::Bus => None,
+    }
+    .map(|range| *range.buffer_size())
+    .unwrap_or(*stream_cfg.buffer_size());
+
+    match supported {
+        SupportedBufferSize::Range { min, max } => {
+            let clamped = requested.clamp(min, max);
+            if clamped != requested {
+                warn!(
+                    "  {}: buffer size {} outside supported range {}..={}, using {}",
+                    alias, requested, min, max, clamped
+                );
+            } else {
+                debug!("  {}: using buffer size {}", alias, clamped);
+            }
+            BufferSize::Fixed(clamped)
+        }
+        SupportedBufferSize::Unknown => {
+            warn!(
+                "  {}: device does not report supported buffer sizes, using host default",
+                alias
+            );
+            BufferSize::Default
+        }
+    }
+}
+
+/// The stream config a stream was actually built with, for the log.
+fn describe_stream_config(stream_config: &StreamConfig, format: SampleFormat) -> String {
+    let buffer = match stream_config.buffer_size {
+        BufferSize::Fixed(frames) => format!("{} frames", frames),
+        BufferSize::Default => "host default".to_string(),
+    };
+    format!(
+        "{} channels, {} Hz, format: {:?}, buffer: {}",
+        stream_config.channels, stream_config.sample_rate.0, format, buffer
+    )
+}
+
+/// Drains a route's ring buffer into output frames, applying the startup
+/// fade-in and the output device's gain, and filling underruns according to
+/// `audio.underrun_strategy`. With a `channel_map`, each frame of route samples is
+/// written to the mapped device channels and all other channels are silenced.
+struct OutputFeed {
+    consumer: HeapConsumer<f32>,
+    output_gain: f32,
+    sample_min: f32,
+    sample_max: f32,
+    clamp: bool,
+    dither: Option<TpdfDither>,
+    fade_in: FadeIn,
+    underrun_fill: UnderrunFill,
+    resampler: Option<Resampler>,
+    delay: Option<DelayLine>,
+    profile: bool,
+    channel_map: Option<Vec<usize>>,
+    device_channels: usize,
+    buffered: Arc<AtomicUsize>,
+    stats: Arc<RouteStats>,
+    rt_log: RtLog,
+    underrun_reported: bool,
+}
+
+impl OutputFeed {
+    fn fill<T>(&mut self, data: &mut [T])
+    where
+        T: SizedSample + FromSample<f32>,
+    {
+        let callback_started = self.profile.then(Instant::now);
+        self.stats
+            .record_output_frames(data.len() / self.device_channels.max(1));
+        let OutputFeed {
+            consumer,
+            output_gain,
+            sample_min,
+            sample_max,
+            clamp,
+            dither,
+            fade_in,
+            underrun_fill,
+            resampler,
+            delay,
+            channel_map,
+            device_channels,
+            ..
+        } = self;
+
+        let mut underrun = false;
+        let mut requested = 0u64;
+        let mut read_frame = |frame: &mut [f32]| {
+            requested += frame.len() as u64;
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                *sample = match consumer.pop() {
+                    Some(value) => {
+                        underrun_fill.played(channel, value);
+                        value
+                    }
+                    None => {
+                        underrun = true;
+                        underrun_fill.missing(channel)
+                    }
+                };
+            }
+        };
+
+        let mut clipped = 0;
+        let mut finish = |value: f32, fade: f32| {
+            let mut value = value * fade;
+            if *output_gain != NO_GAIN {
+                value *= *output_gain;
+                if *clamp && (value < *sample_min || value > *sample_max) {
+                    clipped += 1;
+                    value = value.clamp(*sample_min, *sample_max);
+                }
+            }
+            if let Some(dither) = dither.as_mut() {
+                value += dither.next_offset();
+            }
+            T::from_sample(value)
+        };
+
+        let route_channels = channel_map
+            .as_ref()
+            .map_or(*device_channels, Vec::len)
+            .min(MAX_FRAME_CHANNELS);
+        let mut route_frame = [0.0f32; MAX_FRAME_CHANNELS];
+        for frame in data.chunks_mut(*device_channels) {
+            let fade = fade_in.next_gain();
+            let route_frame = &mut route_frame[..route_channels];
+            match resampler {
+                Some(resampler) => resampler.pull(route_frame, &mut read_frame),
+                None => read_frame(route_frame),
+            }
+            if let Some(delay) = delay {
+                delay.process(route_frame);
+            }
+            match channel_map {
+                Some(channel_map) => {
+                    frame.fill(T::EQUILIBRIUM);
+                    for (&channel, &value) in channel_map.iter().zip(route_frame.iter()) {
+                        frame[channel] = finish(value, fade);
+                    }
+                }
+                None => {
+                    for (sample, &value) in frame.iter_mut().zip(route_frame.iter()) {
+                        *sample = finish(value, fade);
+                    }
+                }
+            }
+        }
+
+        self.stats.record_popped(requested);
+        if underrun {
+            self.stats.record_underrun();
+            if !self.underrun_reported {
+                self.underrun_reported = true;
+                self.rt_log.report(RtEvent::FirstUnderrun);
+            }
+        }
+        if clipped > 0 {
+            self.stats.record_clipped(clipped);
+        }
+        self.buffered.store(self.consumer.len(), Ordering::Relaxed);
+
+        if let Some(started) = callback_started {
+            self.stats.output_callbacks.record(started.elapsed());
+        }
+    }
+}
+
+fn build_output_stream<T>(
+    device: &Device,
+    stream_config: &StreamConfig,
+    mut feed: OutputFeed,
+    monitor: Arc<StreamMonitor>,
+) -> Result<Stream>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let error_monitor = monitor.clone();
+    let alias = monitor.device.clone();
+    let stream = device
+        .build_output_stream(
+            stream_config,
+            move |data: &mut [T], _| {
+                monitor.on_data();
+                feed.fill(data);
+            },
+            move |err| error_monitor.on_error(err),
+            None,
+        )
+        .map_err(|e| RouterError::StreamBuild {
+            details: stream_build_error(
+                device,
+                DeviceType::Output,
+                &alias,
+                stream_config,
+                T::FORMAT,
+            ),
+            source: e.into(),
+        })?;
+
+    Ok(stream)
+}
+
+/// Error context for a failed stream build, listing the configs the device
+/// does support so the config can be corrected.
+fn stream_build_error(
+    device: &Device,
+    direction: DeviceType,
+    alias: &str,
+    stream_config: &StreamConfig,
+    format: SampleFormat,
+) -> String {
+    let ranges: Vec<SupportedStreamConfigRange> = match direction {
+        DeviceType::Input => device
+            .supported_input_configs()
+            .map(|r| r.collect())
+            .unwrap_or_default(),
+        DeviceType::Output => device
+            .supported_output_configs()
+            .map(|r| r.collect())
+            .unwrap_or_default(),
+        DeviceType::Test | DeviceType::Udp | DeviceType::Null | DeviceType::Bus => Vec::new(),
+    };
+
+    let supported = if ranges.is_empty() {
+        "none reported".to_string()
+    } else {
+        ranges
+            .iter()
+            .map(|range| {
+                format!(
+                    "{} ch, {}-{} Hz, {:?}",
+                    range.channels(),
+                    range.min_sample_rate().0,
+                    range.max_sample_rate().0,
+                    range.sample_format()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    };
+
+    format!(
+        "Failed to open {} stream on '{}' ({} ch, {} Hz, {:?}); supported configs: {}",
+        direction, alias, stream_config.channels, stream_config.sample_rate.0, format, supported
+    )
+}
+
+/// Triangular-PDF dither spanning +/- one LSB of the target integer format.
+struct TpdfDither {
+    lsb: f32,
+    state: u32,
+}
+
+impl TpdfDither {
+    fn for_format(format: SampleFormat) -> Option<Self> {
+        // The integer formats `build_output_stream` opens.
+        let bits = match format {
+            SampleFormat::I16 | SampleFormat::U16 => 16,
+            SampleFormat::I32 => 32,
+            _ => return None,
+        };
+
+        Some(TpdfDither {
+            lsb: 2.0 / (1u64 << bits) as f32,
+            state: 0x9E37_79B9,
+        })
+    }
+
+    fn next_offset(&mut self) -> f32 {
+        (self.next_uniform() - self.next_uniform()) * self.lsb
+    }
+
+    fn next_uniform(&mut self) -> f32 {
+        // xorshift32: cheap and allocation-free for use in the audio callback.
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+fn validate_routing(config: &Config) -> Result<()> {
+    for (route_name, route) in &config.routing {
+        if config.endpoint(&route.from).is_none() {
+            return Err(RouterError::InvalidRoute {
+                route: route_name.clone(),
+                reason: format!("references unknown source device: '{}'", route.from),
+            });
+        }
+        if config.endpoint(&route.to).is_none() {
+            return Err(RouterError::InvalidRoute {
+                route: route_name.clone(),
+                reason: format!("references unknown destination device: '{}'", route.to),
+            });
+        }
+        for fallback in &route.to_fallback {
+            if !config
+                .endpoint(fallback)
+                .is_some_and(|device_config| device_config.is_sink())
+            {
+                return Err(RouterError::InvalidRoute {
+                    route: route_name.clone(),
+                    reason: format!(
+                        "fallback output '{}' is not a configured output, udp or null device or bus",
+                        fallback
+                    ),
+                });
+            }
+        }
+    }
+
+    let mut seen_routes = HashMap::new();
+    for (route_name, route) in &config.routing {
+        let key = format!("{}->{}", route.from, route.to);
+        if let Some(existing) = seen_routes.get(&key) {
+            warn!(
+                "Duplicate route detected: '{}' and '{}' both route {} -> {}",
+                existing, route_name, route.from, route.to
+            );
+        }
+        seen_routes.insert(key, route_name);
+    }
+
+    if let Some(cycle) = find_routing_cycle(config) {
+        let routes: Vec<String> = cycle
+            .iter()
+            .map(|route_name| {
+                let route = &config.routing[*route_name];
+                format!("'{}' ({} -> {})", route_name, route.from, route.to)
+            })
+            .collect();
+        return Err(RouterError::RoutingCycle(routes.join(", ")));
+    }
+
+    Ok(())
+}
+
+/// Treats routes as edges of a device graph and returns the names of the
+/// routes along the first cycle found. Cycles are only possible through
+/// devices that act as both source and destination, such as loopback
+/// outputs and udp devices.
+fn find_routing_cycle(config: &Config) -> Option<Vec<&String>> {
+    let mut edges: HashMap<&str, Vec<(&str, &String)>> = HashMap::new();
+    for (route_name, route) in &config.routing {
+        let targets = edges.entry(route.from.as_str()).or_default();
+        for output in route.outputs() {
+            targets.push((output.as_str(), route_name));
+        }
+    }
+    for targets in edges.values_mut() {
+        targets.sort_by(|a, b| a.1.cmp(b.1));
+    }
+
+    let mut starts: Vec<&str> = edges.keys().copied().collect();
+    starts.sort_unstable();
+
+    let mut finished = HashSet::new();
+    for start in starts {
+        let mut path: Vec<(&str, &String)> = Vec::new();
+        if let Some(cycle) = visit_device(start, &edges, &mut path, &mut finished) {
+            return Some(cycle);
+        }
+    }
+
+    None
+}
+
+fn visit_device<'a>(
+    device: &'a str,
+    edges: &HashMap<&'a str, Vec<(&'a str, &'a String)>>,
+    path: &mut Vec<(&'a str, &'a String)>,
+    finished: &mut HashSet<&'a str>,
+) -> Option<Vec<&'a String>> {
+    if finished.contains(device) {
+        return None;
+    }
+
+    for &(target, route_name) in edges.get(device).into_iter().flatten() {
+        path.push((device, route_name));
+
+        if let Some(start) = path.iter().position(|(from, _)| *from == target) {
+            return Some(path[start..].iter().map(|(_, route)| *route).collect());
+        }
+        if let Some(cycle) = visit_device(target, edges, path, finished) {
+            return Some(cycle);
+        }
+
+        path.pop();
+    }
+
+    finished.insert(device);
+    None
+}
+
+fn handle_input_data(
+    data: &[f32],
+    producer: &mut HeapProducer<f32>,
+    in_channels: u16,
+    out_channels: u16,
+    audio_settings: &AudioSettings,
+    filters: &mut FilterChain,
+    stats: &RouteStats,
+) -> u64 {
+    let mut block = BlockStats::default();
+
+    for input in data.chunks_exact(in_channels.max(1) as usize) {
+        let frame = process_frame(
+            input,
+            in_channels,
+            out_channels,
+            audio_settings,
+            filters,
+            &mut block,
+        );
+        block.pushed += frame.len() as u64;
+        if producer.free_len() >= frame.len() {
+            producer.push_slice(&frame);
+        } else {
+            block.dropped += 1;
+        }
+    }
+
+    stats.record_block(&block);
+    block.dropped
+}
+
+/// Input path of `passthrough` routes: whole frames are copied unchanged, and
+/// frames that do not fit in the ring buffer are dropped.
+fn copy_input_data(
+    data: &[f32],
+    producer: &mut HeapProducer<f32>,
+    channels: u16,
+    stats: &RouteStats,
+) -> u64 {
+    let mut block = BlockStats::default();
+
+    for frame in data.chunks_exact(channels.max(1) as usize) {
+        block.pushed += frame.len() as u64;
+        if producer.free_len() >= frame.len() {
+            producer.push_slice(frame);
+        } else {
+            block.dropped += 1;
+        }
+        for &sample in frame {
+            block.observe(sample);
+        }
+    }
+
+    stats.record_block(&block);
+    block.dropped
+}
+
+/// What the caller controls while routes run.
+struct RunControl {
+    running: Arc<AtomicBool>,
+    live_view: Option<LiveView>,
+}
+
+fn keep_alive(
+    control: RunControl,
+    routes: &mut Vec<AudioRoute>,
+    mut pending: Vec<String>,
+    config: &Config,
+    devices: &mut AudioDevices,
+    buses: &MixBuses,
+    hosts: &AudioHosts,
+) {
+    let sleep_duration = Duration::from_millis(config.audio.keep_alive_sleep_ms);
+    let hotplug_interval = Duration::from_secs(config.device_wait.retry_interval.max(1));
+    let mut last_hotplug_check = Instant::now();
+    let device_watch = DeviceWatch::start();
+    match &device_watch {
+        Some(_) => debug!("Watching for audio device changes"),
+        None => debug!(
+            "Device change notifications unavailable, checking for devices every {} s",
+            hotplug_interval.as_secs()
+        ),
+    }
+    let mut last_clip_check = Instant::now();
+    let mut last_clip_counts = vec![0u64; routes.len()];
+    let mut last_profile_report = Instant::now();
+    let mut last_silence_check = Instant::now();
+    let mut silence: HashMap<String, SilenceWatch> = HashMap::new();
+    let mut frames_reported: HashSet<String> = HashSet::new();
+    let warmup = Duration::from_millis(config.audio.warmup_ms);
+    let mut stabilizing = !warmup.is_zero();
+    let mut last_recovery_attempt: Option<Instant> = None;
+    let started = Instant::now();
+    let mut last_status_write: Option<Instant> = None;
+    let rate_mismatch_interval = Duration::from_secs(config.audio.rate_mismatch_warning_secs);
+    let mut last_rate_mismatch_warning = Instant::now();
+    let status_path = status::status_path()
+        .map_err(|e| warn!("Status file disabled: {:#}", e))
+        .ok();
+    let RunControl {
+        running,
+        mut live_view,
+    } = control;
+    let mut last_view_update: Option<Instant> = None;
+    // Peaks a live view took since the status file was last written.
+    let mut held_peaks: Vec<f32> = Vec::new();
+
+    while running.load(Ordering::SeqCst) {
+        thread::sleep(sleep_duration);
+
+        if let Some(view) = &live_view {
+            if apply_route_commands(view, routes) && config.persist_runtime_state {
+                save_runtime_state(routes, config);
+            }
+            if last_view_update.is_none_or(|at| at.elapsed() >= view.interval) {
+                let status = collect_status(routes, started, stabilizing);
+                held_peaks.resize(status.routes.len(), 0.0);
+                for (held, route) in held_peaks.iter_mut().zip(&status.routes) {
+                    *held = held.max(route.peak);
+                }
+                if view.status.send(status).is_err() {
+                    debug!("Live view closed");
+                    live_view = None;
+                }
+                last_view_update = Some(Instant::now());
+            }
+        }
+
+        if let Some(path) = &status_path {
+            if last_status_write.is_none_or(|at| at.elapsed() >= STATUS_WRITE_INTERVAL) {
+                let mut status = collect_status(routes, started, stabilizing);
+                for (route, held) in status.routes.iter_mut().zip(held_peaks.iter_mut()) {
+                    route.peak = route.peak.max(std::mem::take(held));
+                }
+                if let Err(e) = status::write_status(path, &status) {
+                    debug!("Failed to write status file: {:#}", e);
+                }
+                last_status_write = Some(Instant::now());
+            }
+        }
+
+        let devices_changed = device_watch.as_ref().is_some_and(DeviceWatch::take_change);
+        if devices_changed {
+            debug!("Audio devices changed");
+        }
+
+        if !pending.is_empty()
+            && (devices_changed || last_hotplug_check.elapsed() >= hotplug_interval)
+        {
+            activate_pending_routes(&mut pending, routes, config, devices, buses, hosts);
+            last_clip_counts.resize(routes.len(), 0);
+            last_hotplug_check = Instant::now();
+        }
+
+        apply_schedules(routes, config);
+        report_callback_frames(routes, &mut frames_reported);
+
+        // Startup underruns and clipping say nothing about steady state, so
+        // clipping and silence are only checked once warmup is over. Stream
+        // recovery and the other checks run from the start.
+        if stabilizing && started.elapsed() >= warmup {
+            for route in routes.iter() {
+                route.stats.reset();
+            }
+            last_clip_counts.fill(0);
+            last_clip_check = Instant::now();
+            last_silence_check = Instant::now();
+            stabilizing = false;
+            info!("Audio routing active with {} routes", routes.len());
+        }
+
+        if !stabilizing && last_clip_check.elapsed() >= CLIP_CHECK_INTERVAL {
+            let gain_changed = check_clipping(routes, &mut last_clip_counts, config);
+            if gain_changed && config.persist_runtime_state {
+                save_runtime_state(routes, config);
+            }
+            last_clip_check = Instant::now();
+        }
+
+        if !stabilizing && last_silence_check.elapsed() >= SILENCE_CHECK_INTERVAL {
+            check_silence(routes, &mut silence, config);
+            last_silence_check = Instant::now();
+        }
+
+        if !rate_mismatch_interval.is_zero()
+            && last_rate_mismatch_warning.elapsed() >= rate_mismatch_interval
+        {
+            for route in routes.iter() {
+                if let Some((from_rate, to_rate)) = route.rate_mismatch {
+                    warn_rate_mismatch(&route.name, from_rate, to_rate);
+                }
+            }
+            last_rate_mismatch_warning = Instant::now();
+        }
+
+        if config.audio.profile_callbacks
+            && last_profile_report.elapsed() >= CALLBACK_PROFILE_INTERVAL
+        {
+            report_callback_times(routes);
+            last_profile_report = Instant::now();
+        }
+
+        let recovery_due = devices_changed
+            || last_recovery_attempt.is_none_or(|at| at.elapsed() >= RECOVERY_RETRY_INTERVAL);
+        if recovery_due
+            && routes
+                .iter()
+                .any(|route| route.needs_recovery.load(Ordering::SeqCst))
+        {
+            last_recovery_attempt = Some(Instant::now());
+            for (index, route) in routes.iter_mut().enumerate() {
+                if route.needs_recovery.load(Ordering::SeqCst) {
+                    if let Err(e) = recover_route(config, devices, buses, index, route) {
+                        error!("Failed to recover route '{}': {:#}", route.name, e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Logs, once per route, how many frames its callbacks actually handle, which
+/// can differ from the buffer size requested when the streams were built.
+fn report_callback_frames(routes: &[AudioRoute], reported: &mut HashSet<String>) {
+    for route in routes {
+        if reported.contains(&route.name) {
+            continue;
+        }
+        if let Some((input, output)) = route.stats.callback_frames() {
+            info!(
+                "Route '{}' callbacks: {} input frames, {} output frames",
+                route.name, input, output
+            );
+            reported.insert(route.name.clone());
+        }
+    }
+}
+
+/// Hardware devices a route needs that have not been found yet. A route
+/// with fallback outputs only misses its outputs when none of them is
+/// present.
+fn missing_devices<'a>(
+    config: &'a Config,
+    devices: &AudioDevices,
+    route_config: &'a RouteConfig,
+) -> Vec<&'a String> {
+    let is_missing = |alias: &&String| {
+        config
+            .devices
+            .get(*alias)
+            .is_some_and(|device_config| device_config.is_hardware())
+            && !devices.contains(alias)
+    };
+
+    let mut missing: Vec<&String> = std::iter::once(&route_config.from)
+        .filter(is_missing)
+        .collect();
+    if route_config.outputs().all(|output| is_missing(&output)) {
+        missing.extend(route_config.outputs());
+    }
+    missing
+}
+
+/// Starts routes that were waiting for a device once all of their devices
+/// are present. Routes that fail to start stay pending and are retried.
+fn activate_pending_routes(
+    pending: &mut Vec<String>,
+    routes: &mut Vec<AudioRoute>,
+    config: &Config,
+    devices: &mut AudioDevices,
+    buses: &MixBuses,
+    hosts: &AudioHosts,
+) {
+    let mut wanted: Vec<&String> = pending
+        .iter()
+        .filter_map(|route_name| config.routing.get(route_name))
+        .flat_map(|route_config| missing_devices(config, devices, route_config))
+        .collect();
+    wanted.sort_unstable();
+    wanted.dedup();
+
+    if devices.find_new(config, hosts, &wanted).is_empty() {
+        return;
+    }
+
+    pending.retain(|route_name| {
+        let Some(route_config) = config.routing.get(route_name) else {
+            return false;
+        };
+        if !missing_devices(config, devices, route_config).is_empty() {
+            return true;
+        }
+
+        let route = setup_route_on_outputs(
+            config,
+            devices,
+            buses,
+            routes.len(),
+            route_name,
+            route_config,
+        )
+        .and_then(|route| start_route(&route).map(|()| route));
+        match route {
+            Ok(route) => {
+                if config.persist_runtime_state {
+                    restore_runtime_state(std::slice::from_ref(&route));
+                }
+                info!(
+                    "Route '{}' activated: {} → {}",
+                    route_name, route.from_device, route.to_device
+                );
+                routes.push(route);
+                false
+            }
+            Err(e) => {
+                warn!("Route '{}' could not be started yet: {:#}", route_name, e);
+                true
+            }
+        }
+    });
+}
+
+/// Applies the gain and mute changes a live view sent since the last call.
+/// Returns whether any route's gain or mute state changed.
+fn apply_route_commands(view: &LiveView, routes: &[AudioRoute]) -> bool {
+    let mut state_changed = false;
+
+    while let Ok(command) = view.commands.try_recv() {
+        let name = match &command {
+            RouteCommand::SetGain { route, .. } | RouteCommand::SetMuted { route, .. } => route,
+        };
+        let Some(route) = routes.iter().find(|route| &route.name == name) else {
+            debug!(
+                "Ignoring command for route '{}', which is not running",
+                name
+            );
+            continue;
+        };
+
+        match command {
+            RouteCommand::SetGain { gain, .. } => {
+                let gain = gain.max(0.0);
+                route.gain.set(gain);
+                state_changed = true;
+                info!("Route '{}' gain set to {}", route.name, gain);
+            }
+            RouteCommand::SetMuted { muted, .. } => {
+                route.muted.store(muted, Ordering::SeqCst);
+                state_changed = true;
+                info!(
+                    "Route '{}' {}",
+                    route.name,
+                    if muted { "muted" } else { "unmuted" }
+                );
+            }
+        }
+    }
+
+    state_changed
+}
+
+/// Enables or disables scheduled routes. Only the route's flag is touched;
+/// the input callback reads it and substitutes silence while disabled.
+fn apply_schedules(routes: &[AudioRoute], config: &Config) {
+    let now = chrono::Local::now().time();
+
+    for route in routes {
+        let Some(schedule) = config
+            .routing
+            .get(&route.name)
+            .and_then(|route_config| route_config.schedule.as_ref())
+        else {
+            continue;
+        };
+
+        let active = schedule.is_active(now);
+        if route.enabled.swap(active, Ordering::SeqCst) != active {
+            info!(
+                "Route '{}' {} by schedule",
+                route.name,
+                if active { "enabled" } else { "disabled" }
+            );
+        }
+    }
+}
+
+fn collect_status(routes: &[AudioRoute], started: Instant, stabilizing: bool) -> ServiceStatus {
+    ServiceStatus {
+        uptime: started.elapsed(),
+        stabilizing,
+        routes: routes
+            .iter()
+            .map(|route| RouteStatus {
+                name: route.name.clone(),
+                from: route.from_device.clone(),
+                to: route.to_device.clone(),
+                from_device: route.from_device_name.clone(),
+                to_device: route.to_device_name.clone(),
+                gain: route.gain.get(),
+                enabled: route.enabled.load(Ordering::Relaxed),
+                muted: route.muted.load(Ordering::Relaxed),
+                peak: route.stats.take_peak(),
+                clipped: route.stats.clipped(),
+                underruns: route.stats.underruns(),
+                overruns: route.stats.overruns(),
+                samples_pushed: route.stats.samples_pushed(),
+                samples_popped: route.stats.samples_popped(),
+                buffered: route.buffered.load(Ordering::Relaxed),
+            })
+            .collect(),
+    }
+}
+
+/// Rebuilds a route's streams after repeated stream errors. On failure the
+/// route stays flagged and is retried after `RECOVERY_RETRY_INTERVAL`.
+fn recover_route(
+    config: &Config,
+    devices: &AudioDevices,
+    buses: &MixBuses,
+    index: usize,
+    route: &mut AudioRoute,
+) -> Result<()> {
+    info!("Recovering route '{}'", route.name);
+
+    let route_config = config
+        .routing
+        .get(&route.name)
+        .ok_or_else(|| RouterError::RouteNotFound(route.name.to_string()))?;
+
+    route.input_stream.pause().ok();
+    route.output_stream.pause().ok();
+
+    let recovered =
+        setup_route_on_outputs(config, devices, buses, index, &route.name, route_config)?;
+    // Keep gain changes made by auto gain safety or a live view, mute, and
+    // the route's counts.
+    recovered.gain.set(route.gain.get());
+    recovered.stats.carry_over(&route.stats);
+    recovered
+        .muted
+        .store(route.muted.load(Ordering::SeqCst), Ordering::SeqCst);
+    start_route(&recovered)?;
+    let previous_output = std::mem::replace(route, recovered).to_device;
+
+    if route.to_device != previous_output {
+        warn!(
+            "Route '{}' recovered, switched output '{}' → '{}'",
+            route.name, previous_output, route.to_device
+        );
+    } else {
+        info!("Route '{}' recovered", route.name);
+    }
+    Ok(())
+}
+
+/// Warns about routes that clipped heavily since the last check and, with
+/// `audio.auto_gain_safety` enabled, steps their gain down. Returns whether
+/// any gain was changed.
+fn check_clipping(routes: &[AudioRoute], last_clip_counts: &mut [u64], config: &Config) -> bool {
+    let safety = &config.audio.auto_gain_safety;
+    let mut gain_changed = false;
+
+    for (route, last_count) in routes.iter().zip(last_clip_counts.iter_mut()) {
+        let total = route.stats.clipped();
+        let clipped = total.saturating_sub(*last_count);
+        *last_count = total;
+
+        if clipped < CLIP_WARN_THRESHOLD {
+            continue;
+        }
+
+        let gain = route.gain.get();
+        if safety.enabled && gain > safety.min_gain {
+            let reduced = (gain * 10f32.powf(-safety.step_db / 20.0)).max(safety.min_gain);
+            route.gain.set(reduced);
+            gain_changed = true;
+            warn!(
+                "Route '{}' clipping: {} samples clipped in the last {}s, reducing gain {} -> {}",
+                route.name,
+                clipped,
+                CLIP_CHECK_INTERVAL.as_secs(),
+                gain,
+                reduced
+            );
+        } else {
+            warn!(
+                "Route '{}' clipping: {} samples clipped in the last {}s, reduce gain",
+                route.name,
+                clipped,
+                CLIP_CHECK_INTERVAL.as_secs()
+            );
+        }
+    }
+
+    gain_changed
+}
+
+/// Tracks how long a route with `silence_alert_secs` has been silent.
+#[derive(Default)]
+struct SilenceWatch {
+    silent_since: Option<Instant>,
+    alerted: bool,
+}
+
+/// Warns once when a route's RMS level has stayed below its
+/// `silence_threshold_db` for `silence_alert_secs`, and logs when the signal
+/// returns. Routes that passed no audio (e.g. disabled by their schedule) are
+/// not evaluated.
+fn check_silence(
+    routes: &[AudioRoute],
+    silence: &mut HashMap<String, SilenceWatch>,
+    config: &Config,
+) {
+    for route in routes {
+        let Some(route_config) = config.routing.get(&route.name) else {
+            continue;
+        };
+        let Some(alert_secs) = route_config.silence_alert_secs else {
+            continue;
+        };
+        let Some(rms) = route.stats.take_rms() else {
+            continue;
+        };
+
+        let watch = silence.entry(route.name.clone()).or_default();
+        let threshold = 10f32.powf(route_config.silence_threshold_db / 20.0);
+        if rms >= threshold {
+            if let Some(since) = watch.silent_since.filter(|_| watch.alerted) {
+                info!(
+                    "Route '{}' has signal again after {}s of silence",
+                    route.name,
+                    since.elapsed().as_secs()
+                );
+            }
+            *watch = SilenceWatch::default();
+            continue;
+        }
+
+        let since = *watch.silent_since.get_or_insert_with(Instant::now);
+        if !watch.alerted && since.elapsed() >= Duration::from_secs(alert_secs) {
+            warn!(
+                "Route '{}' has been silent for {}s (level below {} dBFS)",
+                route.name, alert_secs, route_config.silence_threshold_db
+            );
+            watch.alerted = true;
+        }
+    }
+}
+
+/// Logs the average and maximum time each route spent in its input and
+/// output callbacks since the previous report.
+fn report_callback_times(routes: &[AudioRoute]) {
+    for route in routes {
+        let sides = [
+            ("input", route.stats.input_callbacks.take()),
+            ("output", route.stats.output_callbacks.take()),
+        ];
+        for (side, timing) in sides {
+            if let Some((average, max)) = timing {
+                info!(
+                    "Route '{}' {} callback: avg {}us, max {}us",
+                    route.name,
+                    side,
+                    average.as_micros(),
+                    max.as_micros()
+                );
+            }
+        }
+    }
+}
+
+/// Applies gains and mutes saved in `state.json` on top of the configured
+/// ones.
+fn restore_runtime_state(routes: &[AudioRoute]) {
+    let state = match RuntimeState::load() {
+        Ok(state) => state,
+        Err(e) => {
+            warn!("Ignoring saved runtime state: {:#}", e);
+            return;
+        }
+    };
+
+    for route in routes {
+        if let Some(saved) = state.routes.get(&route.name) {
+            info!(
+                "Restoring gain {} for route '{}' from saved runtime state",
+                saved.gain, route.name
+            );
+            route.gain.set(saved.gain);
+            if saved.muted {
+                info!("Route '{}' restored muted", route.name);
+            }
+            route.muted.store(saved.muted, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Saves the routes whose gain differs from its configured value or that
+/// are muted.
+fn save_runtime_state(routes: &[AudioRoute], config: &Config) {
+    let state = RuntimeState {
+        routes: routes
+            .iter()
+            .filter(|route| {
+                route.muted.load(Ordering::SeqCst)
+                    || config
+                        .endpoint(&route.from_device)
+                        .is_none_or(|device_config| device_config.gain != route.gain.get())
+            })
+            .map(|route| {
+                (
+                    route.name.clone(),
+                    RouteState {
+                        gain: route.gain.get(),
+                        muted: route.muted.load(Ordering::SeqCst),
+                    },
+                )
+            })
+            .collect(),
+    };
+
+    if let Err(e) = state.save() {
+        warn!("Failed to save runtime state: {:#}", e);
+    }
+}
+
+/// Logs a route's lifetime sample counts. Pushed minus popped minus what is
+/// still buffered is the net number of samples lost to overruns (positive)
+/// or filled in on underruns (negative).
+fn log_sample_totals(route: &AudioRoute) {
+    let pushed = route.stats.samples_pushed();
+    let popped = route.stats.samples_popped();
+    let buffered = route.buffered.load(Ordering::Relaxed) as u64;
+    let drift = pushed as i128 - popped as i128 - buffered as i128;
+    info!(
+        "Route '{}' totals: {} samples pushed, {} popped, {} buffered, drift {:+} ({} underruns, {} overruns)",
+        route.name,
+        pushed,
+        popped,
+        buffered,
+        drift,
+        route.stats.underruns(),
+        route.stats.overruns()
+    );
+}
+
+/// Stops routes without clicks: inputs are paused first so no new audio is
+/// produced, outputs keep running until their ring buffers drain (or the
+/// `audio.shutdown_drain_ms` passes), and only then are outputs paused and
+/// streams dropped.
+fn shutdown(routes: Vec<AudioRoute>, drain_timeout: Duration) {
+    info!("Stopping audio routing...");
+
+    for route in &routes {
+        if let Err(e) = route.input_stream.pause() {
+            warn!(
+                "Failed to pause input stream '{}': {}",
+                route.from_device, e
+            );
+        }
+    }
+
+    let drain_start = Instant::now();
+    loop {
+        if !routes
+            .iter()
+            .any(|route| route.buffered.load(Ordering::Relaxed) > 0)
+        {
+            info!(
+                "Ring buffers drained in {}ms",
+                drain_start.elapsed().as_millis()
+            );
+            break;
+        }
+        if drain_start.elapsed() >= drain_timeout {
+            warn!(
+                "Ring buffers did not drain within {}ms, stopping anyway",
+                drain_timeout.as_millis()
+            );
+            break;
+        }
+        thread::sleep(DRAIN_POLL_INTERVAL);
+    }
+
+    for route in &routes {
+        if let Err(e) = route.output_stream.pause() {
+            warn!("Failed to pause output stream '{}': {}", route.to_device, e);
+        }
+    }
+
+    for route in &routes {
+        log_sample_totals(route);
+    }
+
+    for route in routes {
+        let AudioRoute {
+            name,
+            input_stream,
+            output_stream,
+            ..
+        } = route;
+        drop(input_stream);
+        drop(output_stream);
+        debug!("Route '{}' closed", name);
+    }
+
+    info!("All streams stopped cleanly");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tpdf_dither_noise_floor_matches_one_lsb_triangle() {
+        let mut dither = TpdfDither::for_format(SampleFormat::I16).unwrap();
+        let lsb = 2.0 / 65536.0;
+        let count = 200_000;
+
+        let mut sum = 0.0f64;
+        let mut sum_squares = 0.0f64;
+        for _ in 0..count {
+            let offset = dither.next_offset();
+            assert!(offset.abs() <= lsb, "offset {} exceeds one LSB", offset);
+            sum += offset as f64;
+            sum_squares += (offset as f64).powi(2);
+        }
+
+        // Triangular noise spanning +/- one LSB has zero mean and an RMS of
+        // LSB / sqrt(6), about -98 dBFS for 16-bit output.
+        let mean = sum / count as f64;
+        let rms = (sum_squares / count as f64).sqrt();
+        let expected_rms = lsb as f64 / 6f64.sqrt();
+        assert!(mean.abs() < lsb as f64 * 0.01, "mean {}", mean);
+        assert!(
+            (rms / expected_rms - 1.0).abs() < 0.02,
+            "rms {} expected {}",
+            rms,
+            expected_rms
+        );
+    }
+
+    #[test]
+    fn tpdf_dither_only_applies_to_integer_outputs() {
+        assert!(TpdfDither::for_format(SampleFormat::F32).is_none());
+        assert!(TpdfDither::for_format(SampleFormat::I16).is_some());
+        assert!(TpdfDither::for_format(SampleFormat::U16).is_some());
+        assert!(TpdfDither::for_format(SampleFormat::I32).is_some());
+    }
+
+    /// A test tone routed to a null output, set up without starting it.
+    fn test_tone_route() -> AudioRoute {
+        let config = Config::from_yaml(
+            "\
+devices:
+  tone: { type: test, buffer_size: 256, primary_buffer: 4096, gain: 1.0 }
+  sink: { type: 'null', buffer_size: 256, primary_buffer: 4096, gain: 1.0 }
+routing:
+  r: { from: tone, to: sink }
+audio:
+  keep_alive_sleep_ms: 100
+  stereo_to_mono_mix_ratio: 0.5
+  audio_sample_min: -1.0
+  audio_sample_max: 1.0
+logging:
+  level: info
+device_wait: { enabled: false, max_wait_time: 1, retry_interval: 1, allow_partial: false }
+",
+        )
+        .unwrap();
+        let hosts = AudioHosts::open(&config).unwrap();
+        let devices = AudioDevices::find_all(&config, &hosts).unwrap();
+        setup_route(
+            &config,
+            &devices,
+            &MixBuses::new(),
+            0,
+            "r",
+            &config.routing["r"],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn live_view_mutes_and_gain_changes_are_state_changes() {
+        let routes = [test_tone_route()];
+        let (commands, receiver) = std::sync::mpsc::channel();
+        let (status, _) = std::sync::mpsc::channel();
+        let view = LiveView {
+            interval: Duration::from_secs(1),
+            status,
+            commands: receiver,
+        };
+        assert!(!apply_route_commands(&view, &routes));
+
+        let route = "r".to_string();
+        commands
+            .send(RouteCommand::SetMuted {
+                route: route.clone(),
+                muted: true,
+            })
+            .unwrap();
+        assert!(apply_route_commands(&view, &routes));
+        assert!(routes[0].muted.load(Ordering::SeqCst));
+
+        commands
+            .send(RouteCommand::SetGain { route, gain: 0.5 })
+            .unwrap();
+        assert!(apply_route_commands(&view, &routes));
+        assert_eq!(routes[0].gain.get(), 0.5);
+
+        commands
+            .send(RouteCommand::SetMuted {
+                route: "missing".to_string(),
+                muted: true,
+            })
+            .unwrap();
+        assert!(!apply_route_commands(&view, &routes));
     }
 }
@@ -0,0 +1,311 @@
+use anyhow::Result;
+use log::info;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{BusConfig, Config};
+use crate::dsp::{Compressor, FilterChain};
+use crate::sinks::OutputCallback;
+use crate::sources::InputCallback;
+
+/// Buses mix in 10 ms blocks.
+const BLOCKS_PER_SECOND: u32 = 100;
+
+pub type MixBuses = HashMap<String, Arc<MixBus>>;
+
+/// A named mix bus. Every route into the bus feeds it through its own ring
+/// buffer; a mixer thread pulls a block from each, sums them, applies the
+/// bus gain, filters and compressor once, and hands the mix to every route
+/// leaving the bus. The thread runs until the last handle to the bus is dropped.
+pub struct MixBus {
+    channels: usize,
+    sample_rate: u32,
+    next_id: AtomicU64,
+    inputs: Mutex<Vec<(u64, OutputCallback)>>,
+    outputs: Mutex<Vec<(u64, InputCallback)>>,
+}
+
+impl MixBus {
+    /// Creates a bus and starts its mixer.
+    pub fn start(name: &str, bus_config: &BusConfig, config: &Config) -> Result<Arc<Self>> {
+        let bus = Arc::new(MixBus {
+            channels: bus_config.channels as usize,
+            sample_rate: bus_config.sample_rate,
+            next_id: AtomicU64::new(0),
+            inputs: Mutex::new(Vec::new()),
+            outputs: Mutex::new(Vec::new()),
+        });
+
+        let mut mixer = BusMixer::new(
+            bus_config,
+            (config.audio.audio_sample_min, config.audio.audio_sample_max),
+        );
+        let frames = (bus_config.sample_rate / BLOCKS_PER_SECOND).max(1) as usize;
+        let weak: Weak<MixBus> = Arc::downgrade(&bus);
+
+        info!(
+            "Mix bus '{}': {} channels, {} Hz, gain {}",
+            name, bus_config.channels, bus_config.sample_rate, bus_config.gain
+        );
+        if let Some(compressor) = &bus_config.compressor {
+            info!(
+                "Mix bus '{}' compressor: threshold {} dB, ratio {}:1, makeup {} dB{}",
+                name,
+                compressor.threshold_db,
+                compressor.ratio,
+                compressor.makeup_db,
+                if compressor.bypass { " (bypassed)" } else { "" }
+            );
+        }
+
+        thread::Builder::new()
+            .name(format!("bus-{}", name))
+            .spawn(move || {
+                let block_duration = Duration::from_secs(1) / BLOCKS_PER_SECOND;
+                let Some(channels) = weak.upgrade().map(|bus| bus.channels) else {
+                    return;
+                };
+                let mut block = vec![0.0; frames * channels];
+                let mut mix = vec![0.0; frames * channels];
+                let mut next_block = Instant::now();
+
+                while let Some(bus) = weak.upgrade() {
+                    mix.fill(0.0);
+                    if let Ok(mut inputs) = bus.inputs.lock() {
+                        for (_, callback) in inputs.iter_mut() {
+                            callback(&mut block);
+                            for (sum, &sample) in mix.iter_mut().zip(&block) {
+                                *sum += sample;
+                            }
+                        }
+                    }
+
+                    mixer.process(&mut mix);
+
+                    if let Ok(mut outputs) = bus.outputs.lock() {
+                        for (_, callback) in outputs.iter_mut() {
+                            callback(&mix);
+                        }
+                    }
+                    drop(bus);
+
+                    next_block += block_duration;
+                    if let Some(wait) = next_block.checked_duration_since(Instant::now()) {
+                        thread::sleep(wait);
+                    }
+                }
+            })?;
+
+        Ok(bus)
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// A route leaving the bus; `callback` receives every mixed block while
+    /// the route plays.
+    pub fn source(self: &Arc<Self>, callback: InputCallback) -> BusInput {
+        BusLink::new(self, callback, |bus| &bus.outputs)
+    }
+
+    /// A route into the bus; `callback` fills one block per mix while the
+    /// route plays.
+    pub fn sink(self: &Arc<Self>, callback: OutputCallback) -> BusOutput {
+        BusLink::new(self, callback, |bus| &bus.inputs)
+    }
+}
+
+/// What a bus does to the sum of its inputs: gain, filters, compressor
+/// (unless bypassed) and clamping, frame by frame.
+struct BusMixer {
+    channels: usize,
+    gain: f32,
+    filters: FilterChain,
+    compressor: Option<Compressor>,
+    sample_min: f32,
+    sample_max: f32,
+}
+
+impl BusMixer {
+    fn new(bus_config: &BusConfig, (sample_min, sample_max): (f32, f32)) -> Self {
+        let channels = bus_config.channels as usize;
+        BusMixer {
+            channels,
+            gain: bus_config.gain,
+            filters: FilterChain::new(
+                false,
+                bus_config.highpass_hz,
+                &bus_config.eq,
+                bus_config.sample_rate as f32,
+                channels,
+            ),
+            compressor: bus_config
+                .compressor
+                .as_ref()
+                .filter(|compressor| !compressor.bypass)
+                .map(|compressor| Compressor::new(compressor, bus_config.sample_rate as f32)),
+            sample_min,
+            sample_max,
+        }
+    }
+
+    /// Processes a summed, interleaved block in place.
+    fn process(&mut self, mix: &mut [f32]) {
+        for frame in mix.chunks_exact_mut(self.channels) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                *sample = self.filters.process(channel, *sample * self.gain);
+            }
+            if let Some(compressor) = self.compressor.as_mut() {
+                compressor.process(frame);
+            }
+            for sample in frame {
+                *sample = sample.clamp(self.sample_min, self.sample_max);
+            }
+        }
+    }
+}
+
+pub type BusInput = BusLink<InputCallback>;
+pub type BusOutput = BusLink<OutputCallback>;
+
+type Links<C> = Mutex<Vec<(u64, C)>>;
+
+/// A route's connection to a bus. Playing moves the route's callback into
+/// the bus mixer; pausing (or dropping the route) takes it back out.
+pub struct BusLink<C> {
+    bus: Arc<MixBus>,
+    id: u64,
+    links: fn(&MixBus) -> &Links<C>,
+    callback: Mutex<Option<C>>,
+}
+
+impl<C> BusLink<C> {
+    fn new(bus: &Arc<MixBus>, callback: C, links: fn(&MixBus) -> &Links<C>) -> Self {
+        BusLink {
+            bus: bus.clone(),
+            id: bus.next_id.fetch_add(1, Ordering::Relaxed),
+            links,
+            callback: Mutex::new(Some(callback)),
+        }
+    }
+
+    pub fn start(&self) {
+        let Some(callback) = self.callback.lock().ok().and_then(|mut c| c.take()) else {
+            return;
+        };
+        if let Ok(mut links) = (self.links)(&self.bus).lock() {
+            links.push((self.id, callback));
+        }
+    }
+
+    pub fn stop(&self) {
+        let Ok(mut links) = (self.links)(&self.bus).lock() else {
+            return;
+        };
+        if let Some(position) = links.iter().position(|(id, _)| *id == self.id) {
+            let (_, callback) = links.remove(position);
+            if let Ok(mut slot) = self.callback.lock() {
+                *slot = Some(callback);
+            }
+        }
+    }
+}
+
+impl<C> Drop for BusLink<C> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    /// Two tones that each peak at 0.9 (-0.9 dBFS), summed in stereo the
+    /// way the mixer sums its inputs: the mix peaks near 1.8.
+    fn hot_mix() -> Vec<f32> {
+        (0..4800)
+            .flat_map(|n| {
+                let t = n as f32 / SAMPLE_RATE;
+                let sum = 0.9 * (2.0 * PI * 440.0 * t).sin() + 0.9 * (2.0 * PI * 660.0 * t).sin();
+                [sum, sum]
+            })
+            .collect()
+    }
+
+    fn bus_config(compressor: &str) -> BusConfig {
+        serde_yaml::from_str(&format!("compressor: {}", compressor)).unwrap()
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()))
+    }
+
+    #[test]
+    fn compressor_keeps_a_hot_mix_at_its_threshold() {
+        // Bounds well above the mix, so only the compressor limits it.
+        let mut mixer = BusMixer::new(
+            &bus_config("{ threshold_db: -6.0, ratio: 1000.0 }"),
+            (-4.0, 4.0),
+        );
+        let mut mix = hot_mix();
+        assert!(peak(&mix) > 1.7);
+
+        mixer.process(&mut mix);
+        // 1000:1 lets a thousandth of the 11 dB overshoot through.
+        let threshold = 10f32.powf(-6.0 / 20.0);
+        assert!(
+            peak(&mix) <= threshold * 1.002,
+            "peak {} over threshold {}",
+            peak(&mix),
+            threshold
+        );
+        assert!(peak(&mix) > threshold * 0.99, "peak {}", peak(&mix));
+    }
+
+    #[test]
+    fn compressor_ratio_limits_how_far_the_mix_exceeds_the_threshold() {
+        let mut mixer = BusMixer::new(
+            &bus_config("{ threshold_db: -6.0, ratio: 4.0 }"),
+            (-4.0, 4.0),
+        );
+        let mut mix = hot_mix();
+        let over_db = 20.0 * peak(&mix).log10() + 6.0;
+
+        mixer.process(&mut mix);
+        let expected_db = -6.0 + over_db / 4.0;
+        let peak_db = 20.0 * peak(&mix).log10();
+        assert!(
+            (peak_db - expected_db).abs() < 0.1,
+            "peak {} dB, expected {} dB",
+            peak_db,
+            expected_db
+        );
+    }
+
+    #[test]
+    fn bypassed_compressor_leaves_the_mix_to_the_clamp() {
+        let mut mixer = BusMixer::new(
+            &bus_config("{ threshold_db: -6.0, ratio: 1000.0, bypass: true }"),
+            (-1.0, 1.0),
+        );
+        let input = hot_mix();
+        let mut mix = input.clone();
+
+        mixer.process(&mut mix);
+        for (out, sum) in mix.iter().zip(&input) {
+            assert_eq!(*out, sum.clamp(-1.0, 1.0));
+        }
+    }
+}
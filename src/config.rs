@@ -1,28 +1,172 @@
-use anyhow::{Context, Result};
+use chrono::NaiveTime;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::env;
 use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
+use crate::error::{Result, RouterError};
+
+/// Profile requested on the command line; see `set_profile`.
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Config files given on the command line; see `set_config_files`.
+static CONFIG_FILES: OnceLock<Vec<PathBuf>> = OnceLock::new();
+
+/// Loads `paths` instead of `config.yaml`, each file merged over the ones
+/// before it. Must be called before `Config::load`.
+pub fn set_config_files(paths: Vec<PathBuf>) {
+    let _ = CONFIG_FILES.set(paths);
+}
+
+/// Selects a config profile: `config.<name>.yaml` when it exists, otherwise
+/// the `<name>` section of `profiles` in `config.yaml`. Must be called before
+/// `Config::load`.
+pub fn set_profile(name: String) {
+    let _ = PROFILE.set(name);
+}
+
+/// Devices and routes are kept sorted by name, so logging, route setup order
+/// and buffer index assignment are the same on every run.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
-    pub devices: HashMap<String, DeviceConfig>,
-    pub routing: HashMap<String, RouteConfig>,
+    pub devices: BTreeMap<String, DeviceConfig>,
+    #[serde(default)]
+    pub routing: BTreeMap<String, RouteConfig>,
+    #[serde(default)]
+    pub buses: BTreeMap<String, BusConfig>,
     pub audio: AudioConfig,
     pub logging: LoggingConfig,
     pub device_wait: DeviceWaitConfig,
+    #[serde(default)]
+    pub persist_runtime_state: bool,
+    #[serde(default)]
+    pub allow_empty_routing: bool,
+    /// Refuse to start while another instance is running. Off unless the
+    /// config turns it on, so embedders don't get a lock file by default.
+    #[serde(default)]
+    pub instance_lock: bool,
+    /// Name of the profile the config was resolved from, if any.
+    #[serde(skip)]
+    pub profile: Option<String>,
+    /// Keys in the file that no setting uses, e.g. `audio.gian`. They are
+    /// ignored, and logged once logging is set up.
+    #[serde(skip)]
+    pub unknown_fields: Vec<String>,
+    /// Devices and routes added for routes with `monitor: true`; they are
+    /// not in the file.
+    #[serde(skip)]
+    pub monitor_aliases: Vec<String>,
+    /// Files given with `--config`, in merge order; empty when the config
+    /// was loaded from `config.yaml`.
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DeviceConfig {
+    #[serde(default)]
     pub name: String,
-    #[serde(rename = "type")]
+    #[serde(default)]
+    pub index: Option<usize>,
+    /// WASAPI endpoint ID, stable across driver updates and renames.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// cpal host (e.g. `ASIO`, `WASAPI`, `JACK`) the device is looked up
+    /// through instead of the platform default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(rename = "type", deserialize_with = "deserialize_device_type")]
     pub device_type: DeviceType,
     pub buffer_size: u32,
     pub primary_buffer: usize,
     pub gain: f32,
+    /// `gain` in dB, as written in the file; `gain` holds its linear value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gain_db: Option<f32>,
+    #[serde(default = "default_output_gain")]
+    pub output_gain: f32,
+    #[serde(default)]
+    pub sample_rate: Option<u32>,
+    #[serde(default)]
+    pub exclusive: bool,
+    #[serde(default)]
+    pub loopback: bool,
+    #[serde(default)]
+    pub signal: Option<TestSignalConfig>,
+    #[serde(default)]
+    pub network: Option<NetworkConfig>,
+}
+
+/// Device `name` that selects the host's default input or output device.
+pub const DEFAULT_DEVICE_NAME: &str = "@default";
+
+/// Longest route `delay_ms` accepted.
+const MAX_DELAY_MS: u32 = 10_000;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TestSignalConfig {
+    #[serde(default)]
+    pub waveform: Waveform,
+    #[serde(default = "default_test_frequency")]
+    pub frequency: f32,
+    #[serde(default = "default_test_amplitude")]
+    pub amplitude: f32,
+    #[serde(default = "default_test_channels")]
+    pub channels: u16,
+}
+
+impl Default for TestSignalConfig {
+    fn default() -> Self {
+        TestSignalConfig {
+            waveform: Waveform::default(),
+            frequency: default_test_frequency(),
+            amplitude: default_test_amplitude(),
+            channels: default_test_channels(),
+        }
+    }
+}
+
+/// Endpoint of a `udp` device: the remote `host:port` packets are sent to
+/// when it is a route destination, or the local address to listen on when it
+/// is a route source.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NetworkConfig {
+    pub address: String,
+    #[serde(default = "default_network_channels")]
+    pub channels: u16,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Waveform {
+    #[default]
+    Sine,
+    Noise,
+}
+
+fn default_test_frequency() -> f32 {
+    440.0
+}
+
+fn default_test_amplitude() -> f32 {
+    0.25
+}
+
+fn default_test_channels() -> u16 {
+    2
+}
+
+fn default_network_channels() -> u16 {
+    2
+}
+
+fn default_output_gain() -> f32 {
+    1.0
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
@@ -30,6 +174,85 @@ pub struct DeviceConfig {
 pub enum DeviceType {
     Input,
     Output,
+    Test,
+    Udp,
+    /// Discards whatever is routed to it.
+    Null,
+    /// A mix bus from the `buses` section; never written in `devices`.
+    #[serde(skip)]
+    Bus,
+}
+
+/// An unquoted `type: null` is YAML's null rather than the string "null",
+/// so it is read as the null device too.
+fn deserialize_device_type<'de, D>(deserializer: D) -> std::result::Result<DeviceType, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<DeviceType>::deserialize(deserializer)?.unwrap_or(DeviceType::Null))
+}
+
+impl DeviceConfig {
+    /// Whether `name` is `@default`, which follows the host's default device
+    /// for the device type instead of matching a name.
+    pub fn is_default_device(&self) -> bool {
+        self.name == DEFAULT_DEVICE_NAME
+    }
+
+    /// Whether the device can be used as a route source: input, test and
+    /// udp devices, or output devices opened for loopback capture.
+    pub fn is_capture_source(&self) -> bool {
+        matches!(
+            self.device_type,
+            DeviceType::Input | DeviceType::Test | DeviceType::Udp | DeviceType::Bus
+        ) || self.loopback
+    }
+
+    /// Whether the device is backed by a host audio device, as opposed to a
+    /// synthetic or network endpoint.
+    pub fn is_hardware(&self) -> bool {
+        matches!(self.device_type, DeviceType::Input | DeviceType::Output)
+    }
+
+    /// Whether the device can be used as a route destination.
+    pub fn is_sink(&self) -> bool {
+        matches!(
+            self.device_type,
+            DeviceType::Output | DeviceType::Udp | DeviceType::Null | DeviceType::Bus
+        )
+    }
+
+    pub fn test_signal(&self) -> TestSignalConfig {
+        self.signal.clone().unwrap_or_default()
+    }
+
+    pub fn identifier(&self) -> String {
+        if self.device_type == DeviceType::Test {
+            let signal = self.test_signal();
+            return match signal.waveform {
+                Waveform::Sine => format!("test signal: {} Hz sine", signal.frequency),
+                Waveform::Noise => "test signal: white noise".to_string(),
+            };
+        }
+
+        if let (DeviceType::Udp, Some(network)) = (&self.device_type, &self.network) {
+            return format!("udp://{}", network.address);
+        }
+
+        if self.device_type == DeviceType::Bus {
+            return format!("mix bus '{}'", self.name);
+        }
+
+        if self.device_type == DeviceType::Null {
+            return "null sink".to_string();
+        }
+
+        match (self.index, &self.id) {
+            (Some(index), _) => format!("#{}", index),
+            (None, Some(id)) => format!("id {}", id),
+            (None, None) => self.name.clone(),
+        }
+    }
 }
 
 impl fmt::Display for DeviceType {
@@ -37,6 +260,10 @@ impl fmt::Display for DeviceType {
         match self {
             DeviceType::Input => write!(f, "input"),
             DeviceType::Output => write!(f, "output"),
+            DeviceType::Test => write!(f, "test"),
+            DeviceType::Udp => write!(f, "udp"),
+            DeviceType::Null => write!(f, "null"),
+            DeviceType::Bus => write!(f, "bus"),
         }
     }
 }
@@ -45,20 +272,485 @@ impl fmt::Display for DeviceType {
 pub struct RouteConfig {
     pub from: String,
     pub to: String,
+    #[serde(default = "default_channel_gain")]
+    pub left_gain: f32,
+    #[serde(default = "default_channel_gain")]
+    pub right_gain: f32,
+    #[serde(default)]
+    pub balance: f32,
+    #[serde(default = "default_stereo_width")]
+    pub stereo_width: f32,
+    #[serde(default)]
+    pub input_channels: Option<Vec<usize>>,
+    #[serde(default)]
+    pub output_channels: Option<Vec<usize>>,
+    #[serde(default)]
+    pub highpass_hz: Option<f32>,
+    #[serde(default)]
+    pub eq: Vec<EqBandConfig>,
+    #[serde(default)]
+    pub schedule: Option<ScheduleConfig>,
+    #[serde(default)]
+    pub agc: Option<AgcConfig>,
+    #[serde(default)]
+    pub passthrough: bool,
+    /// Multiplies the route's samples by -1, e.g. to fix a mic wired with
+    /// reversed polarity before it is mixed with another.
+    #[serde(default)]
+    pub invert_phase: bool,
+    /// Sample rate the route processes at; sides whose device runs at a
+    /// different rate are resampled.
+    #[serde(default)]
+    pub target_rate: Option<u32>,
+    /// Ring buffer size for this route, overriding the source device's
+    /// `primary_buffer`.
+    #[serde(default)]
+    pub buffer_samples: Option<usize>,
+    /// Delays the route's output by this many milliseconds, e.g. to line
+    /// audio up with video captured elsewhere.
+    #[serde(default)]
+    pub delay_ms: Option<u32>,
+    /// Warn when the route's level stays below `silence_threshold_db` for
+    /// this many seconds.
+    #[serde(default)]
+    pub silence_alert_secs: Option<u64>,
+    #[serde(default = "default_silence_threshold_db")]
+    pub silence_threshold_db: f32,
+    /// Also play the route on the system default output, at `monitor_gain`.
+    #[serde(default)]
+    pub monitor: bool,
+    #[serde(default = "default_monitor_gain")]
+    pub monitor_gain: f32,
+    /// Routes with a higher priority are set up first, so when devices or
+    /// resources run short it is the lower-priority routes that are skipped.
+    #[serde(default)]
+    pub priority: i32,
+    /// Outputs to use, in order, when `to` is missing or its stream fails.
+    #[serde(default)]
+    pub to_fallback: Vec<String>,
+    /// Rate to open the source device at, in place of its `sample_rate`.
+    /// Every route reading the device must agree on it.
+    #[serde(default)]
+    pub input_rate: Option<u32>,
+    /// Limit samples to `audio_sample_min`..`audio_sample_max`. Only float
+    /// outputs can be given unclamped samples.
+    #[serde(default = "default_clamp")]
+    pub clamp: bool,
+}
+
+fn default_monitor_gain() -> f32 {
+    1.0
+}
+
+fn default_silence_threshold_db() -> f32 {
+    -60.0
+}
+
+/// Automatic gain control for a route. Tracks the input's peak envelope and
+/// steers an internal gain, applied on top of the static `gain`, so the
+/// envelope sits at `target_level`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AgcConfig {
+    #[serde(default = "default_agc_target_level")]
+    pub target_level: f32,
+    #[serde(default = "default_agc_attack_ms")]
+    pub attack_ms: f32,
+    #[serde(default = "default_agc_release_ms")]
+    pub release_ms: f32,
+    #[serde(default = "default_agc_max_gain")]
+    pub max_gain: f32,
+}
+
+fn default_agc_target_level() -> f32 {
+    0.25
+}
+
+fn default_agc_attack_ms() -> f32 {
+    10.0
+}
+
+fn default_agc_release_ms() -> f32 {
+    500.0
+}
+
+fn default_agc_max_gain() -> f32 {
+    8.0
+}
+
+/// Daily window during which a route passes audio; outside it the route
+/// outputs silence. Times are local `HH:MM`, and the window may span midnight.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ScheduleConfig {
+    pub enable: String,
+    pub disable: String,
+}
+
+impl ScheduleConfig {
+    pub fn is_active(&self, now: NaiveTime) -> bool {
+        let (Ok(enable), Ok(disable)) = (
+            parse_time_of_day(&self.enable),
+            parse_time_of_day(&self.disable),
+        ) else {
+            return true;
+        };
+
+        if enable <= disable {
+            enable <= now && now < disable
+        } else {
+            now >= enable || now < disable
+        }
+    }
+}
+
+fn parse_time_of_day(value: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(value.trim(), "%H:%M").map_err(|_| {
+        RouterError::InvalidConfig(format!("Invalid time '{}', expected HH:MM", value))
+    })
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EqBandConfig {
+    #[serde(rename = "type")]
+    pub filter_type: FilterType,
+    pub freq: f32,
+    #[serde(default = "default_q")]
+    pub q: f32,
+    #[serde(default)]
+    pub gain_db: f32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterType {
+    Lowshelf,
+    Highshelf,
+    Peak,
+    Lowpass,
+    Highpass,
+}
+
+fn default_q() -> f32 {
+    std::f32::consts::FRAC_1_SQRT_2
+}
+
+fn default_channel_gain() -> f32 {
+    1.0
+}
+
+fn default_clamp() -> bool {
+    true
+}
+
+fn default_stereo_width() -> f32 {
+    1.0
+}
+
+impl RouteConfig {
+    /// Per-channel multipliers for stereo-preserving routes, combining the
+    /// explicit left/right gains with the balance control.
+    pub fn channel_gains(&self) -> [f32; 2] {
+        let balance = self.balance.clamp(-1.0, 1.0);
+        [
+            self.left_gain * (1.0 - balance).min(1.0),
+            self.right_gain * (1.0 + balance).min(1.0),
+        ]
+    }
+
+    /// The route's outputs in order of preference: `to`, then its fallbacks.
+    pub fn outputs(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.to).chain(&self.to_fallback)
+    }
+}
+
+/// A named mix bus. Routes with the bus as `to` are mixed into it; routes
+/// with the bus as `from` receive the mix after the bus gain and filters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BusConfig {
+    #[serde(default = "default_bus_channels")]
+    pub channels: u16,
+    #[serde(default = "default_bus_sample_rate")]
+    pub sample_rate: u32,
+    #[serde(default = "default_bus_gain")]
+    pub gain: f32,
+    /// Ring buffer size of the routes leaving the bus.
+    #[serde(default = "default_bus_buffer_samples")]
+    pub buffer_samples: usize,
+    #[serde(default)]
+    pub highpass_hz: Option<f32>,
+    #[serde(default)]
+    pub eq: Vec<EqBandConfig>,
+    #[serde(default)]
+    pub compressor: Option<CompressorConfig>,
+}
+
+/// Compressor on a bus's mix. Above `threshold_db` the level rises only
+/// 1/`ratio` dB per dB; a large ratio with a 0 ms attack makes it a peak
+/// limiter. `makeup_db` is applied after the gain reduction.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CompressorConfig {
+    #[serde(default = "default_compressor_threshold_db")]
+    pub threshold_db: f32,
+    #[serde(default = "default_compressor_ratio")]
+    pub ratio: f32,
+    #[serde(default)]
+    pub attack_ms: f32,
+    #[serde(default = "default_compressor_release_ms")]
+    pub release_ms: f32,
+    #[serde(default)]
+    pub makeup_db: f32,
+    #[serde(default)]
+    pub bypass: bool,
+}
+
+fn default_compressor_threshold_db() -> f32 {
+    -1.0
+}
+
+fn default_compressor_ratio() -> f32 {
+    20.0
+}
+
+fn default_compressor_release_ms() -> f32 {
+    100.0
+}
+
+impl BusConfig {
+    /// The bus as the device routes see: a source and destination with the
+    /// bus format and unity device gains.
+    pub fn as_device(&self, name: &str) -> DeviceConfig {
+        DeviceConfig {
+            name: name.to_string(),
+            index: None,
+            id: None,
+            host: None,
+            device_type: DeviceType::Bus,
+            buffer_size: 0,
+            primary_buffer: self.buffer_samples,
+            gain: 1.0,
+            gain_db: None,
+            output_gain: 1.0,
+            sample_rate: Some(self.sample_rate),
+            exclusive: false,
+            loopback: false,
+            signal: None,
+            network: None,
+        }
+    }
+}
+
+fn default_bus_channels() -> u16 {
+    2
+}
+
+fn default_bus_sample_rate() -> u32 {
+    48000
+}
+
+fn default_bus_gain() -> f32 {
+    1.0
+}
+
+fn default_bus_buffer_samples() -> usize {
+    9600
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AudioConfig {
+    #[serde(default)]
     pub prefill_samples: usize,
+    #[serde(default)]
+    pub prefill_ms: Option<u32>,
     pub keep_alive_sleep_ms: u64,
     pub stereo_to_mono_mix_ratio: f32,
+    #[serde(default)]
+    pub downmix_law: DownmixLaw,
     pub audio_sample_min: f32,
     pub audio_sample_max: f32,
+    #[serde(default)]
+    pub dither: DitherMode,
+    #[serde(default)]
+    pub dc_block: bool,
+    #[serde(default)]
+    pub underrun_strategy: UnderrunStrategy,
+    #[serde(default)]
+    pub profile_callbacks: bool,
+    #[serde(default = "default_shutdown_drain_ms")]
+    pub shutdown_drain_ms: u64,
+    #[serde(default)]
+    pub max_routes: Option<usize>,
+    #[serde(default = "default_stream_build_retries")]
+    pub stream_build_retries: u32,
+    #[serde(default = "default_stream_build_retry_ms")]
+    pub stream_build_retry_ms: u64,
+    #[serde(default = "default_startup_fade_ms")]
+    pub startup_fade_ms: u32,
+    /// How long after startup routes are left to settle before their
+    /// counters start and routing is reported active.
+    #[serde(default)]
+    pub warmup_ms: u64,
+    #[serde(default)]
+    pub auto_gain_safety: AutoGainSafetyConfig,
+    #[serde(default)]
+    pub resampling: ResamplingConfig,
+    /// Lets a route without a `target_rate` run between devices at different
+    /// sample rates, playing at the wrong speed, instead of failing to start.
+    #[serde(default)]
+    pub allow_rate_mismatch: bool,
+    /// How often such routes are warned about again; 0 warns only when they
+    /// start.
+    #[serde(default = "default_rate_mismatch_warning_secs")]
+    pub rate_mismatch_warning_secs: u64,
+}
+
+/// How routes with a `target_rate` convert between sample rates.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ResamplingConfig {
+    #[serde(default)]
+    pub quality: ResampleQuality,
+    /// Windowed-sinc filter length in taps, overriding `quality`.
+    #[serde(default)]
+    pub sinc_len: Option<usize>,
+}
+
+impl ResamplingConfig {
+    /// Taps of the windowed-sinc filter to use, or `None` for linear
+    /// interpolation.
+    pub fn filter_taps(&self) -> Option<usize> {
+        self.sinc_len.or(match self.quality {
+            ResampleQuality::Linear => None,
+            ResampleQuality::Low => Some(8),
+            ResampleQuality::Medium => Some(32),
+            ResampleQuality::High => Some(64),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ResampleQuality {
+    #[default]
+    Linear,
+    Low,
+    Medium,
+    High,
+}
+
+/// Longest `resampling.sinc_len` accepted.
+const MAX_SINC_LEN: usize = 256;
+
+/// Lowers a route's gain when it keeps clipping. Each check interval with
+/// sustained clipping reduces the gain by `step_db`, never below `min_gain`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AutoGainSafetyConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_gain_safety_step_db")]
+    pub step_db: f32,
+    #[serde(default = "default_gain_safety_min_gain")]
+    pub min_gain: f32,
+}
+
+impl Default for AutoGainSafetyConfig {
+    fn default() -> Self {
+        AutoGainSafetyConfig {
+            enabled: false,
+            step_db: default_gain_safety_step_db(),
+            min_gain: default_gain_safety_min_gain(),
+        }
+    }
+}
+
+fn default_rate_mismatch_warning_secs() -> u64 {
+    60
+}
+
+fn default_startup_fade_ms() -> u32 {
+    20
+}
+
+fn default_shutdown_drain_ms() -> u64 {
+    500
+}
+
+fn default_stream_build_retries() -> u32 {
+    3
+}
+
+fn default_stream_build_retry_ms() -> u64 {
+    250
+}
+
+fn default_gain_safety_step_db() -> f32 {
+    3.0
+}
+
+fn default_gain_safety_min_gain() -> f32 {
+    0.25
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DownmixLaw {
+    #[default]
+    Sum,
+    Average,
+    Rms,
+}
+
+impl AudioConfig {
+    /// Multiplier applied to `left + right` when mixing stereo to mono.
+    /// `sum` keeps the configured `stereo_to_mono_mix_ratio`; `rms` uses the
+    /// -3 dB pan law, which preserves perceived loudness of uncorrelated
+    /// channels.
+    pub fn downmix_ratio(&self) -> f32 {
+        match self.downmix_law {
+            DownmixLaw::Sum => self.stereo_to_mono_mix_ratio,
+            DownmixLaw::Average => 0.5,
+            DownmixLaw::Rms => std::f32::consts::FRAC_1_SQRT_2,
+        }
+    }
+}
+
+/// What an output plays when its route's buffer runs dry: silence, the last
+/// sample repeated for a few milliseconds before decaying, or the last sample
+/// decaying toward zero.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UnderrunStrategy {
+    #[default]
+    Silence,
+    Hold,
+    Fade,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DitherMode {
+    #[default]
+    None,
+    Tpdf,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LoggingConfig {
     pub level: String,
+    #[serde(default = "default_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+    /// Also report warnings and errors to the Windows Event Log. Ignored on
+    /// other platforms.
+    #[serde(default)]
+    pub event_log: bool,
+    /// Log file; relative paths are relative to the executable's directory.
+    #[serde(default = "default_log_file")]
+    pub file: PathBuf,
+}
+
+fn default_flush_interval_ms() -> u64 {
+    1000
+}
+
+fn default_log_file() -> PathBuf {
+    PathBuf::from("logs.txt")
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -67,41 +759,1297 @@ pub struct DeviceWaitConfig {
     pub max_wait_time: u64,
     pub retry_interval: u64,
     pub allow_partial: bool,
+    #[serde(default)]
+    pub strict: bool,
 }
 
 impl Config {
     pub fn load() -> Result<Self> {
-        let exe_path = env::current_exe().context("Failed to get executable path")?;
+        if let Some(paths) = CONFIG_FILES.get().filter(|paths| !paths.is_empty()) {
+            let documents = read_config_files(paths)?;
+            let mut config = Self::parse_merged(paths, &documents, None)?;
+            config.files = paths.clone();
+            return Ok(config);
+        }
 
-        let config_path = exe_path
-            .parent()
-            .context("Failed to get executable directory")?
-            .join("config.yaml");
+        let (config_path, file_profile) = Self::config_path()?;
 
         if !config_path.exists() {
-            return Err(anyhow::anyhow!(
-                "Config file not found at: {}. Please create a config.yaml file next to the executable.",
-                config_path.display()
-            ));
+            return Err(RouterError::ConfigNotFound(config_path));
         }
 
-        let config_str = fs::read_to_string(&config_path)
-            .with_context(|| format!("Failed to read config from: {}", config_path.display()))?;
+        let config_str = read_config_file(&config_path)?;
+
+        Self::parse(&config_str, file_profile)
+    }
+
+    /// Parses several config files as one: mappings are merged key by key,
+    /// so a later file only lists what it changes, and any other value in a
+    /// later file replaces the earlier one.
+    fn parse_merged(
+        paths: &[PathBuf],
+        documents: &[String],
+        file_profile: Option<String>,
+    ) -> Result<Self> {
+        let mut merged = serde_yaml::Value::Null;
+        for (path, document) in paths.iter().zip(documents) {
+            let document: serde_yaml::Value =
+                serde_yaml::from_str(document).map_err(|source| RouterError::ConfigParse {
+                    path: Some(path.clone()),
+                    source,
+                })?;
+            merge_yaml(&mut merged, document);
+        }
+        Self::parse_document(merged, file_profile)
+    }
+
+    /// Parses and validates a config from YAML text, for embedding the
+    /// router without a `config.yaml` next to the executable.
+    pub fn from_yaml(config_str: &str) -> Result<Self> {
+        Self::parse(config_str, None)
+    }
+
+    fn parse(config_str: &str, file_profile: Option<String>) -> Result<Self> {
+        let document: serde_yaml::Value = serde_yaml::from_str(config_str).map_err(parse_error)?;
+        Self::parse_document(document, file_profile)
+    }
+
+    fn parse_document(
+        mut document: serde_yaml::Value,
+        file_profile: Option<String>,
+    ) -> Result<Self> {
+        interpolate_env(&mut document)?;
+        let requested = match file_profile {
+            Some(_) => None,
+            None => PROFILE.get().map(String::as_str),
+        };
+        let embedded_profile = apply_embedded_profile(&mut document, requested)?;
+        apply_device_defaults(&mut document)?;
+        resolve_gain_db(&mut document)?;
+
+        let mut config: Config = serde_yaml::from_value(document.clone()).map_err(parse_error)?;
+        config.profile = embedded_profile.or(file_profile);
+
+        // Every recognised field is written back when the parsed config is
+        // serialized, so keys missing from that round trip were ignored.
+        let known = serde_yaml::to_value(&config).map_err(parse_error)?;
+        collect_unknown_fields(&document, &known, "", &mut config.unknown_fields);
 
-        let config: Config =
-            serde_yaml::from_str(&config_str).context("Failed to parse config YAML")?;
+        config.add_monitor_routes()?;
+        config.validate()?;
 
         Ok(config)
     }
 
+    /// The config file to load and, when it is a profile file, the profile
+    /// name it was picked for.
+    pub fn config_path() -> Result<(PathBuf, Option<String>)> {
+        let dir = Self::get_config_dir()?;
+
+        if let Some(profile) = PROFILE.get() {
+            let path = dir.join(format!("config.{}.yaml", profile));
+            if path.exists() {
+                return Ok((path, Some(profile.clone())));
+            }
+        }
+
+        Ok((dir.join("config.yaml"), None))
+    }
+
+    /// Turns each route's `monitor` option into a route of its own: a copy
+    /// of the route that plays to the default output device, whose
+    /// `output_gain` is the route's `monitor_gain`. Settings that belong to
+    /// the route's destination (`output_channels`, `delay_ms`, silence
+    /// alerts) are not copied.
+    fn add_monitor_routes(&mut self) -> Result<()> {
+        let monitored: Vec<(String, RouteConfig)> = self
+            .routing
+            .iter()
+            .filter(|(_, route_config)| route_config.monitor)
+            .map(|(route_name, route_config)| (route_name.clone(), route_config.clone()))
+            .collect();
+
+        for (route_name, route_config) in monitored {
+            let alias = format!("{}.monitor", route_name);
+            if self.devices.contains_key(&alias) || self.routing.contains_key(&alias) {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Route '{}' monitors through '{}', which is already configured",
+                    route_name, alias
+                )));
+            }
+            // Validation reports the unknown device.
+            let Some(source) = self.endpoint(&route_config.from) else {
+                continue;
+            };
+
+            let device = DeviceConfig {
+                name: DEFAULT_DEVICE_NAME.to_string(),
+                index: None,
+                id: None,
+                host: None,
+                device_type: DeviceType::Output,
+                buffer_size: source.buffer_size,
+                primary_buffer: source.primary_buffer,
+                gain: 1.0,
+                gain_db: None,
+                output_gain: route_config.monitor_gain,
+                sample_rate: None,
+                exclusive: false,
+                loopback: false,
+                signal: None,
+                network: None,
+            };
+            let route = RouteConfig {
+                to: alias.clone(),
+                to_fallback: Vec::new(),
+                output_channels: None,
+                delay_ms: None,
+                silence_alert_secs: None,
+                monitor: false,
+                ..route_config
+            };
+            self.devices.insert(alias.clone(), device);
+            self.routing.insert(alias.clone(), route);
+            self.monitor_aliases.push(alias);
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (route_name, route_config) in &self.routing {
+            if route_config.stereo_width < 0.0 {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Route '{}' has a negative stereo_width",
+                    route_name
+                )));
+            }
+            if route_config.highpass_hz.is_some_and(|hz| hz <= 0.0) {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Route '{}' has a non-positive highpass_hz",
+                    route_name
+                )));
+            }
+            if let Some(output) = route_config
+                .outputs()
+                .enumerate()
+                .find(|(i, output)| route_config.outputs().take(*i).any(|o| o == *output))
+                .map(|(_, output)| output)
+            {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Route '{}' lists output '{}' more than once in to/to_fallback",
+                    route_name, output
+                )));
+            }
+            if route_config.silence_alert_secs == Some(0) {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Route '{}' has a silence_alert_secs of 0",
+                    route_name
+                )));
+            }
+            if route_config.buffer_samples == Some(0) {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Route '{}' has a buffer_samples of 0",
+                    route_name
+                )));
+            }
+            if route_config.delay_ms.is_some_and(|ms| ms > MAX_DELAY_MS) {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Route '{}' has a delay_ms above {}",
+                    route_name, MAX_DELAY_MS
+                )));
+            }
+            if route_config.passthrough && route_config.invert_phase {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Route '{}' cannot invert_phase as a passthrough route",
+                    route_name
+                )));
+            }
+            if route_config.target_rate == Some(0) {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Route '{}' has a target_rate of 0",
+                    route_name
+                )));
+            }
+            if let Some(rate) = route_config.input_rate {
+                if rate == 0 {
+                    return Err(RouterError::InvalidConfig(format!(
+                        "Route '{}' has an input_rate of 0",
+                        route_name
+                    )));
+                }
+                if !self
+                    .devices
+                    .get(&route_config.from)
+                    .is_some_and(|device_config| device_config.is_hardware())
+                {
+                    return Err(RouterError::InvalidConfig(format!(
+                        "Route '{}' sets input_rate, but its source '{}' is not an input device or loopback output; set the source's sample_rate instead",
+                        route_name,
+                        route_config.from
+                    )));
+                }
+            }
+            if let Some(schedule) = &route_config.schedule {
+                for time in [&schedule.enable, &schedule.disable] {
+                    parse_time_of_day(time).map_err(|e| {
+                        RouterError::InvalidConfig(format!(
+                            "Route '{}' has an invalid schedule: {}",
+                            route_name, e
+                        ))
+                    })?;
+                }
+            }
+            if let Some(agc) = &route_config.agc {
+                if agc.target_level <= 0.0 || agc.target_level > 1.0 {
+                    return Err(RouterError::InvalidConfig(format!(
+                        "Route '{}' has an agc.target_level outside (0, 1]",
+                        route_name
+                    )));
+                }
+                if agc.max_gain <= 0.0 || agc.attack_ms < 0.0 || agc.release_ms < 0.0 {
+                    return Err(RouterError::InvalidConfig(format!(
+                        "Route '{}' needs a positive agc.max_gain and non-negative attack/release times",
+                        route_name
+                    )));
+                }
+            }
+            for band in &route_config.eq {
+                if band.freq <= 0.0 || band.q <= 0.0 {
+                    return Err(RouterError::InvalidConfig(format!(
+                        "Route '{}' has an EQ band with non-positive freq or q",
+                        route_name
+                    )));
+                }
+            }
+        }
+
+        // Each route opens its own stream on its source, and a device runs
+        // at one rate at a time.
+        let mut source_rates: BTreeMap<&String, (&String, u32)> = BTreeMap::new();
+        for (route_name, route_config) in &self.routing {
+            let Some(rate) = route_config.input_rate.or_else(|| {
+                self.devices
+                    .get(&route_config.from)
+                    .and_then(|device_config| device_config.sample_rate)
+            }) else {
+                continue;
+            };
+            match source_rates.get(&route_config.from) {
+                Some(&(other_route, other_rate)) if other_rate != rate => {
+                    return Err(RouterError::InvalidConfig(format!(
+                        "Routes '{}' and '{}' both read '{}' but need it at different rates ({} and {} Hz); give them the same input_rate",
+                        other_route,
+                        route_name,
+                        route_config.from,
+                        other_rate,
+                        rate
+                    )));
+                }
+                Some(_) => {}
+                None => {
+                    source_rates.insert(&route_config.from, (route_name, rate));
+                }
+            }
+        }
+
+        if let Some(taps) = self.audio.resampling.sinc_len {
+            if !(4..=MAX_SINC_LEN).contains(&taps) || taps % 2 != 0 {
+                return Err(RouterError::InvalidConfig(format!(
+                    "audio.resampling.sinc_len must be an even number from 4 to {}",
+                    MAX_SINC_LEN
+                )));
+            }
+        }
+
+        let safety = &self.audio.auto_gain_safety;
+        if safety.enabled && (safety.step_db <= 0.0 || safety.min_gain <= 0.0) {
+            return Err(RouterError::InvalidConfig(
+                "audio.auto_gain_safety needs a positive step_db and min_gain".to_string(),
+            ));
+        }
+
+        for (name, bus) in &self.buses {
+            if self.devices.contains_key(name) {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Bus '{}' has the same name as a device",
+                    name
+                )));
+            }
+            if bus.channels == 0 || bus.sample_rate == 0 || bus.buffer_samples == 0 {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Bus '{}' needs positive channels, sample_rate and buffer_samples",
+                    name
+                )));
+            }
+            if bus.highpass_hz.is_some_and(|hz| hz <= 0.0)
+                || bus.eq.iter().any(|band| band.freq <= 0.0 || band.q <= 0.0)
+            {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Bus '{}' has a non-positive highpass_hz or EQ freq or q",
+                    name
+                )));
+            }
+            if let Some(compressor) = &bus.compressor {
+                if compressor.ratio < 1.0
+                    || compressor.threshold_db > 0.0
+                    || compressor.attack_ms < 0.0
+                    || compressor.release_ms < 0.0
+                {
+                    return Err(RouterError::InvalidConfig(format!(
+                        "Bus '{}' needs a compressor ratio of at least 1, a threshold_db of at most 0 and non-negative attack/release times",
+                        name
+                    )));
+                }
+            }
+        }
+
+        for (alias, device_config) in &self.devices {
+            if device_config.host.is_some() && !device_config.is_hardware() {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Device '{}' sets a host, but is not an input or output device",
+                    alias
+                )));
+            }
+
+            if device_config.loopback && device_config.device_type != DeviceType::Output {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Device '{}' has loopback enabled but is not an output device",
+                    alias
+                )));
+            }
+
+            if device_config.device_type == DeviceType::Test
+                && device_config.test_signal().channels == 0
+            {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Test device '{}' must have at least one channel",
+                    alias
+                )));
+            }
+
+            if device_config.device_type == DeviceType::Udp {
+                match &device_config.network {
+                    None => {
+                        return Err(RouterError::InvalidConfig(format!(
+                            "UDP device '{}' must have a network.address",
+                            alias
+                        )));
+                    }
+                    Some(network) if network.channels == 0 => {
+                        return Err(RouterError::InvalidConfig(format!(
+                            "UDP device '{}' must have at least one channel",
+                            alias
+                        )));
+                    }
+                    Some(_) => {}
+                }
+            }
+
+            match device_config.index {
+                Some(0) => {
+                    return Err(RouterError::InvalidConfig(format!(
+                        "Device '{}' has index 0; indices start at 1 (see list-devices)",
+                        alias
+                    )));
+                }
+                Some(_) if device_config.id.is_some() => {
+                    return Err(RouterError::InvalidConfig(format!(
+                        "Device '{}' sets both index and id; use one of them",
+                        alias
+                    )));
+                }
+                None if device_config.name.is_empty()
+                    && device_config.id.is_none()
+                    && device_config.is_hardware() =>
+                {
+                    return Err(RouterError::InvalidConfig(format!(
+                        "Device '{}' must specify a name, an id or an index",
+                        alias
+                    )));
+                }
+                _ => {}
+            }
+
+            if device_config.id.is_some() && !cfg!(windows) {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Device '{}' is selected by id, which needs WASAPI on Windows",
+                    alias
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Persists runtime-tunable settings back to `config.yaml`, or with
+    /// `--config` to the last file that lists each device. The existing
+    /// documents are edited line by line rather than re-serialized, so
+    /// comments and formatting are kept. Returns the files written.
+    pub fn save(&self) -> Result<Vec<PathBuf>> {
+        let (paths, file_profile) = match CONFIG_FILES.get().filter(|paths| !paths.is_empty()) {
+            Some(paths) => (paths.clone(), None),
+            None => {
+                let (config_path, file_profile) = Self::config_path()?;
+                (vec![config_path], file_profile)
+            }
+        };
+
+        let mut documents = read_config_files(&paths)?;
+        let changed = self.write_gains(&paths, &mut documents, file_profile)?;
+
+        let mut written = Vec::new();
+        for ((path, document), changed) in paths.into_iter().zip(&documents).zip(changed) {
+            if changed {
+                fs::write(&path, document).map_err(|source| RouterError::Io {
+                    action: format!("Failed to write config to: {}", path.display()),
+                    source,
+                })?;
+                written.push(path);
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Writes the gains that differ from `documents` into them, and returns
+    /// which documents were edited. The edited documents are parsed again
+    /// and every written gain checked, so an edit that would leave the
+    /// config unloadable or with a different gain fails before anything is
+    /// saved.
+    fn write_gains(
+        &self,
+        paths: &[PathBuf],
+        documents: &mut [String],
+        file_profile: Option<String>,
+    ) -> Result<Vec<bool>> {
+        // Only gains that changed are written, so devices that take their
+        // gain from `device_defaults` keep doing so.
+        let on_disk = Self::parse_merged(paths, documents, file_profile.clone()).ok();
+        let mut changed = vec![false; documents.len()];
+        let mut written = Vec::new();
+
+        for (alias, device_config) in &self.devices {
+            let unchanged = on_disk
+                .as_ref()
+                .and_then(|on_disk| on_disk.devices.get(alias))
+                .is_some_and(|saved| saved.gain == device_config.gain);
+            if unchanged || self.monitor_aliases.contains(alias) {
+                continue;
+            }
+            // The gain goes to the last file that lists the device, which is
+            // the one its settings come from.
+            let target = documents
+                .iter()
+                .rposition(|document| lists_device(document, alias))
+                .unwrap_or(documents.len() - 1);
+            let document = &mut documents[target];
+            changed[target] = true;
+            *document = match device_config.gain_db {
+                // A gain of 0 is -inf dB; a negative gain has no dB value.
+                Some(_) if device_config.gain < 0.0 || device_config.gain.is_nan() => {
+                    return Err(RouterError::InvalidConfig(format!(
+                        "Device '{}' sets gain_db, which can't express a gain of {}",
+                        alias, device_config.gain
+                    )))
+                }
+                Some(_) => set_yaml_scalar(
+                    document,
+                    &["devices", alias, "gain_db"],
+                    &yaml_float(gain_to_db(device_config.gain)),
+                )?,
+                None => set_yaml_scalar(
+                    document,
+                    &["devices", alias, "gain"],
+                    &format!("{:?}", device_config.gain),
+                )?,
+            };
+            written.push((alias, device_config.gain));
+        }
+
+        if written.is_empty() {
+            return Ok(changed);
+        }
+        let saved = Self::parse_merged(paths, documents, file_profile)?;
+        for (alias, gain) in written {
+            let saved_gain = saved.devices.get(alias).map(|device| device.gain);
+            if !saved_gain.is_some_and(|saved| (saved - gain).abs() <= gain.abs() * 1e-5) {
+                return Err(RouterError::InvalidConfig(format!(
+                    "Saving the gain of device '{}' would not keep it at {} (read back: {:?})",
+                    alias, gain, saved_gain
+                )));
+            }
+        }
+
+        Ok(changed)
+    }
+
+    /// The device or mix bus a route endpoint names.
+    pub fn endpoint(&self, alias: &str) -> Option<Cow<'_, DeviceConfig>> {
+        match self.devices.get(alias) {
+            Some(device_config) => Some(Cow::Borrowed(device_config)),
+            None => self
+                .buses
+                .get(alias)
+                .map(|bus| Cow::Owned(bus.as_device(alias))),
+        }
+    }
+
+    /// Whether every route using `alias` uses it as one of several outputs,
+    /// so the routes can still run while the device is missing.
+    pub fn is_replaceable(&self, alias: &str) -> bool {
+        let mut used = false;
+        for route in self.routing.values() {
+            if route.from == alias {
+                return false;
+            }
+            if route.outputs().any(|output| output == alias) {
+                if route.to_fallback.is_empty() {
+                    return false;
+                }
+                used = true;
+            }
+        }
+        used
+    }
+
+    /// The rate routes open `alias` at as their source, when one sets
+    /// `input_rate`.
+    pub fn input_rate(&self, alias: &str) -> Option<u32> {
+        self.routing
+            .values()
+            .filter(|route| route.from == alias)
+            .find_map(|route| route.input_rate)
+    }
+
+    /// The log file from `logging.file`, resolved against the executable's
+    /// directory.
+    pub fn log_path(&self) -> Result<PathBuf> {
+        Ok(Self::get_config_dir()?.join(&self.logging.file))
+    }
+
     pub fn get_config_dir() -> Result<PathBuf> {
-        let exe_path = env::current_exe().context("Failed to get executable path")?;
+        let exe_path = env::current_exe().map_err(|source| RouterError::Io {
+            action: "Failed to get executable path".to_string(),
+            source,
+        })?;
+
+        let dir = exe_path.parent().ok_or_else(|| RouterError::Io {
+            action: format!(
+                "Failed to get executable directory of: {}",
+                exe_path.display()
+            ),
+            source: io::ErrorKind::NotFound.into(),
+        })?;
+
+        Ok(dir.to_path_buf())
+    }
+}
+
+/// Replaces top-level sections of the document with those of the selected
+/// entry in `profiles`. The profile is `requested` if given, otherwise the
+/// document's `active_profile`. Returns the name of the applied profile.
+fn apply_embedded_profile(
+    document: &mut serde_yaml::Value,
+    requested: Option<&str>,
+) -> Result<Option<String>> {
+    let serde_yaml::Value::Mapping(root) = document else {
+        return Ok(None);
+    };
+
+    let profiles = root.remove("profiles");
+    let active = root
+        .remove("active_profile")
+        .and_then(|value| value.as_str().map(str::to_string));
+
+    let Some(name) = requested.map(str::to_string).or(active) else {
+        return Ok(None);
+    };
+
+    let profile = profiles
+        .as_ref()
+        .and_then(|profiles| profiles.get(name.as_str()))
+        .ok_or_else(|| {
+            RouterError::InvalidConfig(format!(
+                "Profile '{}' not found: no config.{}.yaml and no '{}' entry in profiles",
+                name, name, name
+            ))
+        })?;
+
+    let serde_yaml::Value::Mapping(sections) = profile else {
+        return Err(RouterError::InvalidConfig(format!(
+            "Profile '{}' must be a mapping",
+            name
+        )));
+    };
+    for (key, value) in sections {
+        root.insert(key.clone(), value.clone());
+    }
+
+    Ok(Some(name))
+}
+
+/// Copies every `device_defaults` field into each device that leaves it out,
+/// then drops the section. A device that sets either of `gain` and `gain_db`
+/// takes neither from the defaults.
+fn apply_device_defaults(document: &mut serde_yaml::Value) -> Result<()> {
+    use serde_yaml::Value;
+
+    let Value::Mapping(root) = document else {
+        return Ok(());
+    };
+    let Some(defaults) = root.remove("device_defaults") else {
+        return Ok(());
+    };
+    let Value::Mapping(defaults) = defaults else {
+        return Err(RouterError::InvalidConfig(
+            "device_defaults must be a mapping".to_string(),
+        ));
+    };
+    let Some(devices) = root.get_mut("devices").and_then(Value::as_mapping_mut) else {
+        return Ok(());
+    };
+
+    for device in devices.values_mut() {
+        let Some(device) = device.as_mapping_mut() else {
+            continue;
+        };
+        let sets_gain = device.contains_key("gain") || device.contains_key("gain_db");
+        for (key, value) in &defaults {
+            let is_gain = matches!(key.as_str(), Some("gain" | "gain_db"));
+            if !device.contains_key(key) && (!is_gain || !sets_gain) {
+                device.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+pub fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.log10()
+}
+
+/// Fills in `gain` for devices that set `gain_db` instead, so the rest of
+/// the config only deals with linear gains.
+fn resolve_gain_db(document: &mut serde_yaml::Value) -> Result<()> {
+    use serde_yaml::Value;
+
+    let Some(devices) = document.get_mut("devices").and_then(Value::as_mapping_mut) else {
+        return Ok(());
+    };
+    for (alias, device) in devices.iter_mut() {
+        let alias = alias.as_str().unwrap_or_default();
+        let Some(gain_db) = device.get("gain_db") else {
+            continue;
+        };
+        if device.get("gain").is_some() {
+            return Err(RouterError::InvalidConfig(format!(
+                "Device '{}' sets both gain and gain_db; use one of them",
+                alias
+            )));
+        }
+        let gain_db = gain_db.as_f64().ok_or_else(|| {
+            RouterError::InvalidConfig(format!("Device '{}' has a non-numeric gain_db", alias))
+        })?;
+        if let Some(device) = device.as_mapping_mut() {
+            device.insert(
+                Value::from("gain"),
+                Value::from(db_to_gain(gain_db as f32) as f64),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Replaces `${NAME}` in every string value with the environment variable
+/// `NAME`, so one config can be shared by machines whose device names differ.
+fn interpolate_env(value: &mut serde_yaml::Value) -> Result<()> {
+    use serde_yaml::Value;
+
+    match value {
+        Value::String(text) if text.contains("${") => *text = expand_env(text)?,
+        Value::Mapping(mapping) => {
+            for (_, value) in mapping.iter_mut() {
+                interpolate_env(value)?;
+            }
+        }
+        Value::Sequence(sequence) => {
+            for value in sequence {
+                interpolate_env(value)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn expand_env(text: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find('}').ok_or_else(|| {
+            RouterError::InvalidConfig(format!("Unterminated '${{' in config value \"{}\"", text))
+        })?;
+        let name = &after[..end];
+        let value = env::var(name).map_err(|_| {
+            RouterError::InvalidConfig(format!(
+                "Environment variable '{}' used in config value \"{}\" is not set",
+                name, text
+            ))
+        })?;
+        expanded.push_str(&value);
+        rest = &after[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Appends the dotted path of every key in `document` that has no
+/// counterpart in `known`.
+fn collect_unknown_fields(
+    document: &serde_yaml::Value,
+    known: &serde_yaml::Value,
+    path: &str,
+    unknown: &mut Vec<String>,
+) {
+    use serde_yaml::Value;
+
+    match (document, known) {
+        (Value::Mapping(document), Value::Mapping(known)) => {
+            for (key, value) in document {
+                let name = match key {
+                    Value::String(name) => name.clone(),
+                    other => serde_yaml::to_string(other)
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string(),
+                };
+                let field = if path.is_empty() {
+                    name
+                } else {
+                    format!("{}.{}", path, name)
+                };
+                match known.get(key) {
+                    Some(known_value) => {
+                        collect_unknown_fields(value, known_value, &field, unknown)
+                    }
+                    None => unknown.push(field),
+                }
+            }
+        }
+        (Value::Sequence(document), Value::Sequence(known)) => {
+            for (i, (value, known_value)) in document.iter().zip(known).enumerate() {
+                collect_unknown_fields(value, known_value, &format!("{}[{}]", path, i), unknown);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn read_config_files(paths: &[PathBuf]) -> Result<Vec<String>> {
+    paths.iter().map(|path| read_config_file(path)).collect()
+}
+
+fn read_config_file(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|source| RouterError::Io {
+        action: format!("Failed to read config from: {}", path.display()),
+        source,
+    })
+}
+
+fn parse_error(source: serde_yaml::Error) -> RouterError {
+    RouterError::ConfigParse { path: None, source }
+}
+
+/// Merges `overlay` into `base`: mappings key by key, recursively; any other
+/// overlay value replaces the base value. An empty file merges as nothing.
+fn merge_yaml(base: &mut serde_yaml::Value, overlay: serde_yaml::Value) {
+    match (base, overlay) {
+        (_, serde_yaml::Value::Null) => {}
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) if value.is_mapping() => merge_yaml(existing, value),
+                    _ => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// `value` as a YAML float. YAML spells infinity `.inf`, and reads Rust's
+/// `inf` as a string.
+fn yaml_float(value: f32) -> String {
+    match value {
+        f32::INFINITY => ".inf".to_string(),
+        f32::NEG_INFINITY => "-.inf".to_string(),
+        value => format!("{:?}", value),
+    }
+}
+
+/// Whether a config file has an entry for device `alias`.
+fn lists_device(document: &str, alias: &str) -> bool {
+    serde_yaml::from_str::<serde_yaml::Value>(document)
+        .ok()
+        .and_then(|document| document.get("devices")?.get(alias).cloned())
+        .is_some()
+}
+
+/// Replaces the scalar value at `path` in a YAML document, keeping
+/// indentation and any trailing comment. A parent written as a one-line flow
+/// mapping (`mic: { name: Mic, gain: 1.0 }`) has the entry replaced or
+/// appended inside its braces; other flow collections on the path are
+/// refused. A missing key is inserted as the first child of its parent
+/// mapping. The edited document is parsed again to check that `path` now
+/// holds `value`.
+fn set_yaml_scalar(document: &str, path: &[&str], value: &str) -> Result<String> {
+    let edited = edit_yaml_scalar(document, path, value)?;
+
+    let expected = serde_yaml::from_str::<serde_yaml::Value>(value).ok();
+    let parsed = serde_yaml::from_str::<serde_yaml::Value>(&edited).ok();
+    let actual = parsed
+        .as_ref()
+        .and_then(|parsed| path.iter().try_fold(parsed, |node, key| node.get(key)));
+    if expected.is_none() || actual != expected.as_ref() {
+        return Err(RouterError::InvalidConfig(format!(
+            "Could not set config key '{}' to {} without breaking the file; edit it by hand",
+            path.join("."),
+            value
+        )));
+    }
+
+    Ok(edited)
+}
+
+fn edit_yaml_scalar(document: &str, path: &[&str], value: &str) -> Result<String> {
+    let mut lines: Vec<String> = document.lines().map(str::to_string).collect();
+    let mut stack: Vec<(usize, String)> = Vec::new();
+    let mut parent_line = None;
+
+    for (i, line) in lines.iter_mut().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('-') {
+            continue;
+        }
+
+        let indent = line.len() - trimmed.len();
+        let Some((raw_key, rest)) = trimmed.split_once(':') else {
+            continue;
+        };
+        let key = raw_key.trim().trim_matches(|c| c == '"' || c == '\'');
+
+        while stack.last().is_some_and(|(depth, _)| *depth >= indent) {
+            stack.pop();
+        }
+
+        let on_path = stack.len() < path.len()
+            && stack.iter().zip(path).all(|((_, k), p)| k == p)
+            && key == path[stack.len()];
+
+        if on_path && stack.len() + 1 == path.len() {
+            let comment = find_comment(rest)
+                .map(|pos| {
+                    let before = &rest[..pos];
+                    let spacing = &before[before.trim_end().len()..];
+                    format!("{}{}", spacing, &rest[pos..])
+                })
+                .unwrap_or_default();
+            *line = format!("{}{}: {}{}", &line[..indent], raw_key, value, comment);
+            return Ok(join_lines(&lines, document));
+        }
+
+        let content = rest[..find_comment(rest).unwrap_or(rest.len())].trim_start();
+        if on_path && (content.starts_with('{') || content.starts_with('[')) {
+            let edited = (stack.len() + 2 == path.len())
+                .then(|| set_flow_entry(rest, path[path.len() - 1], value))
+                .flatten()
+                .ok_or_else(|| {
+                    RouterError::InvalidConfig(format!(
+                        "Config key '{}' is written in a flow style that can't be edited \
+                         automatically; use a block mapping or a one-line {{ ... }}",
+                        path[..=stack.len()].join(".")
+                    ))
+                })?;
+            *line = format!("{}{}:{}", &line[..indent], raw_key, edited);
+            return Ok(join_lines(&lines, document));
+        }
+
+        stack.push((indent, key.to_string()));
+
+        if on_path && stack.len() + 1 == path.len() {
+            parent_line = Some((i, indent));
+        }
+    }
+
+    let (parent_index, parent_indent) = parent_line.ok_or_else(|| {
+        RouterError::InvalidConfig(format!(
+            "Config key '{}' not found",
+            path[..path.len() - 1].join(".")
+        ))
+    })?;
+
+    let child_indent = lines[parent_index + 1..]
+        .iter()
+        .map(|line| (line.len() - line.trim_start().len(), line.trim_start()))
+        .find(|(_, trimmed)| !trimmed.is_empty() && !trimmed.starts_with('#'))
+        .map(|(indent, _)| indent)
+        .filter(|indent| *indent > parent_indent)
+        .unwrap_or(parent_indent + 2);
+
+    lines.insert(
+        parent_index + 1,
+        format!(
+            "{}{}: {}",
+            " ".repeat(child_indent),
+            path[path.len() - 1],
+            value
+        ),
+    );
+
+    Ok(join_lines(&lines, document))
+}
+
+/// Sets `key` to `value` in the one-line flow mapping `text` (what follows a
+/// key's colon, e.g. ` { name: Mic, gain: 1.0 }  # comment`), replacing the
+/// entry or appending it before the closing brace. `None` if `text` is not a
+/// complete one-line flow mapping.
+fn set_flow_entry(text: &str, key: &str, value: &str) -> Option<String> {
+    let open = text.find('{')?;
+    let mut entries = Vec::new();
+    let mut entry_start = open + 1;
+    let mut close = None;
+    let mut depth = 0;
+    let mut quote = None;
+
+    for (i, c) in text.char_indices().skip_while(|(i, _)| *i <= open) {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '{' | '[') => depth += 1,
+            (None, ']') => depth -= 1,
+            (None, '}') if depth > 0 => depth -= 1,
+            (None, '}') => {
+                entries.push(entry_start..i);
+                close = Some(i);
+                break;
+            }
+            (None, ',') if depth == 0 => {
+                entries.push(entry_start..i);
+                entry_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    let close = close?;
+    let after = &text[close + 1..];
+    if !after[..find_comment(after).unwrap_or(after.len())]
+        .trim()
+        .is_empty()
+    {
+        return None;
+    }
+
+    for entry in entries {
+        let Some((entry_key, entry_value)) = text[entry.clone()].split_once(':') else {
+            continue;
+        };
+        if entry_key.trim().trim_matches(|c| c == '"' || c == '\'') != key {
+            continue;
+        }
+        let value_start = entry.start + entry_key.len() + 1;
+        let leading = entry_value.len() - entry_value.trim_start().len();
+        let trailing = entry_value.len() - entry_value.trim_end().len();
+        let value_end = entry.end - trailing;
+        let value_start = (value_start + leading).min(value_end);
+        let separator = if leading == 0 { " " } else { "" };
+        return Some(format!(
+            "{}{}{}{}",
+            &text[..value_start],
+            separator,
+            value,
+            &text[value_end..]
+        ));
+    }
+
+    let inner = &text[open + 1..close];
+    Some(if inner.trim().is_empty() {
+        format!("{}{{ {}: {} }}{}", &text[..open], key, value, after)
+    } else {
+        let end = open + 1 + inner.trim_end().len();
+        format!("{}, {}: {}{}", &text[..end], key, value, &text[end..])
+    })
+}
+
+/// Finds the start of a trailing `# comment` outside of quoted strings.
+fn find_comment(value: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut prev_is_space = true;
+
+    for (i, c) in value.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '#') if prev_is_space => return Some(i),
+            _ => {}
+        }
+        prev_is_space = c.is_whitespace();
+    }
+
+    None
+}
+
+fn join_lines(lines: &[String], original: &str) -> String {
+    let mut joined = lines.join("\n");
+    if original.ends_with('\n') {
+        joined.push('\n');
+    }
+    joined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio_config(law: &str, mix_ratio: f32) -> AudioConfig {
+        serde_yaml::from_str(&format!(
+            "keep_alive_sleep_ms: 100\n\
+             stereo_to_mono_mix_ratio: {}\n\
+             downmix_law: {}\n\
+             audio_sample_min: -1.0\n\
+             audio_sample_max: 1.0\n",
+            mix_ratio, law
+        ))
+        .unwrap()
+    }
+
+    /// Level in dB of mono mixed from `left` and `right` under `law`,
+    /// relative to the level of one channel.
+    fn downmix_level_db(law: &str, mix_ratio: f32, left: &[f32], right: &[f32]) -> f32 {
+        let ratio = audio_config(law, mix_ratio).downmix_ratio();
+        let power = |signal: &mut dyn Iterator<Item = f32>| {
+            signal.map(|s| (s * s) as f64).sum::<f64>() / left.len() as f64
+        };
+        let mono = power(&mut left.iter().zip(right).map(|(l, r)| (l + r) * ratio));
+        let channel = power(&mut left.iter().copied());
+        (10.0 * (mono / channel).log10()) as f32
+    }
+
+    fn noise(seed: u32, len: usize) -> Vec<f32> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> 8) as f32 / (1u32 << 23) as f32 - 1.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn downmix_laws_scale_identical_channels() {
+        let tone: Vec<f32> = (0..4800).map(|n| (n as f32 * 0.05).sin() * 0.5).collect();
+        let average = downmix_level_db("average", 0.5, &tone, &tone);
+        let rms = downmix_level_db("rms", 0.5, &tone, &tone);
+        let sum = downmix_level_db("sum", 1.0, &tone, &tone);
+
+        // Identical channels: average keeps the level, rms is 3 dB above it
+        // and a plain sum is 6 dB above it.
+        assert!(average.abs() < 0.01, "average {} dB", average);
+        assert!((rms - average - 3.01).abs() < 0.01, "rms {} dB", rms);
+        assert!((sum - average - 6.02).abs() < 0.01, "sum {} dB", sum);
+    }
+
+    #[test]
+    fn rms_downmix_keeps_the_loudness_of_uncorrelated_channels() {
+        let left = noise(1, 48000);
+        let right = noise(2, 48000);
+        let average = downmix_level_db("average", 0.5, &left, &right);
+        let rms = downmix_level_db("rms", 0.5, &left, &right);
+
+        assert!(rms.abs() < 0.2, "rms {} dB", rms);
+        assert!((average + 3.01).abs() < 0.2, "average {} dB", average);
+    }
+
+    #[test]
+    fn sum_downmix_uses_the_configured_ratio() {
+        assert_eq!(audio_config("sum", 0.3).downmix_ratio(), 0.3);
+        assert_eq!(audio_config("average", 0.3).downmix_ratio(), 0.5);
+        assert_eq!(
+            audio_config("rms", 0.3).downmix_ratio(),
+            std::f32::consts::FRAC_1_SQRT_2
+        );
+    }
+
+    #[test]
+    fn average_downmix_never_exceeds_full_scale() {
+        let ratio = audio_config("average", 0.5).downmix_ratio();
+        for (left, right) in [(1.0f32, 1.0f32), (-1.0, -1.0), (1.0, -1.0), (0.7, 1.0)] {
+            assert!(((left + right) * ratio).abs() <= 1.0);
+        }
+    }
+
+    const MINIMAL_CONFIG: &str = "\
+devices:
+  mic: { name: Mic, type: input, buffer_size: 256, primary_buffer: 4096, gain: 1.0 }
+  out: { name: Out, type: output, buffer_size: 256, primary_buffer: 4096, gain: 1.0 }
+routing:
+  r: { from: mic, to: out }
+audio:
+  keep_alive_sleep_ms: 100
+  stereo_to_mono_mix_ratio: 0.5
+  audio_sample_min: -1.0
+  audio_sample_max: 1.0
+logging:
+  level: info
+device_wait: { enabled: false, max_wait_time: 1, retry_interval: 1, allow_partial: false }
+";
+
+    #[test]
+    fn a_valid_config_parses() {
+        let config = Config::from_yaml(MINIMAL_CONFIG).unwrap();
+        assert_eq!(config.routing["r"].to, "out");
+        // Embedders only get a lock file when they ask for one.
+        assert!(!config.instance_lock);
+    }
+
+    #[test]
+    fn malformed_yaml_is_a_parse_error() {
+        let error = Config::from_yaml("devices: [unclosed").unwrap_err();
+        assert!(
+            matches!(error, RouterError::ConfigParse { path: None, .. }),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn inconsistent_settings_are_invalid_config() {
+        let yaml = MINIMAL_CONFIG.replace("gain: 1.0 }", "gain: 1.0, gain_db: 0.0 }");
+        let error = Config::from_yaml(&yaml).unwrap_err();
+        let RouterError::InvalidConfig(reason) = error else {
+            panic!("{:?}", error);
+        };
+        assert!(reason.contains("both gain and gain_db"), "{}", reason);
+    }
+
+    /// Saves `config` over `document` and returns the edited document.
+    fn saved(config: &Config, document: &str) -> Result<String> {
+        let mut documents = vec![document.to_string()];
+        config.write_gains(&[PathBuf::from("config.yaml")], &mut documents, None)?;
+        Ok(documents.remove(0))
+    }
+
+    #[test]
+    fn saving_a_gain_keeps_comments_and_formatting() {
+        let document = MINIMAL_CONFIG.replace(
+            "  mic: { name: Mic, type: input, buffer_size: 256, primary_buffer: 4096, gain: 1.0 }\n",
+            "  # The desk microphone.\n\
+             \x20 mic:\n\
+             \x20   name: Mic   # USB interface\n\
+             \x20   type: input\n\
+             \x20   buffer_size: 256\n\
+             \x20   primary_buffer: 4096\n\
+             \x20   gain: 1.0   # unity\n",
+        );
+        let mut config = Config::from_yaml(&document).unwrap();
+        config.devices.get_mut("mic").unwrap().gain = 2.0;
+
+        let saved = saved(&config, &document).unwrap();
+        assert_eq!(
+            saved,
+            document.replace("gain: 1.0   # unity", "gain: 2.0   # unity")
+        );
+        assert_eq!(Config::from_yaml(&saved).unwrap().devices["mic"].gain, 2.0);
+    }
+
+    #[test]
+    fn saving_a_gain_edits_flow_mappings() {
+        let mut config = Config::from_yaml(MINIMAL_CONFIG).unwrap();
+        config.devices.get_mut("out").unwrap().gain = 0.5;
+
+        let saved = saved(&config, MINIMAL_CONFIG).unwrap();
+        assert_eq!(
+            saved,
+            MINIMAL_CONFIG.replace(
+                "name: Out, type: output, buffer_size: 256, primary_buffer: 4096, gain: 1.0 }",
+                "name: Out, type: output, buffer_size: 256, primary_buffer: 4096, gain: 0.5 }"
+            )
+        );
+        let reloaded = Config::from_yaml(&saved).unwrap();
+        assert_eq!(reloaded.devices["out"].gain, 0.5);
+        assert_eq!(reloaded.devices["mic"].gain, 1.0);
+    }
+
+    #[test]
+    fn flow_entries_are_replaced_or_appended() {
+        let document = "devices:\n  mic: { name: \"Mic, {USB}\", gain: 1.0 }  # desk\n";
+        assert_eq!(
+            set_yaml_scalar(document, &["devices", "mic", "gain"], "2.0").unwrap(),
+            "devices:\n  mic: { name: \"Mic, {USB}\", gain: 2.0 }  # desk\n"
+        );
+        assert_eq!(
+            set_yaml_scalar(document, &["devices", "mic", "gain_db"], "-6.0").unwrap(),
+            "devices:\n  mic: { name: \"Mic, {USB}\", gain: 1.0, gain_db: -6.0 }  # desk\n"
+        );
+        assert_eq!(
+            set_yaml_scalar("devices:\n  mic: {}\n", &["devices", "mic", "gain"], "2.0").unwrap(),
+            "devices:\n  mic: { gain: 2.0 }\n"
+        );
+    }
+
+    #[test]
+    fn missing_block_keys_are_inserted() {
+        let document = "devices:\n  mic:\n    name: Mic\n";
+        assert_eq!(
+            set_yaml_scalar(document, &["devices", "mic", "gain"], "2.0").unwrap(),
+            "devices:\n  mic:\n    gain: 2.0\n    name: Mic\n"
+        );
+    }
+
+    #[test]
+    fn flow_mappings_that_cannot_be_edited_are_refused() {
+        for document in [
+            "devices: { mic: { name: Mic, gain: 1.0 } }\n",
+            "devices:\n  mic: {\n    name: Mic, gain: 1.0 }\n",
+        ] {
+            let error = set_yaml_scalar(document, &["devices", "mic", "gain"], "2.0").unwrap_err();
+            assert!(
+                matches!(error, RouterError::InvalidConfig(_)),
+                "{}: {:?}",
+                document,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn a_zero_gain_round_trips_through_gain_db() {
+        let document = MINIMAL_CONFIG.replace("gain: 1.0 }", "gain_db: -6.0 }");
+        let mut config = Config::from_yaml(&document).unwrap();
+        config.devices.get_mut("mic").unwrap().gain = 0.0;
 
-        let dir = exe_path
-            .parent()
-            .context("Failed to get executable directory")?
-            .to_path_buf();
+        let muted = saved(&config, &document).unwrap();
+        assert!(muted.contains("gain_db: -.inf }"), "{}", muted);
+        let reloaded = Config::from_yaml(&muted).unwrap();
+        assert_eq!(reloaded.devices["mic"].gain, 0.0);
 
-        Ok(dir)
+        config.devices.get_mut("mic").unwrap().gain = -1.0;
+        assert!(matches!(
+            saved(&config, &document),
+            Err(RouterError::InvalidConfig(_))
+        ));
     }
 }
@@ -0,0 +1,70 @@
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use std::env;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use crate::{Config, Router, ShutdownReason};
+
+pub fn run_as_daemon() -> Result<()> {
+    let config = Config::load().context("Failed to load configuration")?;
+
+    let log_path = config.log_path()?;
+    crate::logger::FileLogger::init(log_path.clone(), &config.logging)?;
+
+    info!("Audio Router {}", crate::VERSION);
+    info!("Audio Router daemon starting");
+    if let Some(profile) = &config.profile {
+        info!("Using config profile: {}", profile);
+    }
+    info!("Logging to: {}", log_path.display());
+
+    let running = Arc::new(AtomicBool::new(true));
+    let running_handle = running.clone();
+    let stop_reason = Arc::new(OnceLock::new());
+    let stop_reason_handle = stop_reason.clone();
+
+    ctrlc::set_handler(move || {
+        info!("Daemon stop requested (SIGTERM/SIGINT)");
+        let _ = stop_reason_handle.set(ShutdownReason::ServiceStop);
+        running_handle.store(false, Ordering::SeqCst);
+    })?;
+
+    // systemd reports the unit started only once routes are playing, so a
+    // config whose devices cannot be found fails the start.
+    let result = Router::new(config)
+        .on_ready(|| sd_notify("READY=1"))
+        .run(running);
+
+    sd_notify("STOPPING=1");
+
+    let reason = match &result {
+        Ok(()) => stop_reason
+            .get()
+            .copied()
+            .unwrap_or(ShutdownReason::ServiceStop),
+        Err(e) => {
+            error!("Audio routing failed: {:#}", e);
+            ShutdownReason::from(e)
+        }
+    };
+    reason.log();
+    info!("Daemon stopped");
+
+    log::logger().flush();
+    Ok(result?)
+}
+
+fn sd_notify(state: &str) {
+    let Some(socket_path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let result =
+        UnixDatagram::unbound().and_then(|socket| socket.send_to(state.as_bytes(), &socket_path));
+
+    if let Err(e) = result {
+        warn!("Failed to notify systemd ({}): {}", state, e);
+    }
+}
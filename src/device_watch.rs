@@ -0,0 +1,361 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Tells the keep-alive loop as soon as the OS reports an audio device
+/// change, so missing devices are looked for at once instead of at the next
+/// poll. Stops watching when dropped.
+pub struct DeviceWatch {
+    changed: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DeviceWatch {
+    /// Starts watching for device changes. Returns `None` where the platform
+    /// offers no notifications, leaving polling as the only trigger.
+    pub fn start() -> Option<Self> {
+        let changed = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = platform::spawn(changed.clone(), stop.clone())?;
+
+        Some(DeviceWatch {
+            changed,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Whether a change was reported since the last call.
+    pub fn take_change(&self) -> bool {
+        self.changed.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl Drop for DeviceWatch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Listens on the kernel's uevent netlink socket, the same one udev reads,
+/// and reports events of the `sound` subsystem: a card or PCM device being
+/// added, removed or changed.
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+    use log::debug;
+    use std::io;
+    use std::mem;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    /// How often the thread wakes up without events to check for `stop`.
+    const STOP_CHECK_INTERVAL_US: libc::suseconds_t = 250_000;
+
+    /// The kernel broadcasts uevents to this multicast group.
+    const KERNEL_EVENTS: u32 = 1;
+
+    pub fn spawn(changed: Arc<AtomicBool>, stop: Arc<AtomicBool>) -> Option<JoinHandle<()>> {
+        let socket = open_socket()
+            .map_err(|e| debug!("Failed to open the uevent socket: {}", e))
+            .ok()?;
+
+        thread::Builder::new()
+            .name("device-watch".to_string())
+            .spawn(move || {
+                let mut buffer = [0u8; 8192];
+                while !stop.load(Ordering::SeqCst) {
+                    let received = unsafe {
+                        libc::recv(
+                            socket.as_raw_fd(),
+                            buffer.as_mut_ptr().cast(),
+                            buffer.len(),
+                            0,
+                        )
+                    };
+                    // Timeouts and interruptions come back as errors.
+                    let Ok(len) = usize::try_from(received) else {
+                        continue;
+                    };
+                    if is_sound_event(&buffer[..len]) {
+                        debug!("Sound device changed");
+                        changed.store(true, Ordering::SeqCst);
+                    }
+                }
+            })
+            .ok()
+    }
+
+    fn open_socket() -> io::Result<OwnedFd> {
+        unsafe {
+            let fd = libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            );
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let socket = OwnedFd::from_raw_fd(fd);
+
+            let mut address: libc::sockaddr_nl = mem::zeroed();
+            address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            address.nl_groups = KERNEL_EVENTS;
+            if libc::bind(
+                fd,
+                (&address as *const libc::sockaddr_nl).cast(),
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            ) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            let timeout = libc::timeval {
+                tv_sec: 0,
+                tv_usec: STOP_CHECK_INTERVAL_US,
+            };
+            if libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                (&timeout as *const libc::timeval).cast(),
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            ) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(socket)
+        }
+    }
+
+    /// Whether a kernel uevent (`action@devpath` followed by NUL-separated
+    /// `KEY=value` pairs) is about a sound device.
+    pub(super) fn is_sound_event(message: &[u8]) -> bool {
+        message
+            .split(|&byte| byte == 0)
+            .skip(1)
+            .any(|field| field == b"SUBSYSTEM=sound")
+    }
+}
+
+/// Registers an `IMMNotificationClient` with the WASAPI device enumerator;
+/// Windows calls it when an endpoint is added, removed, enabled or disabled,
+/// or the default device changes.
+#[cfg(windows)]
+mod platform {
+    use super::*;
+    use log::debug;
+    use std::ptr;
+    use std::sync::atomic::AtomicU32;
+    use std::sync::mpsc;
+    use winapi::ctypes::c_void;
+    use winapi::shared::guiddef::{IsEqualIID, REFIID};
+    use winapi::shared::minwindef::{DWORD, ULONG};
+    use winapi::shared::winerror::{E_NOINTERFACE, FAILED, SUCCEEDED, S_OK};
+    use winapi::shared::wtypes::PROPERTYKEY;
+    use winapi::um::combaseapi::{CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_ALL};
+    use winapi::um::mmdeviceapi::{
+        CLSID_MMDeviceEnumerator, EDataFlow, ERole, IMMDeviceEnumerator, IMMNotificationClient,
+        IMMNotificationClientVtbl,
+    };
+    use winapi::um::objbase::COINIT_MULTITHREADED;
+    use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+    use winapi::um::winnt::{HRESULT, LPCWSTR};
+    use winapi::Interface;
+
+    pub fn spawn(changed: Arc<AtomicBool>, stop: Arc<AtomicBool>) -> Option<JoinHandle<()>> {
+        let (registered, result) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("device-watch".to_string())
+            .spawn(move || unsafe {
+                let initialized = SUCCEEDED(CoInitializeEx(ptr::null_mut(), COINIT_MULTITHREADED));
+                let client = Client::new(changed);
+                let enumerator = register(client);
+                let _ = registered.send(enumerator.is_some());
+
+                if let Some(enumerator) = enumerator {
+                    while !stop.load(Ordering::SeqCst) {
+                        thread::park();
+                    }
+                    (*enumerator).UnregisterEndpointNotificationCallback(client.cast());
+                    (*enumerator).Release();
+                }
+                Client::release(client.cast());
+                if initialized {
+                    CoUninitialize();
+                }
+            })
+            .ok()?;
+
+        if result.recv().unwrap_or(false) {
+            Some(thread)
+        } else {
+            let _ = thread.join();
+            None
+        }
+    }
+
+    unsafe fn register(client: *mut Client) -> Option<*mut IMMDeviceEnumerator> {
+        let mut enumerator: *mut IMMDeviceEnumerator = ptr::null_mut();
+        let hr = CoCreateInstance(
+            &CLSID_MMDeviceEnumerator,
+            ptr::null_mut(),
+            CLSCTX_ALL,
+            &IMMDeviceEnumerator::uuidof(),
+            &mut enumerator as *mut _ as *mut _,
+        );
+        if FAILED(hr) {
+            debug!("Failed to create the device enumerator: 0x{:08x}", hr);
+            return None;
+        }
+
+        let hr = (*enumerator).RegisterEndpointNotificationCallback(client.cast());
+        if FAILED(hr) {
+            debug!("Failed to register for device notifications: 0x{:08x}", hr);
+            (*enumerator).Release();
+            return None;
+        }
+        Some(enumerator)
+    }
+
+    /// The COM object Windows calls. `interface` comes first, so a pointer
+    /// to the client is also a pointer to its `IMMNotificationClient`.
+    #[repr(C)]
+    struct Client {
+        interface: IMMNotificationClient,
+        references: AtomicU32,
+        changed: Arc<AtomicBool>,
+    }
+
+    static VTABLE: IMMNotificationClientVtbl = IMMNotificationClientVtbl {
+        parent: IUnknownVtbl {
+            QueryInterface: Client::query_interface,
+            AddRef: Client::add_ref,
+            Release: Client::release,
+        },
+        OnDeviceStateChanged: Client::on_device_state_changed,
+        OnDeviceAdded: Client::on_device_added,
+        OnDeviceRemoved: Client::on_device_removed,
+        OnDefaultDeviceChanged: Client::on_default_device_changed,
+        OnPropertyValueChanged: Client::on_property_value_changed,
+    };
+
+    impl Client {
+        /// Allocates a client holding one reference.
+        fn new(changed: Arc<AtomicBool>) -> *mut Client {
+            Box::into_raw(Box::new(Client {
+                interface: IMMNotificationClient { lpVtbl: &VTABLE },
+                references: AtomicU32::new(1),
+                changed,
+            }))
+        }
+
+        fn notify(this: *mut IMMNotificationClient) -> HRESULT {
+            let client = unsafe { &*(this as *const Client) };
+            client.changed.store(true, Ordering::SeqCst);
+            S_OK
+        }
+
+        unsafe extern "system" fn query_interface(
+            this: *mut IUnknown,
+            riid: REFIID,
+            object: *mut *mut c_void,
+        ) -> HRESULT {
+            if IsEqualIID(&*riid, &IUnknown::uuidof())
+                || IsEqualIID(&*riid, &IMMNotificationClient::uuidof())
+            {
+                Self::add_ref(this);
+                *object = this.cast();
+                S_OK
+            } else {
+                *object = ptr::null_mut();
+                E_NOINTERFACE
+            }
+        }
+
+        unsafe extern "system" fn add_ref(this: *mut IUnknown) -> ULONG {
+            let client = &*(this as *const Client);
+            client.references.fetch_add(1, Ordering::SeqCst) + 1
+        }
+
+        unsafe extern "system" fn release(this: *mut IUnknown) -> ULONG {
+            let client = this as *mut Client;
+            let remaining = (*client).references.fetch_sub(1, Ordering::SeqCst) - 1;
+            if remaining == 0 {
+                drop(Box::from_raw(client));
+            }
+            remaining
+        }
+
+        unsafe extern "system" fn on_device_state_changed(
+            this: *mut IMMNotificationClient,
+            _device_id: LPCWSTR,
+            _new_state: DWORD,
+        ) -> HRESULT {
+            Self::notify(this)
+        }
+
+        unsafe extern "system" fn on_device_added(
+            this: *mut IMMNotificationClient,
+            _device_id: LPCWSTR,
+        ) -> HRESULT {
+            Self::notify(this)
+        }
+
+        unsafe extern "system" fn on_device_removed(
+            this: *mut IMMNotificationClient,
+            _device_id: LPCWSTR,
+        ) -> HRESULT {
+            Self::notify(this)
+        }
+
+        unsafe extern "system" fn on_default_device_changed(
+            this: *mut IMMNotificationClient,
+            _flow: EDataFlow,
+            _role: ERole,
+            _device_id: LPCWSTR,
+        ) -> HRESULT {
+            Self::notify(this)
+        }
+
+        /// Property changes (volume, format) do not add or remove devices.
+        unsafe extern "system" fn on_property_value_changed(
+            _this: *mut IMMNotificationClient,
+            _device_id: LPCWSTR,
+            _key: PROPERTYKEY,
+        ) -> HRESULT {
+            S_OK
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod platform {
+    use super::*;
+
+    pub fn spawn(_changed: Arc<AtomicBool>, _stop: Arc<AtomicBool>) -> Option<JoinHandle<()>> {
+        None
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::platform::is_sound_event;
+
+    #[test]
+    fn only_sound_subsystem_events_count() {
+        let card_added = b"add@/devices/pci0000:00/usb1/1-2/1-2:1.0/sound/card2\0\
+ACTION=add\0DEVPATH=/devices/pci0000:00/usb1/1-2/1-2:1.0/sound/card2\0\
+SUBSYSTEM=sound\0SEQNUM=4711\0";
+        let disk_added = b"add@/devices/virtual/block/loop0\0ACTION=add\0SUBSYSTEM=block\0";
+
+        assert!(is_sound_event(card_added));
+        assert!(!is_sound_event(disk_added));
+        assert!(!is_sound_event(b"SUBSYSTEM=sound"));
+    }
+}
@@ -1,51 +1,163 @@
-use anyhow::{Context, Result};
 use cpal::traits::{DeviceTrait, HostTrait};
-use cpal::{Device, Host};
-use log::{debug, info, warn};
-use std::collections::HashMap;
+use cpal::{Device, Host, HostId, SupportedStreamConfigRange};
+use log::{debug, info, trace, warn};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
 use std::thread;
 use std::time::{Duration, Instant};
 
-use crate::config::{Config, DeviceType};
+use crate::config::{Config, DeviceConfig, DeviceType};
+use crate::error::{Result, RouterError};
+use crate::hosts::AudioHosts;
 
 pub struct AudioDevices {
     devices: HashMap<String, Device>,
 }
 
+/// Named devices of each host in use, in host order, enumerated once per
+/// lookup pass.
+type Snapshots = HashMap<HostId, Vec<(String, Device)>>;
+
+/// How one configured device resolves against the live device list, as
+/// reported by `match-devices`.
+pub struct DeviceMatch {
+    pub alias: String,
+    pub device_type: DeviceType,
+    pub identifier: String,
+    /// Every device the name pattern or index matched, in host order.
+    pub candidates: Vec<String>,
+    /// The device `find_all` would use.
+    pub selected: Option<String>,
+    /// The host the device is looked up through, when it is not the
+    /// default.
+    pub host: Option<String>,
+    /// Why the selected device would still be rejected.
+    pub problem: Option<String>,
+}
+
 impl AudioDevices {
-    pub fn get(&self, name: &str) -> Result<&Device> {
-        self.devices
-            .get(name)
-            .ok_or_else(|| anyhow::anyhow!("Device '{}' not found", name))
+    pub fn get(&self, name: &str) -> Option<&Device> {
+        self.devices.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.devices.contains_key(name)
+    }
+
+    /// Looks for configured devices that were missing until now and adds
+    /// any that have appeared. Returns the aliases that were found.
+    pub fn find_new(
+        &mut self,
+        config: &Config,
+        hosts: &AudioHosts,
+        aliases: &[&String],
+    ) -> Vec<String> {
+        let snapshots = Self::enumerate_hosts(
+            hosts,
+            aliases
+                .iter()
+                .filter_map(|&alias| config.devices.get(alias)),
+        );
+        let mut found = Vec::new();
+
+        for &alias in aliases {
+            let Some(device_config) = config.devices.get(alias) else {
+                continue;
+            };
+            if self.devices.contains_key(alias) {
+                continue;
+            }
+
+            match Self::find_on_host(
+                &snapshots,
+                hosts,
+                device_config,
+                alias,
+                config.device_wait.strict,
+            ) {
+                Ok(Some(device)) => {
+                    if let Err(e) =
+                        Self::verify_device_type(&device, &device_config.device_type, alias)
+                    {
+                        debug!("Device '{}' is listed but not ready: {}", alias, e);
+                        continue;
+                    }
+                    if let Err(e) = Self::verify_channels(&device, config, alias) {
+                        debug!("Device '{}' cannot be used yet: {:#}", alias, e);
+                        continue;
+                    }
+                    info!(
+                        "Device '{}' appeared: {}",
+                        alias,
+                        device_config.identifier()
+                    );
+                    self.devices.insert(alias.clone(), device);
+                    found.push(alias.clone());
+                }
+                Ok(None) => {}
+                Err(e) => debug!("Device '{}' lookup failed: {:#}", alias, e),
+            }
+        }
+
+        found
     }
 
-    pub fn find_all(config: &Config, host: &Host) -> Result<Self> {
+    pub fn find_all(config: &Config, hosts: &AudioHosts) -> Result<Self> {
+        for host in hosts.all() {
+            info!("Using audio host: {}", host.id().name());
+        }
+
         if config.device_wait.enabled {
-            Self::find_with_retry(config, host)
+            Self::find_with_retry(config, hosts)
         } else {
-            Self::find_immediate(config, host)
+            Self::find_immediate(config, hosts)
         }
     }
 
-    fn find_immediate(config: &Config, host: &Host) -> Result<Self> {
+    fn find_immediate(config: &Config, hosts: &AudioHosts) -> Result<Self> {
         info!("Searching for audio devices...");
 
         let mut devices = HashMap::new();
+        let snapshots = Self::enumerate_hosts(
+            hosts,
+            Self::hardware_devices(config).map(|(_, device_config)| device_config),
+        );
 
-        for (alias, device_config) in &config.devices {
-            let device = Self::find_device(host, &device_config.name)
-                .with_context(|| format!("Device '{}' not found: {}", alias, device_config.name))?;
+        for (alias, device_config) in Self::hardware_devices(config) {
+            let Some(device) = Self::find_on_host(
+                &snapshots,
+                hosts,
+                device_config,
+                alias,
+                config.device_wait.strict,
+            )?
+            else {
+                if config.is_replaceable(alias) {
+                    warn!(
+                        "Device '{}' not found: {}; its routes will use their fallback outputs",
+                        alias,
+                        device_config.identifier()
+                    );
+                    continue;
+                }
+                Self::log_host_hint(hosts.for_device(device_config));
+                return Err(RouterError::DeviceNotFound {
+                    device: alias.clone(),
+                    identifier: device_config.identifier(),
+                });
+            };
 
             Self::verify_device_type(&device, &device_config.device_type, alias)?;
+            Self::verify_channels(&device, config, alias)?;
 
-            info!("Found {} device: {}", alias, device_config.name);
+            info!("Found {} device: {}", alias, device_config.identifier());
             devices.insert(alias.clone(), device);
         }
 
         Ok(Self { devices })
     }
 
-    fn find_with_retry(config: &Config, host: &Host) -> Result<Self> {
+    fn find_with_retry(config: &Config, hosts: &AudioHosts) -> Result<Self> {
         let wait_config = &config.device_wait;
         let start_time = Instant::now();
         let max_duration = Duration::from_secs(wait_config.max_wait_time);
@@ -57,20 +169,38 @@ impl AudioDevices {
         );
 
         let mut devices = HashMap::new();
-        let mut missing: Vec<String> = config.devices.keys().cloned().collect();
+        let mut missing: Vec<String> = Self::hardware_devices(config)
+            .map(|(alias, _)| alias.clone())
+            .collect();
+        let mut last_missing_count = None;
 
         while start_time.elapsed() < max_duration && !missing.is_empty() {
             let mut found_this_round = Vec::new();
+            let snapshots = Self::enumerate_hosts(
+                hosts,
+                missing.iter().filter_map(|alias| config.devices.get(alias)),
+            );
 
             for alias in &missing {
                 if let Some(device_config) = config.devices.get(alias) {
-                    if let Some(device) = Self::find_device(host, &device_config.name) {
-                        if Self::verify_device_type(&device, &device_config.device_type, alias)
-                            .is_ok()
-                        {
-                            info!("Found {} device: {}", alias, device_config.name);
-                            devices.insert(alias.clone(), device);
-                            found_this_round.push(alias.clone());
+                    if let Some(device) = Self::find_on_host(
+                        &snapshots,
+                        hosts,
+                        device_config,
+                        alias,
+                        wait_config.strict,
+                    )? {
+                        // Devices can be listed before their server (JACK,
+                        // PipeWire) is ready to hand out a config; keep
+                        // retrying those rather than failing.
+                        match Self::verify_device_type(&device, &device_config.device_type, alias) {
+                            Ok(()) => {
+                                Self::verify_channels(&device, config, alias)?;
+                                info!("Found {} device: {}", alias, device_config.identifier());
+                                devices.insert(alias.clone(), device);
+                                found_this_round.push(alias.clone());
+                            }
+                            Err(e) => debug!("Device '{}' is listed but not ready: {}", alias, e),
                         }
                     }
                 }
@@ -86,68 +216,485 @@ impl AudioDevices {
             }
 
             let elapsed = start_time.elapsed().as_secs();
-            debug!(
-                "Waiting for devices... ({}s elapsed, {} missing)",
-                elapsed,
-                missing.len()
-            );
+            if last_missing_count != Some(missing.len()) {
+                debug!(
+                    "Waiting for devices... ({}s elapsed, {} missing: {:?})",
+                    elapsed,
+                    missing.len(),
+                    missing
+                );
+                last_missing_count = Some(missing.len());
+            } else {
+                trace!(
+                    "Waiting for devices... ({}s elapsed, {} missing)",
+                    elapsed,
+                    missing.len()
+                );
+            }
 
             thread::sleep(retry_interval);
         }
 
-        if !missing.is_empty() {
+        if !missing.is_empty() && missing.iter().all(|alias| config.is_replaceable(alias)) {
+            warn!(
+                "Devices not found after {}s timeout: {:?}; their routes will use their fallback outputs",
+                wait_config.max_wait_time, missing
+            );
+        } else if !missing.is_empty() {
+            Self::log_host_hint(hosts.default_host());
+
             if wait_config.allow_partial {
                 warn!("Some devices not found after timeout: {:?}", missing);
                 warn!("Continuing with partial device set (allow_partial=true)");
 
                 if devices.is_empty() {
-                    return Err(anyhow::anyhow!("No devices found, cannot continue"));
+                    return Err(RouterError::DevicesNotFound {
+                        devices: missing,
+                        waited: Some(max_duration),
+                    });
                 }
             } else {
-                return Err(anyhow::anyhow!(
-                    "Devices not found after {}s timeout: {:?}",
-                    wait_config.max_wait_time,
-                    missing
-                ));
+                return Err(RouterError::DevicesNotFound {
+                    devices: missing,
+                    waited: Some(max_duration),
+                });
             }
         }
 
         Ok(Self { devices })
     }
 
+    /// On Linux, devices served by JACK or PipeWire only appear through the
+    /// ALSA host once their server is running.
+    fn log_host_hint(host: &Host) {
+        if cfg!(target_os = "linux") {
+            warn!(
+                "Devices are looked up through the {} host; if they are provided by JACK or PipeWire, check that the server is running (increase device_wait.max_wait_time if it starts after Audio Router)",
+                host.id().name()
+            );
+        }
+    }
+
+    /// Configured devices backed by a host audio device; synthetic test
+    /// devices and network endpoints are excluded.
+    fn hardware_devices(config: &Config) -> impl Iterator<Item = (&String, &DeviceConfig)> {
+        config
+            .devices
+            .iter()
+            .filter(|(_, device_config)| device_config.is_hardware())
+    }
+
     fn verify_device_type(device: &Device, expected_type: &DeviceType, alias: &str) -> Result<()> {
         match expected_type {
             DeviceType::Input => {
                 device
                     .default_input_config()
-                    .map_err(|_| anyhow::anyhow!("Device '{}' is not an input device", alias))?;
+                    .map_err(|_| RouterError::WrongDeviceType {
+                        device: alias.to_string(),
+                        reason: format!("Device '{}' is not an input device", alias),
+                    })?;
             }
             DeviceType::Output => {
                 device
                     .default_output_config()
-                    .map_err(|_| anyhow::anyhow!("Device '{}' is not an output device", alias))?;
+                    .map_err(|_| RouterError::WrongDeviceType {
+                        device: alias.to_string(),
+                        reason: format!("Device '{}' is not an output device", alias),
+                    })?;
             }
+            DeviceType::Test | DeviceType::Udp | DeviceType::Null | DeviceType::Bus => {}
         }
         Ok(())
     }
 
-    fn find_device(host: &Host, name_pattern: &str) -> Option<Device> {
-        host.devices()
-            .ok()?
-            .find(|d| d.name().unwrap_or_default().contains(name_pattern))
+    /// Checks that the device offers a stream config with as many channels
+    /// as the routes using it address through `input_channels` or
+    /// `output_channels`, at the rate the routes open it at (`input_rate` or
+    /// `sample_rate`) if there is one, so a mismatch fails here rather than
+    /// when the stream is built. Devices that cannot list their configs are
+    /// left for stream setup to judge.
+    fn verify_channels(device: &Device, config: &Config, alias: &str) -> Result<()> {
+        let Some(device_config) = config.devices.get(alias) else {
+            return Ok(());
+        };
+        let ranges: Vec<SupportedStreamConfigRange> = match device_config.device_type {
+            DeviceType::Input => device.supported_input_configs().map(|r| r.collect()),
+            DeviceType::Output => device.supported_output_configs().map(|r| r.collect()),
+            DeviceType::Test | DeviceType::Udp | DeviceType::Null | DeviceType::Bus => {
+                return Ok(())
+            }
+        }
+        .unwrap_or_default();
+        if ranges.is_empty() {
+            return Ok(());
+        }
+
+        let required = Self::required_channels(config, alias);
+        let rate = config.input_rate(alias).or(device_config.sample_rate);
+        let supported = ranges.iter().any(|range| {
+            range.channels() >= required
+                && rate.is_none_or(|rate| {
+                    range.min_sample_rate().0 <= rate && range.max_sample_rate().0 >= rate
+                })
+        });
+        if supported {
+            return Ok(());
+        }
+
+        let offered: BTreeSet<String> = ranges
+            .iter()
+            .map(|range| {
+                format!(
+                    "{} ch {}-{} Hz",
+                    range.channels(),
+                    range.min_sample_rate().0,
+                    range.max_sample_rate().0
+                )
+            })
+            .collect();
+        Err(RouterError::WrongDeviceType {
+            device: alias.to_string(),
+            reason: format!(
+                "Device '{}' has no {} config with {} channels{} (supports: {})",
+                alias,
+                device_config.device_type,
+                required,
+                rate.map(|rate| format!(" at {} Hz", rate))
+                    .unwrap_or_default(),
+                offered.into_iter().collect::<Vec<_>>().join(", ")
+            ),
+        })
+    }
+
+    /// Channels the routes using `alias` need: one more than the highest
+    /// channel they select, and at least one.
+    fn required_channels(config: &Config, alias: &str) -> u16 {
+        config
+            .routing
+            .values()
+            .flat_map(|route| {
+                let input = (route.from == alias).then_some(&route.input_channels);
+                let output = route
+                    .outputs()
+                    .any(|output| output == alias)
+                    .then_some(&route.output_channels);
+                input.into_iter().chain(output).flatten().flatten()
+            })
+            .map(|&channel| channel as u16 + 1)
+            .max()
+            .unwrap_or(1)
+    }
+
+    /// Devices matching a config entry: the one at `index` if set, the one
+    /// with endpoint ID `id` if set, the host's current default for
+    /// `@default`, else every device whose name contains `name`.
+    fn candidates<'a>(
+        snapshot: &'a [(String, Device)],
+        host: &Host,
+        device_config: &DeviceConfig,
+    ) -> Vec<&'a (String, Device)> {
+        match device_config.index {
+            Some(index) => index
+                .checked_sub(1)
+                .and_then(|i| snapshot.get(i))
+                .into_iter()
+                .collect(),
+            None if device_config.id.is_some() => snapshot
+                .iter()
+                .zip(Self::endpoint_ids(snapshot))
+                .filter(|(_, id)| id.is_some() && *id == device_config.id)
+                .map(|(entry, _)| entry)
+                .take(1)
+                .collect(),
+            None if device_config.is_default_device() => {
+                let default = match device_config.device_type {
+                    DeviceType::Input => host.default_input_device(),
+                    _ => host.default_output_device(),
+                };
+                let default_name = default.and_then(|device| device.name().ok());
+                snapshot
+                    .iter()
+                    .filter(|(name, _)| default_name.as_ref() == Some(name))
+                    .take(1)
+                    .collect()
+            }
+            None => snapshot
+                .iter()
+                .filter(|(name, _)| name.contains(&device_config.name))
+                .collect(),
+        }
+    }
+
+    /// Looks `device_config` up among the devices of its own host.
+    fn find_on_host(
+        snapshots: &Snapshots,
+        hosts: &AudioHosts,
+        device_config: &DeviceConfig,
+        alias: &str,
+        strict: bool,
+    ) -> Result<Option<Device>> {
+        let host = hosts.for_device(device_config);
+        let snapshot = snapshots.get(&host.id()).map(Vec::as_slice);
+        Self::find_device(
+            snapshot.unwrap_or_default(),
+            host,
+            device_config,
+            alias,
+            strict,
+        )
+    }
+
+    fn find_device(
+        snapshot: &[(String, Device)],
+        host: &Host,
+        device_config: &DeviceConfig,
+        alias: &str,
+        strict: bool,
+    ) -> Result<Option<Device>> {
+        let matches = Self::candidates(snapshot, host, device_config);
+
+        if matches.len() > 1 {
+            warn!(
+                "Device '{}' pattern \"{}\" matches {} devices:",
+                alias,
+                device_config.name,
+                matches.len()
+            );
+            for (name, _) in &matches {
+                warn!("  {}", name);
+            }
+
+            if strict {
+                return Err(RouterError::AmbiguousDevice {
+                    device: alias.to_string(),
+                    pattern: device_config.name.clone(),
+                    matches: matches.iter().map(|(name, _)| name.clone()).collect(),
+                });
+            }
+            warn!("Using the first match; use a more specific name or an index");
+        }
+
+        if let (true, Some((name, _))) = (device_config.is_default_device(), matches.first()) {
+            debug!(
+                "Device '{}' resolves to the default device: {}",
+                alias, name
+            );
+        }
+
+        Ok(matches.first().map(|(_, device)| device.clone()))
     }
 
-    pub fn list_available(host: &Host) -> Vec<String> {
+    /// Enumerates named devices in host order. `list-devices` numbering and
+    /// index-based lookup both use this ordering.
+    fn enumerate(host: &Host) -> Vec<(String, Device)> {
         let mut devices = Vec::new();
 
-        if let Ok(available) = host.devices() {
-            for device in available {
-                if let Ok(name) = device.name() {
-                    devices.push(name);
+        match host.devices() {
+            Ok(available) => {
+                for device in available {
+                    match device.name() {
+                        Ok(name) => devices.push((name, device)),
+                        Err(e) => trace!("Skipping device without a name: {}", e),
+                    }
                 }
             }
+            Err(e) => debug!("Failed to enumerate {} devices: {}", host.id().name(), e),
         }
 
         devices
     }
+
+    /// Enumerates each host the given devices are looked up through, once.
+    fn enumerate_hosts<'a>(
+        hosts: &AudioHosts,
+        device_configs: impl Iterator<Item = &'a DeviceConfig>,
+    ) -> Snapshots {
+        let mut snapshots = Snapshots::new();
+        for device_config in device_configs {
+            let host = hosts.for_device(device_config);
+            snapshots
+                .entry(host.id())
+                .or_insert_with(|| Self::enumerate(host));
+        }
+        snapshots
+    }
+
+    /// The WASAPI endpoint ID of each device in `snapshot`, where known.
+    /// Devices are paired with endpoints by position when both lists have
+    /// the same name there, else by a name that only one endpoint has.
+    #[cfg(windows)]
+    fn endpoint_ids(snapshot: &[(String, Device)]) -> Vec<Option<String>> {
+        let endpoints = crate::endpoints::list();
+
+        snapshot
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| match endpoints.get(i) {
+                Some(endpoint) if &endpoint.name == name => Some(endpoint.id.clone()),
+                _ => {
+                    let mut named = endpoints.iter().filter(|endpoint| &endpoint.name == name);
+                    match (named.next(), named.next()) {
+                        (Some(endpoint), None) => Some(endpoint.id.clone()),
+                        _ => None,
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Endpoint IDs are only available from WASAPI.
+    #[cfg(not(windows))]
+    fn endpoint_ids(snapshot: &[(String, Device)]) -> Vec<Option<String>> {
+        vec![None; snapshot.len()]
+    }
+
+    /// Runs the same matching as `find_all` for every hardware device in the
+    /// config, without waiting or failing on the first problem.
+    pub fn match_configured(config: &Config, hosts: &AudioHosts) -> Vec<DeviceMatch> {
+        let snapshots = Self::enumerate_hosts(
+            hosts,
+            Self::hardware_devices(config).map(|(_, device_config)| device_config),
+        );
+
+        Self::hardware_devices(config)
+            .map(|(alias, device_config)| {
+                let host = hosts.for_device(device_config);
+                let snapshot = snapshots.get(&host.id()).map(Vec::as_slice);
+                let candidates =
+                    Self::candidates(snapshot.unwrap_or_default(), host, device_config)
+                        .into_iter()
+                        .map(|(name, _)| name.clone())
+                        .collect();
+
+                let (selected, problem) = match Self::find_on_host(
+                    &snapshots,
+                    hosts,
+                    device_config,
+                    alias,
+                    config.device_wait.strict,
+                ) {
+                    Ok(Some(device)) => {
+                        let problem =
+                            Self::verify_device_type(&device, &device_config.device_type, alias)
+                                .and_then(|()| Self::verify_channels(&device, config, alias))
+                                .err()
+                                .map(|e| e.to_string());
+                        (device.name().ok(), problem)
+                    }
+                    Ok(None) => (None, None),
+                    Err(e) => (None, Some(e.to_string())),
+                };
+
+                DeviceMatch {
+                    alias: alias.clone(),
+                    device_type: device_config.device_type.clone(),
+                    identifier: device_config.identifier(),
+                    candidates,
+                    selected,
+                    host: (host.id() != hosts.default_host().id())
+                        .then(|| host.id().name().to_string()),
+                    problem,
+                }
+            })
+            .collect()
+    }
+
+    /// Every device's name and, where known, its endpoint ID, in
+    /// `list-devices` order.
+    pub fn list_available(host: &Host) -> Vec<(String, Option<String>)> {
+        let snapshot = Self::enumerate(host);
+        let ids = Self::endpoint_ids(&snapshot);
+
+        snapshot
+            .into_iter()
+            .map(|(name, _)| name)
+            .zip(ids)
+            .collect()
+    }
+
+    /// Describes every device's capabilities as a JSON array, in
+    /// `list-devices` order.
+    pub fn list_available_json(host: &Host) -> serde_json::Result<String> {
+        let default_input = host.default_input_device().and_then(|d| d.name().ok());
+        let default_output = host.default_output_device().and_then(|d| d.name().ok());
+        let devices = Self::enumerate(host);
+        let ids = Self::endpoint_ids(&devices);
+
+        let infos: Vec<DeviceInfo> = devices
+            .iter()
+            .zip(ids)
+            .enumerate()
+            .map(|(i, ((name, device), id))| {
+                let input = device
+                    .supported_input_configs()
+                    .map(|ranges| ranges.collect::<Vec<_>>())
+                    .unwrap_or_default();
+                let output = device
+                    .supported_output_configs()
+                    .map(|ranges| ranges.collect::<Vec<_>>())
+                    .unwrap_or_default();
+
+                DeviceInfo {
+                    index: i + 1,
+                    name: name.clone(),
+                    id,
+                    input: capabilities(&input, default_input.as_ref() == Some(name)),
+                    output: capabilities(&output, default_output.as_ref() == Some(name)),
+                }
+            })
+            .collect();
+
+        serde_json::to_string_pretty(&infos)
+    }
+}
+
+/// One device as listed by `list-devices --json`.
+#[derive(Serialize)]
+struct DeviceInfo {
+    index: usize,
+    name: String,
+    id: Option<String>,
+    input: Option<Capabilities>,
+    output: Option<Capabilities>,
+}
+
+/// What a device supports in one direction.
+#[derive(Serialize)]
+struct Capabilities {
+    default: bool,
+    channels: BTreeSet<u16>,
+    sample_rates: Vec<SampleRateRange>,
+    formats: BTreeSet<String>,
+}
+
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+struct SampleRateRange {
+    min: u32,
+    max: u32,
+}
+
+/// One direction of a device, or `None` when the device has no configs in
+/// that direction.
+fn capabilities(ranges: &[SupportedStreamConfigRange], is_default: bool) -> Option<Capabilities> {
+    if ranges.is_empty() {
+        return None;
+    }
+
+    let sample_rates: BTreeSet<SampleRateRange> = ranges
+        .iter()
+        .map(|range| SampleRateRange {
+            min: range.min_sample_rate().0,
+            max: range.max_sample_rate().0,
+        })
+        .collect();
+
+    Some(Capabilities {
+        default: is_default,
+        channels: ranges.iter().map(|range| range.channels()).collect(),
+        sample_rates: sample_rates.into_iter().collect(),
+        formats: ranges
+            .iter()
+            .map(|range| format!("{:?}", range.sample_format()))
+            .collect(),
+    })
 }
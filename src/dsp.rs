@@ -0,0 +1,1117 @@
+use std::f32::consts::PI;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::config::{AgcConfig, CompressorConfig, EqBandConfig, FilterType, UnderrunStrategy};
+use crate::status::BlockStats;
+
+/// Frames wider than this are truncated; no supported device comes close.
+pub const MAX_FRAME_CHANNELS: usize = 64;
+
+pub struct AudioSettings {
+    pub gain: f32,
+    /// -1.0 inverts the route's polarity, 1.0 leaves it as is.
+    pub polarity: f32,
+    pub mix_ratio: f32,
+    pub channel_gains: [f32; 2],
+    pub stereo_width: f32,
+    pub sample_min: f32,
+    pub sample_max: f32,
+    /// Whether samples are limited to `sample_min`..`sample_max`.
+    pub clamp: bool,
+}
+
+/// A route gain that can be changed while the route runs; the input callback
+/// picks up the current value once per block.
+pub struct GainControl {
+    bits: AtomicU32,
+}
+
+impl GainControl {
+    pub fn new(gain: f32) -> Self {
+        GainControl {
+            bits: AtomicU32::new(gain.to_bits()),
+        }
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, gain: f32) {
+        self.bits.store(gain.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Linear ramp from silence to unity over a fixed number of frames, applied
+/// once when a stream starts.
+pub struct FadeIn {
+    position: usize,
+    length: usize,
+}
+
+impl FadeIn {
+    pub fn new(length: usize) -> Self {
+        FadeIn {
+            position: 0,
+            length,
+        }
+    }
+
+    pub fn next_gain(&mut self) -> f32 {
+        if self.position >= self.length {
+            return 1.0;
+        }
+        self.position += 1;
+        self.position as f32 / self.length as f32
+    }
+}
+
+/// Per-sample decay of the `fade` underrun strategy, reaching -60 dB after
+/// about 30 samples.
+const UNDERRUN_FADE_DECAY: f32 = 0.8;
+
+/// How long the `hold` underrun strategy repeats the last sample before
+/// fading it out, so a long dropout doesn't leave a DC offset on the output.
+const UNDERRUN_HOLD_MS: u32 = 5;
+
+/// Produces the samples played while a route's buffer is empty, remembering
+/// the last sample played on each channel.
+pub struct UnderrunFill {
+    strategy: UnderrunStrategy,
+    last: [f32; MAX_FRAME_CHANNELS],
+    /// Samples each channel has been held for since it last played.
+    held: [u32; MAX_FRAME_CHANNELS],
+    hold_limit: u32,
+}
+
+impl UnderrunFill {
+    pub fn new(strategy: UnderrunStrategy, sample_rate: u32) -> Self {
+        UnderrunFill {
+            strategy,
+            last: [0.0; MAX_FRAME_CHANNELS],
+            held: [0; MAX_FRAME_CHANNELS],
+            hold_limit: sample_rate * UNDERRUN_HOLD_MS / 1000,
+        }
+    }
+
+    pub fn played(&mut self, channel: usize, sample: f32) {
+        if let Some(last) = self.last.get_mut(channel) {
+            *last = sample;
+            self.held[channel] = 0;
+        }
+    }
+
+    pub fn missing(&mut self, channel: usize) -> f32 {
+        let Some(last) = self.last.get_mut(channel) else {
+            return 0.0;
+        };
+        match self.strategy {
+            UnderrunStrategy::Silence => 0.0,
+            UnderrunStrategy::Hold if self.held[channel] < self.hold_limit => {
+                self.held[channel] += 1;
+                *last
+            }
+            UnderrunStrategy::Hold | UnderrunStrategy::Fade => {
+                *last *= UNDERRUN_FADE_DECAY;
+                *last
+            }
+        }
+    }
+}
+
+/// Fixed delay for interleaved frames: each frame passed in comes back out
+/// `frames` frames later, starting with silence.
+pub struct DelayLine {
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl DelayLine {
+    pub fn new(frames: usize, channels: usize) -> Self {
+        DelayLine {
+            buffer: vec![0.0; frames * channels],
+            position: 0,
+        }
+    }
+
+    /// Swaps `frame` with the frame written `frames` calls ago.
+    pub fn process(&mut self, frame: &mut [f32]) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        for sample in frame {
+            std::mem::swap(sample, &mut self.buffer[self.position]);
+            self.position = (self.position + 1) % self.buffer.len();
+        }
+    }
+}
+
+/// Regroups interleaved input into whole frames when a callback ends partway
+/// through a frame: the leftover samples are held back and completed by the
+/// start of the next callback, so channels stay aligned and nothing is lost.
+pub struct FrameAligner {
+    channels: usize,
+    partial: Vec<f32>,
+    aligned: Vec<f32>,
+}
+
+impl FrameAligner {
+    pub fn new(channels: usize) -> Self {
+        let channels = channels.max(1);
+        FrameAligner {
+            channels,
+            partial: Vec::with_capacity(channels),
+            aligned: Vec::new(),
+        }
+    }
+
+    /// Returns the whole frames made up of any held-back samples followed by
+    /// `data`, holding back a trailing partial frame for the next call.
+    pub fn align<'a>(&'a mut self, data: &'a [f32]) -> &'a [f32] {
+        if self.partial.is_empty() {
+            let whole = data.len() - data.len() % self.channels;
+            self.partial.extend_from_slice(&data[whole..]);
+            return &data[..whole];
+        }
+
+        self.aligned.clear();
+        self.aligned.extend_from_slice(&self.partial);
+        self.aligned.extend_from_slice(data);
+        let whole = self.aligned.len() - self.aligned.len() % self.channels;
+        self.partial.clear();
+        self.partial.extend_from_slice(&self.aligned[whole..]);
+        self.aligned.truncate(whole);
+        &self.aligned
+    }
+}
+
+/// Sample rate converter for interleaved frames, interpolating linearly or
+/// with a windowed-sinc filter. Input can be pushed a block at a time (the
+/// input side of a route) or pulled a frame at a time (the output side).
+pub struct Resampler {
+    /// Input frames advanced per output frame.
+    step: f64,
+    /// Position of the next output frame between `previous` and `current`.
+    position: f64,
+    channels: usize,
+    previous: [f32; MAX_FRAME_CHANNELS],
+    current: [f32; MAX_FRAME_CHANNELS],
+    sinc: Option<SincFilter>,
+}
+
+impl Resampler {
+    /// `taps` selects a windowed-sinc filter of that length; `None`
+    /// interpolates linearly.
+    pub fn new(from_rate: u32, to_rate: u32, channels: usize, taps: Option<usize>) -> Self {
+        let channels = channels.clamp(1, MAX_FRAME_CHANNELS);
+        let cutoff = (to_rate as f32 / from_rate.max(1) as f32).min(1.0);
+        Resampler {
+            step: from_rate as f64 / to_rate.max(1) as f64,
+            position: 0.0,
+            channels,
+            previous: [0.0; MAX_FRAME_CHANNELS],
+            current: [0.0; MAX_FRAME_CHANNELS],
+            sinc: taps.map(|taps| SincFilter::new(taps, cutoff, channels)),
+        }
+    }
+
+    /// Names the interpolation, e.g. `linear` or `32-tap sinc`.
+    pub fn describe(&self) -> String {
+        match &self.sinc {
+            Some(sinc) => format!("{}-tap sinc", sinc.taps),
+            None => "linear".to_string(),
+        }
+    }
+
+    /// Delay the converter adds, in frames at the input rate.
+    pub fn latency_frames(&self) -> usize {
+        self.sinc.as_ref().map_or(1, |sinc| sinc.taps / 2)
+    }
+
+    /// Converts a block of input frames, appending the output frames.
+    pub fn push(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let channels = self.channels;
+        let mut frame_out = [0.0f32; MAX_FRAME_CHANNELS];
+        for frame in input.chunks_exact(channels) {
+            self.advance(|current| current.copy_from_slice(frame));
+            while self.position < 1.0 {
+                self.interpolate(self.position as f32, &mut frame_out[..channels]);
+                output.extend_from_slice(&frame_out[..channels]);
+                self.position += self.step;
+            }
+            self.position -= 1.0;
+        }
+    }
+
+    /// Produces one output frame, reading input frames as they are needed.
+    pub fn pull(&mut self, output: &mut [f32], mut read: impl FnMut(&mut [f32])) {
+        while self.position >= 1.0 {
+            self.advance(&mut read);
+            self.position -= 1.0;
+        }
+        let channels = self.channels.min(output.len());
+        self.interpolate(self.position as f32, &mut output[..channels]);
+        self.position += self.step;
+    }
+
+    fn advance(&mut self, read: impl FnOnce(&mut [f32])) {
+        let channels = self.channels;
+        self.previous[..channels].copy_from_slice(&self.current[..channels]);
+        read(&mut self.current[..channels]);
+        if let Some(sinc) = &mut self.sinc {
+            sinc.push_frame(&self.current[..channels]);
+        }
+    }
+
+    fn interpolate(&self, t: f32, output: &mut [f32]) {
+        match &self.sinc {
+            Some(sinc) => sinc.interpolate(t, output),
+            None => {
+                for (channel, sample) in output.iter_mut().enumerate() {
+                    *sample = self.previous[channel]
+                        + (self.current[channel] - self.previous[channel]) * t;
+                }
+            }
+        }
+    }
+}
+
+/// Fractional positions the sinc filter's weights are tabulated at; weights
+/// in between are interpolated.
+const SINC_PHASES: usize = 128;
+
+/// Windowed-sinc interpolator over the last `taps` input frames. The output
+/// point lies between the two middle frames, so it lags the newest input by
+/// `taps / 2` frames.
+struct SincFilter {
+    taps: usize,
+    channels: usize,
+    /// Weights for each of `SINC_PHASES + 1` fractional positions, `taps`
+    /// per position.
+    table: Vec<f32>,
+    /// The last `taps` frames, stored twice so the window is contiguous.
+    history: Vec<f32>,
+    /// Frame slot the next input frame is written to; also the oldest frame.
+    write: usize,
+}
+
+impl SincFilter {
+    fn new(taps: usize, cutoff: f32, channels: usize) -> Self {
+        let taps = taps.max(2) & !1;
+        let half = (taps / 2) as f32;
+        // Stay a little below the band edge so the short filters still
+        // attenuate at the Nyquist frequency.
+        let cutoff = cutoff * 0.95;
+
+        let mut table = Vec::with_capacity((SINC_PHASES + 1) * taps);
+        for phase in 0..=SINC_PHASES {
+            let t = phase as f32 / SINC_PHASES as f32;
+            let row: Vec<f32> = (0..taps)
+                .map(|k| {
+                    let distance = k as f32 - (half - 1.0) - t;
+                    let x = distance / half;
+                    let window = if x.abs() >= 1.0 {
+                        0.0
+                    } else {
+                        0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+                    };
+                    cutoff * sinc(cutoff * distance) * window
+                })
+                .collect();
+            // Unity gain at DC for every phase.
+            let sum: f32 = row.iter().sum();
+            table.extend(row.iter().map(|weight| weight / sum));
+        }
+
+        SincFilter {
+            taps,
+            channels,
+            table,
+            history: vec![0.0; 2 * taps * channels],
+            write: 0,
+        }
+    }
+
+    fn push_frame(&mut self, frame: &[f32]) {
+        let channels = self.channels;
+        let first = self.write * channels;
+        let second = (self.write + self.taps) * channels;
+        self.history[first..first + channels].copy_from_slice(frame);
+        self.history[second..second + channels].copy_from_slice(frame);
+        self.write = (self.write + 1) % self.taps;
+    }
+
+    fn interpolate(&self, t: f32, output: &mut [f32]) {
+        let taps = self.taps;
+        let channels = self.channels;
+        let position = t.clamp(0.0, 1.0) * SINC_PHASES as f32;
+        let phase = (position as usize).min(SINC_PHASES - 1);
+        let fraction = position - phase as f32;
+        let lower = &self.table[phase * taps..(phase + 1) * taps];
+        let upper = &self.table[(phase + 1) * taps..(phase + 2) * taps];
+        let window = &self.history[self.write * channels..(self.write + taps) * channels];
+
+        output.fill(0.0);
+        for ((frame, &low), &high) in window.chunks_exact(channels).zip(lower).zip(upper) {
+            let weight = low + (high - low) * fraction;
+            for (sample, &value) in output.iter_mut().zip(frame) {
+                *sample += value * weight;
+            }
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Automatic gain control. A peak envelope follower rises with the attack
+/// time and falls with the release time; the gain is `target / envelope`,
+/// capped at `max_gain` so silence and noise are not boosted without bound.
+pub struct AutoGain {
+    target: f32,
+    max_gain: f32,
+    attack: f32,
+    release: f32,
+    envelope: f32,
+}
+
+impl AutoGain {
+    pub fn new(config: &AgcConfig, sample_rate: f32) -> Self {
+        AutoGain {
+            target: config.target_level,
+            max_gain: config.max_gain,
+            attack: smoothing_coefficient(config.attack_ms, sample_rate),
+            release: smoothing_coefficient(config.release_ms, sample_rate),
+            // Start at unity gain rather than boosting the first frames.
+            envelope: config.target_level,
+        }
+    }
+
+    pub fn next_gain(&mut self, input: &[f32]) -> f32 {
+        let level = input.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let coefficient = if level > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope = level + coefficient * (self.envelope - level);
+
+        if self.envelope <= f32::EPSILON {
+            return self.max_gain;
+        }
+        (self.target / self.envelope).min(self.max_gain)
+    }
+}
+
+/// Feed-forward compressor for interleaved frames. A peak envelope follower
+/// shared by all channels keeps the stereo image when reducing gain.
+pub struct Compressor {
+    threshold_db: f32,
+    slope: f32,
+    makeup: f32,
+    attack: f32,
+    release: f32,
+    envelope: f32,
+}
+
+impl Compressor {
+    pub fn new(config: &CompressorConfig, sample_rate: f32) -> Self {
+        Compressor {
+            threshold_db: config.threshold_db,
+            slope: 1.0 - 1.0 / config.ratio.max(1.0),
+            makeup: 10f32.powf(config.makeup_db / 20.0),
+            attack: smoothing_coefficient(config.attack_ms, sample_rate),
+            release: smoothing_coefficient(config.release_ms, sample_rate),
+            envelope: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, frame: &mut [f32]) {
+        let level = frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let coefficient = if level > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope = level + coefficient * (self.envelope - level);
+
+        let over_db = 20.0 * self.envelope.max(f32::EPSILON).log10() - self.threshold_db;
+        let reduction = if over_db > 0.0 {
+            10f32.powf(-over_db * self.slope / 20.0)
+        } else {
+            1.0
+        };
+        let gain = reduction * self.makeup;
+        for sample in frame {
+            *sample *= gain;
+        }
+    }
+}
+
+/// One-pole smoothing coefficient reaching ~63% of a step in `time_ms`.
+fn smoothing_coefficient(time_ms: f32, sample_rate: f32) -> f32 {
+    if time_ms <= 0.0 {
+        return 0.0;
+    }
+    (-1.0 / (time_ms * 0.001 * sample_rate)).exp()
+}
+
+/// One output frame, stored inline so processing never allocates.
+pub struct Frame {
+    samples: [f32; MAX_FRAME_CHANNELS],
+    len: usize,
+}
+
+impl Frame {
+    fn new() -> Self {
+        Frame {
+            samples: [0.0; MAX_FRAME_CHANNELS],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        if self.len < MAX_FRAME_CHANNELS {
+            self.samples[self.len] = sample;
+            self.len += 1;
+        }
+    }
+}
+
+impl Deref for Frame {
+    type Target = [f32];
+
+    fn deref(&self) -> &[f32] {
+        &self.samples[..self.len]
+    }
+}
+
+/// Converts one interleaved input frame into an output frame: channel
+/// conversion, gain, filtering, stereo processing and clamping. Clipping and
+/// peak levels are accumulated into `block`.
+pub fn process_frame(
+    input: &[f32],
+    in_channels: u16,
+    out_channels: u16,
+    settings: &AudioSettings,
+    filters: &mut FilterChain,
+    block: &mut BlockStats,
+) -> Frame {
+    let gain = settings.gain * settings.polarity * filters.auto_gain(input);
+    let mut frame = Frame::new();
+
+    match (in_channels, out_channels) {
+        (1, 2) => {
+            let input_sample = filters.block_dc(0, input[0]);
+            let filtered = filters.process(0, input_sample * gain);
+            let boosted = clamp_sample(filtered, settings, block);
+            frame.push(boosted);
+            frame.push(boosted);
+        }
+        (2, 1) => {
+            let mixed = filters.block_dc(0, input[0]) + filters.block_dc(1, input[1]);
+            let filtered = filters.process(0, mixed * settings.mix_ratio * gain);
+            frame.push(clamp_sample(filtered, settings, block));
+        }
+        (2, 2) => {
+            let left = filters.block_dc(0, input[0]) * gain * settings.channel_gains[0];
+            let right = filters.block_dc(1, input[1]) * gain * settings.channel_gains[1];
+            let left = filters.process(0, left);
+            let right = filters.process(1, right);
+            let (left, right) = apply_stereo_width(left, right, settings.stereo_width);
+            frame.push(clamp_sample(left, settings, block));
+            frame.push(clamp_sample(right, settings, block));
+        }
+        _ => {
+            for (channel, &sample) in input.iter().enumerate() {
+                let sample = filters.block_dc(channel, sample);
+                let filtered = filters.process(channel, sample * gain);
+                frame.push(clamp_sample(filtered, settings, block));
+            }
+        }
+    }
+
+    frame
+}
+
+/// Scales the side (L-R) component of a stereo pair: 0.0 collapses to mono,
+/// 1.0 leaves the signal unchanged and values above 1.0 widen it.
+fn apply_stereo_width(left: f32, right: f32, width: f32) -> (f32, f32) {
+    if width == 1.0 {
+        return (left, right);
+    }
+
+    let mid = (left + right) * 0.5;
+    let side = (left - right) * 0.5 * width;
+    (mid + side, mid - side)
+}
+
+fn clamp_sample(value: f32, settings: &AudioSettings, block: &mut BlockStats) -> f32 {
+    if !settings.clamp {
+        block.observe(value);
+        return value;
+    }
+    if value < settings.sample_min || value > settings.sample_max {
+        block.clipped += 1;
+    }
+    let clamped = value.clamp(settings.sample_min, settings.sample_max);
+    block.observe(clamped);
+    clamped
+}
+
+/// A single biquad stage using the RBJ audio EQ cookbook formulas, run in
+/// transposed direct form II.
+#[derive(Debug, Clone)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    pub fn new(band: &EqBandConfig, sample_rate: f32) -> Self {
+        let w0 = 2.0 * PI * band.freq / sample_rate;
+        let (sin_w0, cos_w0) = w0.sin_cos();
+        let alpha = sin_w0 / (2.0 * band.q);
+        let a = 10f32.powf(band.gain_db / 40.0);
+
+        let (b0, b1, b2, a0, a1, a2) = match band.filter_type {
+            FilterType::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos_w0,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos_w0,
+                1.0 - alpha / a,
+            ),
+            FilterType::Lowshelf => {
+                let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos_w0 + sqrt_a_alpha),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w0),
+                    a * ((a + 1.0) - (a - 1.0) * cos_w0 - sqrt_a_alpha),
+                    (a + 1.0) + (a - 1.0) * cos_w0 + sqrt_a_alpha,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos_w0),
+                    (a + 1.0) + (a - 1.0) * cos_w0 - sqrt_a_alpha,
+                )
+            }
+            FilterType::Highshelf => {
+                let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos_w0 + sqrt_a_alpha),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0),
+                    a * ((a + 1.0) + (a - 1.0) * cos_w0 - sqrt_a_alpha),
+                    (a + 1.0) - (a - 1.0) * cos_w0 + sqrt_a_alpha,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos_w0),
+                    (a + 1.0) - (a - 1.0) * cos_w0 - sqrt_a_alpha,
+                )
+            }
+            FilterType::Lowpass => (
+                (1.0 - cos_w0) / 2.0,
+                1.0 - cos_w0,
+                (1.0 - cos_w0) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos_w0,
+                1.0 - alpha,
+            ),
+            FilterType::Highpass => (
+                (1.0 + cos_w0) / 2.0,
+                -(1.0 + cos_w0),
+                (1.0 + cos_w0) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos_w0,
+                1.0 - alpha,
+            ),
+        };
+
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// One-pole high-pass filter for removing DC offset and low-frequency rumble.
+#[derive(Debug, Clone)]
+pub struct OnePoleHighPass {
+    coefficient: f32,
+    prev_input: f32,
+    prev_output: f32,
+}
+
+impl OnePoleHighPass {
+    pub fn new(cutoff_hz: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff_hz);
+        let dt = 1.0 / sample_rate;
+
+        OnePoleHighPass {
+            coefficient: rc / (rc + dt),
+            prev_input: 0.0,
+            prev_output: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = self.coefficient * (self.prev_output + x - self.prev_input);
+        self.prev_input = x;
+        self.prev_output = y;
+        y
+    }
+}
+
+/// First-order DC blocker: a zero at DC and a pole just inside the unit circle.
+/// A constant offset decays by `POLE` per sample, to under 1% within 920
+/// samples (about 19 ms at 48 kHz); the cutoff is about 38 Hz at 48 kHz.
+#[derive(Debug, Clone)]
+pub struct DcBlocker {
+    prev_input: f32,
+    prev_output: f32,
+}
+
+impl DcBlocker {
+    const POLE: f32 = 0.995;
+
+    pub fn new() -> Self {
+        DcBlocker {
+            prev_input: 0.0,
+            prev_output: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = x - self.prev_input + Self::POLE * self.prev_output;
+        self.prev_input = x;
+        self.prev_output = y;
+        y
+    }
+}
+
+/// Per-channel filter state for a route: an optional DC blocker applied to
+/// the raw input, then an optional high-pass and cascaded EQ biquads.
+pub struct FilterChain {
+    agc: Option<AutoGain>,
+    dc_blockers: Vec<DcBlocker>,
+    highpass: Vec<OnePoleHighPass>,
+    channels: Vec<Vec<Biquad>>,
+}
+
+impl FilterChain {
+    pub fn new(
+        dc_block: bool,
+        highpass_hz: Option<f32>,
+        bands: &[EqBandConfig],
+        sample_rate: f32,
+        channels: usize,
+    ) -> Self {
+        let dc_blockers = if dc_block {
+            vec![DcBlocker::new(); channels]
+        } else {
+            Vec::new()
+        };
+        let highpass = match highpass_hz {
+            Some(cutoff_hz) => vec![OnePoleHighPass::new(cutoff_hz, sample_rate); channels],
+            None => Vec::new(),
+        };
+        let stages: Vec<Biquad> = bands
+            .iter()
+            .map(|band| Biquad::new(band, sample_rate))
+            .collect();
+
+        FilterChain {
+            agc: None,
+            dc_blockers,
+            highpass,
+            channels: vec![stages; channels],
+        }
+    }
+
+    pub fn with_agc(mut self, agc: Option<&AgcConfig>, sample_rate: f32) -> Self {
+        self.agc = agc.map(|agc| AutoGain::new(agc, sample_rate));
+        self
+    }
+
+    /// The AGC gain for one input frame, or unity without AGC.
+    pub fn auto_gain(&mut self, input: &[f32]) -> f32 {
+        match self.agc.as_mut() {
+            Some(agc) => agc.next_gain(input),
+            None => 1.0,
+        }
+    }
+
+    pub fn block_dc(&mut self, channel: usize, sample: f32) -> f32 {
+        match self.dc_blockers.get_mut(channel) {
+            Some(blocker) => blocker.process(sample),
+            None => sample,
+        }
+    }
+
+    pub fn process(&mut self, channel: usize, sample: f32) -> f32 {
+        let sample = match self.highpass.get_mut(channel) {
+            Some(filter) => filter.process(sample),
+            None => sample,
+        };
+
+        match self.channels.get_mut(channel) {
+            Some(stages) => stages
+                .iter_mut()
+                .fold(sample, |value, stage| stage.process(value)),
+            None => sample,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    /// Deterministic white noise in -1.0..1.0.
+    fn white_noise(len: usize) -> Vec<f32> {
+        let mut state = 0x1234_5678u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> 8) as f32 / (1u32 << 23) as f32 - 1.0
+            })
+            .collect()
+    }
+
+    /// Power of `signal` in DFT bins `bins`, summed.
+    fn band_power(signal: &[f32], bins: std::ops::RangeInclusive<usize>) -> f64 {
+        let len = signal.len() as f64;
+        bins.map(|bin| {
+            let (mut re, mut im) = (0.0f64, 0.0f64);
+            for (n, &sample) in signal.iter().enumerate() {
+                let angle = 2.0 * std::f64::consts::PI * bin as f64 * n as f64 / len;
+                re += sample as f64 * angle.cos();
+                im -= sample as f64 * angle.sin();
+            }
+            re * re + im * im
+        })
+        .sum()
+    }
+
+    fn gain_db_at(input: &[f32], output: &[f32], freq: f32) -> f64 {
+        // One bin per Hz when the signal is one second long.
+        let bin = (freq * input.len() as f32 / SAMPLE_RATE).round() as usize;
+        let bins = bin - 3..=bin + 3;
+        10.0 * (band_power(output, bins.clone()) / band_power(input, bins)).log10()
+    }
+
+    #[test]
+    fn peak_filter_boosts_white_noise_at_its_center_frequency() {
+        let band = EqBandConfig {
+            filter_type: FilterType::Peak,
+            freq: 1000.0,
+            q: 1.0,
+            gain_db: 6.0,
+        };
+        let mut filter = Biquad::new(&band, SAMPLE_RATE);
+        let input = white_noise(SAMPLE_RATE as usize);
+        let output: Vec<f32> = input.iter().map(|&x| filter.process(x)).collect();
+
+        let center = gain_db_at(&input, &output, 1000.0);
+        assert!((center - 6.0).abs() < 0.5, "gain at 1 kHz: {} dB", center);
+        let far = gain_db_at(&input, &output, 12000.0);
+        assert!(far.abs() < 0.5, "gain at 12 kHz: {} dB", far);
+    }
+
+    fn settings() -> AudioSettings {
+        AudioSettings {
+            gain: 1.0,
+            polarity: 1.0,
+            mix_ratio: 0.5,
+            channel_gains: [1.0, 1.0],
+            stereo_width: 1.0,
+            sample_min: -1.0,
+            sample_max: 1.0,
+            clamp: true,
+        }
+    }
+
+    fn process(
+        input: &[f32],
+        in_channels: u16,
+        out_channels: u16,
+        settings: &AudioSettings,
+    ) -> (Vec<f32>, BlockStats) {
+        let mut filters = FilterChain::new(false, None, &[], SAMPLE_RATE, in_channels as usize);
+        let mut block = BlockStats::default();
+        let frame = process_frame(
+            input,
+            in_channels,
+            out_channels,
+            settings,
+            &mut filters,
+            &mut block,
+        );
+        (frame.to_vec(), block)
+    }
+
+    #[test]
+    fn mono_is_copied_to_both_stereo_channels() {
+        let (frame, _) = process(&[0.25], 1, 2, &settings());
+        assert_eq!(frame, [0.25, 0.25]);
+    }
+
+    #[test]
+    fn stereo_is_mixed_to_mono_with_the_mix_ratio() {
+        let (frame, _) = process(&[0.5, 0.25], 2, 1, &settings());
+        assert_eq!(frame, [0.375]);
+
+        let settings = AudioSettings {
+            mix_ratio: 1.0,
+            ..settings()
+        };
+        let (frame, _) = process(&[0.5, 0.25], 2, 1, &settings);
+        assert_eq!(frame, [0.75]);
+    }
+
+    #[test]
+    fn matching_channel_counts_pass_through_unchanged() {
+        let (frame, block) = process(&[0.1, -0.2], 2, 2, &settings());
+        assert_eq!(frame, [0.1, -0.2]);
+        assert_eq!(block.clipped, 0);
+
+        let (frame, _) = process(&[0.1, -0.2, 0.3, -0.4], 4, 4, &settings());
+        assert_eq!(frame, [0.1, -0.2, 0.3, -0.4]);
+    }
+
+    #[test]
+    fn gain_polarity_and_channel_gains_scale_samples() {
+        let settings = AudioSettings {
+            gain: 2.0,
+            polarity: -1.0,
+            channel_gains: [1.0, 0.5],
+            ..settings()
+        };
+        let (frame, _) = process(&[0.25, 0.25], 2, 2, &settings);
+        assert_eq!(frame, [-0.5, -0.25]);
+
+        let (frame, _) = process(
+            &[0.1],
+            1,
+            2,
+            &AudioSettings {
+                gain: 0.0,
+                ..settings
+            },
+        );
+        assert_eq!(frame, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn samples_outside_the_bounds_are_clamped_and_counted() {
+        let settings = AudioSettings {
+            gain: 4.0,
+            ..settings()
+        };
+        let (frame, block) = process(&[0.5, -0.5], 2, 2, &settings);
+        assert_eq!(frame, [1.0, -1.0]);
+        assert_eq!(block.clipped, 2);
+        assert_eq!(block.peak, 1.0);
+    }
+
+    #[test]
+    fn samples_exactly_at_the_bounds_are_not_clipping() {
+        let (frame, block) = process(&[1.0, -1.0], 2, 2, &settings());
+        assert_eq!(frame, [1.0, -1.0]);
+        assert_eq!(block.clipped, 0);
+    }
+
+    #[test]
+    fn custom_bounds_are_applied_per_sample() {
+        let settings = AudioSettings {
+            sample_min: -0.5,
+            sample_max: 0.25,
+            ..settings()
+        };
+        let (frame, block) = process(&[0.3, -0.6, 0.2], 3, 3, &settings);
+        assert_eq!(frame, [0.25, -0.5, 0.2]);
+        assert_eq!(block.clipped, 2);
+    }
+
+    #[test]
+    fn unclamped_routes_keep_overs_and_count_no_clipping() {
+        let settings = AudioSettings {
+            gain: 3.0,
+            clamp: false,
+            ..settings()
+        };
+        let (frame, block) = process(&[0.5], 1, 2, &settings);
+        assert_eq!(frame, [1.5, 1.5]);
+        assert_eq!(block.clipped, 0);
+        assert_eq!(block.peak, 1.5);
+    }
+
+    /// Output peak of a mono tone whose level steps through `levels`, each
+    /// held for `secs`, measured over the last 100 ms of every step.
+    fn agc_settled_peaks(agc: &AgcConfig, levels: &[f32], secs: f32) -> Vec<f32> {
+        let mut filters =
+            FilterChain::new(false, None, &[], SAMPLE_RATE, 1).with_agc(Some(agc), SAMPLE_RATE);
+        let mut block = BlockStats::default();
+        let step_len = (secs * SAMPLE_RATE) as usize;
+        let window = (0.1 * SAMPLE_RATE) as usize;
+        let mut n = 0usize;
+
+        levels
+            .iter()
+            .map(|&level| {
+                let mut peak = 0.0f32;
+                for i in 0..step_len {
+                    let input = level * (2.0 * PI * 1000.0 * n as f32 / SAMPLE_RATE).sin();
+                    n += 1;
+                    let frame =
+                        process_frame(&[input], 1, 1, &settings(), &mut filters, &mut block);
+                    if i >= step_len - window {
+                        peak = peak.max(frame[0].abs());
+                    }
+                }
+                peak
+            })
+            .collect()
+    }
+
+    #[test]
+    fn agc_settles_near_the_target_as_the_level_changes() {
+        let agc = AgcConfig {
+            target_level: 0.25,
+            attack_ms: 10.0,
+            release_ms: 500.0,
+            max_gain: 10.0,
+        };
+
+        // Quiet, loud, then moderate: each settles within 10% of the target
+        // once the release (3 s = 6 time constants) has run its course. The
+        // envelope sags a little between the tone's peaks, so the output
+        // peak sits slightly above the target.
+        let peaks = agc_settled_peaks(&agc, &[0.05, 0.8, 0.1], 3.0);
+        for (level, peak) in [0.05, 0.8, 0.1].iter().zip(&peaks) {
+            assert!(
+                (peak - 0.25).abs() < 0.25 * 0.1,
+                "input {} settled at {}",
+                level,
+                peak
+            );
+        }
+    }
+
+    #[test]
+    fn agc_never_boosts_beyond_max_gain() {
+        let agc = AgcConfig {
+            target_level: 0.25,
+            attack_ms: 10.0,
+            release_ms: 50.0,
+            max_gain: 4.0,
+        };
+
+        let peaks = agc_settled_peaks(&agc, &[0.01], 1.0);
+        assert!((peaks[0] - 0.04).abs() < 0.001, "settled at {}", peaks[0]);
+    }
+
+    #[test]
+    fn dc_blocker_removes_a_constant_offset() {
+        let offset = 0.5;
+        let mut blocker = DcBlocker::new();
+        let output: Vec<f32> = (0..2000).map(|_| blocker.process(offset)).collect();
+
+        // The offset decays geometrically by POLE per sample.
+        for (n, &sample) in output.iter().enumerate() {
+            let bound = offset * DcBlocker::POLE.powi(n as i32) + 1e-6;
+            assert!(sample.abs() <= bound, "sample {}: {}", n, sample);
+        }
+        assert!(output[920].abs() < offset * 0.01);
+        let late_mean = output[1000..].iter().sum::<f32>() / 1000.0;
+        assert!(late_mean.abs() < offset * 0.005, "mean {}", late_mean);
+    }
+
+    #[test]
+    fn dc_blocker_keeps_audio_on_top_of_the_offset() {
+        let mut blocker = DcBlocker::new();
+        let tone = |n: usize| 0.25 * (2.0 * PI * 1000.0 * n as f32 / SAMPLE_RATE).sin();
+        let output: Vec<f32> = (0..4800).map(|n| blocker.process(0.3 + tone(n))).collect();
+
+        // After settling, only the tone is left: zero mean, same peak.
+        let settled = &output[2400..];
+        let mean = settled.iter().sum::<f32>() / settled.len() as f32;
+        let peak = settled.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(mean.abs() < 0.005, "mean {}", mean);
+        assert!((peak - 0.25).abs() < 0.01, "peak {}", peak);
+    }
+
+    #[test]
+    fn hold_repeats_the_last_sample_then_fades_out() {
+        let mut fill = UnderrunFill::new(UnderrunStrategy::Hold, 48000);
+        fill.played(0, 0.5);
+
+        // 5 ms at 48 kHz is held unchanged...
+        let held: Vec<f32> = (0..240).map(|_| fill.missing(0)).collect();
+        assert!(held.iter().all(|&s| s == 0.5));
+
+        // ...then the sample decays like `fade`, well below -60 dB soon after.
+        let faded: Vec<f32> = (0..40).map(|_| fill.missing(0)).collect();
+        assert!(faded[0] < 0.5);
+        assert!(faded.windows(2).all(|w| w[1] < w[0]));
+        assert!(faded[39] < 0.5 * 0.001, "last {}", faded[39]);
+    }
+
+    #[test]
+    fn playing_again_restarts_the_hold() {
+        let mut fill = UnderrunFill::new(UnderrunStrategy::Hold, 48000);
+        fill.played(0, 0.5);
+        for _ in 0..1000 {
+            fill.missing(0);
+        }
+
+        fill.played(0, -0.25);
+        assert_eq!(fill.missing(0), -0.25);
+        assert_eq!(fill.missing(1), 0.0);
+    }
+
+    #[test]
+    fn frame_aligner_keeps_channels_aligned_across_odd_chunks() {
+        // Each sample encodes its frame and channel: frame * 10 + channel.
+        let channels = 3;
+        let samples: Vec<f32> = (0..60)
+            .map(|i| (i / channels * 10 + i % channels) as f32)
+            .collect();
+        let mut aligner = FrameAligner::new(channels);
+        let mut output = Vec::new();
+        let mut chunks = [3, 1, 5, 7, 2, 4, 1].iter().cycle();
+        let mut rest = samples.as_slice();
+        while !rest.is_empty() {
+            let (chunk, tail) = rest.split_at((*chunks.next().unwrap()).min(rest.len()));
+            let aligned = aligner.align(chunk);
+            assert_eq!(aligned.len() % channels, 0, "partial frame passed through");
+            output.extend_from_slice(aligned);
+            rest = tail;
+        }
+
+        assert_eq!(output, samples);
+    }
+}
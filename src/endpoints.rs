@@ -0,0 +1,111 @@
+use std::ptr;
+use std::slice;
+use winapi::shared::winerror::{FAILED, SUCCEEDED};
+use winapi::um::combaseapi::{
+    CoCreateInstance, CoInitializeEx, CoTaskMemFree, CoUninitialize, PropVariantClear, CLSCTX_ALL,
+};
+use winapi::um::coml2api::STGM_READ;
+use winapi::um::functiondiscoverykeys_devpkey::PKEY_Device_FriendlyName;
+use winapi::um::mmdeviceapi::{
+    eAll, CLSID_MMDeviceEnumerator, IMMDevice, IMMDeviceCollection, IMMDeviceEnumerator,
+    DEVICE_STATE_ACTIVE,
+};
+use winapi::um::objbase::COINIT_MULTITHREADED;
+use winapi::um::propidl::PROPVARIANT;
+use winapi::um::propsys::IPropertyStore;
+use winapi::um::winnt::LPWSTR;
+use winapi::Interface;
+
+/// One active WASAPI endpoint: its stable ID and the friendly name cpal
+/// reports for it.
+pub struct Endpoint {
+    pub id: String,
+    pub name: String,
+}
+
+/// Lists active render and capture endpoints in the order WASAPI enumerates
+/// them, which is the order cpal's WASAPI host lists devices in. Returns an
+/// empty list if the endpoints cannot be enumerated.
+pub fn list() -> Vec<Endpoint> {
+    let initialized = SUCCEEDED(unsafe { CoInitializeEx(ptr::null_mut(), COINIT_MULTITHREADED) });
+    let endpoints = unsafe { enumerate() }.unwrap_or_default();
+    if initialized {
+        unsafe { CoUninitialize() };
+    }
+    endpoints
+}
+
+unsafe fn enumerate() -> Option<Vec<Endpoint>> {
+    let mut enumerator: *mut IMMDeviceEnumerator = ptr::null_mut();
+    let hr = CoCreateInstance(
+        &CLSID_MMDeviceEnumerator,
+        ptr::null_mut(),
+        CLSCTX_ALL,
+        &IMMDeviceEnumerator::uuidof(),
+        &mut enumerator as *mut _ as *mut _,
+    );
+    if FAILED(hr) {
+        return None;
+    }
+
+    let mut collection: *mut IMMDeviceCollection = ptr::null_mut();
+    let hr = (*enumerator).EnumAudioEndpoints(eAll, DEVICE_STATE_ACTIVE, &mut collection);
+    (*enumerator).Release();
+    if FAILED(hr) {
+        return None;
+    }
+
+    let mut count = 0;
+    let mut endpoints = Vec::new();
+    // winapi declares the out parameter `*const UINT`.
+    if SUCCEEDED((*collection).GetCount(ptr::addr_of_mut!(count))) {
+        for i in 0..count {
+            let mut device: *mut IMMDevice = ptr::null_mut();
+            if FAILED((*collection).Item(i, &mut device)) {
+                continue;
+            }
+            if let (Some(id), Some(name)) = (device_id(device), friendly_name(device)) {
+                endpoints.push(Endpoint { id, name });
+            }
+            (*device).Release();
+        }
+    }
+    (*collection).Release();
+
+    Some(endpoints)
+}
+
+unsafe fn device_id(device: *mut IMMDevice) -> Option<String> {
+    let mut id: LPWSTR = ptr::null_mut();
+    if FAILED((*device).GetId(&mut id)) || id.is_null() {
+        return None;
+    }
+    let value = from_wide(id);
+    CoTaskMemFree(id as *mut _);
+    Some(value)
+}
+
+unsafe fn friendly_name(device: *mut IMMDevice) -> Option<String> {
+    let mut store: *mut IPropertyStore = ptr::null_mut();
+    if FAILED((*device).OpenPropertyStore(STGM_READ, &mut store)) {
+        return None;
+    }
+
+    let mut value: PROPVARIANT = std::mem::zeroed();
+    let name = if SUCCEEDED((*store).GetValue(&PKEY_Device_FriendlyName, &mut value)) {
+        let text = *value.data.pwszVal();
+        let name = (!text.is_null()).then(|| from_wide(text));
+        PropVariantClear(&mut value);
+        name
+    } else {
+        None
+    };
+    (*store).Release();
+
+    name
+}
+
+unsafe fn from_wide(text: LPWSTR) -> String {
+    let len = (0..).take_while(|&i| *text.add(i) != 0).count();
+    String::from_utf16_lossy(slice::from_raw_parts(text, len))
+}
@@ -0,0 +1,228 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub type Result<T, E = RouterError> = std::result::Result<T, E>;
+
+type Source = Box<dyn Error + Send + Sync>;
+
+/// Ways the routing engine can fail, so applications embedding the router
+/// can react to specific failures instead of parsing messages.
+#[derive(Debug)]
+pub enum RouterError {
+    /// The routing section is empty and `allow_empty_routing` is not set.
+    NoRoutesConfigured,
+    /// More routes are configured than `audio.max_routes` allows.
+    TooManyRoutes { configured: usize, max: usize },
+    /// No route of this name is configured.
+    RouteNotFound(String),
+    /// A route or command refers to a device alias that is not configured.
+    DeviceNotConfigured(String),
+    /// The config file does not exist.
+    ConfigNotFound(PathBuf),
+    /// The config is not valid YAML or does not have the expected layout;
+    /// `path` is the file, when it came from one.
+    ConfigParse {
+        path: Option<PathBuf>,
+        source: serde_yaml::Error,
+    },
+    /// The config parsed, but its settings are invalid or inconsistent.
+    InvalidConfig(String),
+    /// Reading or writing a file failed; `action` names the file.
+    Io { action: String, source: io::Error },
+    /// A device names an audio host this machine does not have.
+    UnknownHost {
+        name: String,
+        available: Vec<String>,
+    },
+    /// An available audio host failed to open.
+    HostUnavailable { host: String, source: Source },
+    /// A configured device is not present; `identifier` is how it is
+    /// looked up.
+    DeviceNotFound { device: String, identifier: String },
+    /// Several configured devices are not present, after waiting for them
+    /// for `waited` when `device_wait` is enabled.
+    DevicesNotFound {
+        devices: Vec<String>,
+        waited: Option<Duration>,
+    },
+    /// A device's name pattern matches several devices and
+    /// `device_wait.strict` is set.
+    AmbiguousDevice {
+        device: String,
+        pattern: String,
+        matches: Vec<String>,
+    },
+    /// A device cannot play the role a route gives it.
+    WrongDeviceType { device: String, reason: String },
+    /// A route's settings do not fit its devices.
+    InvalidRoute { route: String, reason: String },
+    /// Routes form a feedback loop; holds the routes along the loop.
+    RoutingCycle(String),
+    /// No usable stream configuration could be chosen for a device.
+    StreamConfig {
+        device: String,
+        reason: String,
+        source: Option<Source>,
+    },
+    /// Opening a device stream failed; `details` lists what the device
+    /// supports.
+    StreamBuild { details: String, source: Source },
+    /// Starting a stream, source or sink failed.
+    StreamStart { device: String, source: Source },
+    /// A UDP source or sink could not be set up.
+    Network { device: String, source: Source },
+    /// Routes were configured but none of them could be set up.
+    NoRoutesStarted,
+    /// Some routes could not be set up and the router was asked to require
+    /// all of them.
+    RoutesNotStarted { started: usize, configured: usize },
+    /// `selftest` found no output device to test.
+    NoOutputDevices,
+    /// Another router process holds the instance lock.
+    AlreadyRunning { pid: u32, path: PathBuf },
+    /// A WAV file could not be read.
+    InvalidWav { path: PathBuf, reason: String },
+    /// The status file is missing, so no router is running.
+    StatusNotFound(PathBuf),
+    /// The logger could not be installed.
+    Logger(Source),
+}
+
+impl fmt::Display for RouterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouterError::NoRoutesConfigured => write!(
+                f,
+                "No routes configured: the routing section is empty or missing (set allow_empty_routing: true to run without routes)"
+            )?,
+            RouterError::TooManyRoutes { configured, max } => write!(
+                f,
+                "{} routes configured, but audio.max_routes is {}",
+                configured, max
+            )?,
+            RouterError::RouteNotFound(route) => {
+                write!(f, "Route '{}' not found in config", route)?
+            }
+            RouterError::DeviceNotConfigured(device) => {
+                write!(f, "Device '{}' not found in config", device)?
+            }
+            RouterError::ConfigNotFound(path) => write!(
+                f,
+                "Config file not found at: {}. Please create a config.yaml file next to the executable.",
+                path.display()
+            )?,
+            RouterError::ConfigParse { path: Some(path), .. } => {
+                write!(f, "Failed to parse config YAML in: {}", path.display())?
+            }
+            RouterError::ConfigParse { path: None, .. } => {
+                write!(f, "Failed to parse config YAML")?
+            }
+            RouterError::InvalidConfig(reason) => write!(f, "{}", reason)?,
+            RouterError::Io { action, .. } => write!(f, "{}", action)?,
+            RouterError::UnknownHost { name, available } => write!(
+                f,
+                "Audio host '{}' is not available (available: {})",
+                name,
+                available.join(", ")
+            )?,
+            RouterError::HostUnavailable { host, .. } => {
+                write!(f, "Failed to open the {} audio host", host)?
+            }
+            RouterError::DeviceNotFound { device, identifier } => {
+                write!(f, "Device '{}' not found: {}", device, identifier)?
+            }
+            RouterError::DevicesNotFound { devices, waited } => {
+                write!(f, "Devices not found")?;
+                if let Some(waited) = waited {
+                    write!(f, " after {}s timeout", waited.as_secs())?;
+                }
+                write!(f, ": {}", devices.join(", "))?
+            }
+            RouterError::AmbiguousDevice {
+                device,
+                pattern,
+                matches,
+            } => write!(
+                f,
+                "Device '{}' pattern \"{}\" is ambiguous, matching {} devices (device_wait.strict=true)",
+                device,
+                pattern,
+                matches.len()
+            )?,
+            RouterError::WrongDeviceType { reason, .. } => write!(f, "{}", reason)?,
+            RouterError::InvalidRoute { route, reason } => {
+                write!(f, "Route '{}' {}", route, reason)?
+            }
+            RouterError::RoutingCycle(routes) => {
+                write!(f, "Routes form a feedback loop: {}", routes)?
+            }
+            RouterError::StreamConfig { device, reason, .. } => {
+                write!(f, "Device '{}': {}", device, reason)?
+            }
+            RouterError::StreamBuild { details, .. } => write!(f, "{}", details)?,
+            RouterError::StreamStart { device, .. } => {
+                write!(f, "Failed to start stream for '{}'", device)?
+            }
+            RouterError::Network { device, .. } => {
+                write!(f, "Failed to set up network device '{}'", device)?
+            }
+            RouterError::NoRoutesStarted => write!(f, "No routes could be set up")?,
+            RouterError::RoutesNotStarted {
+                started,
+                configured,
+            } => write!(
+                f,
+                "Only {} of {} routes could be started",
+                started, configured
+            )?,
+            RouterError::NoOutputDevices => write!(f, "No output devices configured")?,
+            RouterError::AlreadyRunning { pid, path } => write!(
+                f,
+                "Audio Router is already running (process {}); stop it first, or delete {} if that process is not Audio Router",
+                pid,
+                path.display()
+            )?,
+            RouterError::InvalidWav { path, reason } => {
+                write!(f, "Invalid WAV file: {}: {}", path.display(), reason)?
+            }
+            RouterError::StatusNotFound(path) => write!(
+                f,
+                "Status file not found at: {}. Is Audio Router running?",
+                path.display()
+            )?,
+            RouterError::Logger(_) => write!(f, "Failed to initialize logger")?,
+        }
+
+        // Like anyhow, `{:#}` appends the chain of causes.
+        if f.alternate() {
+            let mut source = self.source();
+            while let Some(cause) = source {
+                write!(f, ": {}", cause)?;
+                source = cause.source();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for RouterError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RouterError::ConfigParse { source, .. } => Some(source),
+            RouterError::Io { source, .. } => Some(source),
+            RouterError::HostUnavailable { source, .. }
+            | RouterError::Logger(source)
+            | RouterError::StreamBuild { source, .. }
+            | RouterError::StreamStart { source, .. }
+            | RouterError::Network { source, .. }
+            | RouterError::StreamConfig {
+                source: Some(source),
+                ..
+            } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use log::Level;
+use std::ffi::OsStr;
+use std::iter;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr;
+use winapi::um::winbase::{DeregisterEventSource, RegisterEventSourceW, ReportEventW};
+use winapi::um::winnt::{EVENTLOG_ERROR_TYPE, EVENTLOG_WARNING_TYPE, HANDLE};
+
+const EVENT_SOURCE: &str = "AudioRouter";
+
+/// Reports warnings and errors to the Windows Event Log (Application log),
+/// where administrators look for service failures.
+pub struct EventLog {
+    handle: HANDLE,
+}
+
+// The event source handle may be used from any thread.
+unsafe impl Send for EventLog {}
+unsafe impl Sync for EventLog {}
+
+impl EventLog {
+    pub fn open() -> Option<Self> {
+        let source = wide(EVENT_SOURCE);
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), source.as_ptr()) };
+        (!handle.is_null()).then_some(EventLog { handle })
+    }
+
+    /// Writes `message` as an error or warning event; other levels are
+    /// ignored.
+    pub fn report(&self, level: Level, message: &str) {
+        let event_type = match level {
+            Level::Error => EVENTLOG_ERROR_TYPE,
+            Level::Warn => EVENTLOG_WARNING_TYPE,
+            _ => return,
+        };
+        let message = wide(message);
+        let mut strings = [message.as_ptr()];
+        unsafe {
+            ReportEventW(
+                self.handle,
+                event_type,
+                0,
+                0,
+                ptr::null_mut(),
+                1,
+                0,
+                strings.as_mut_ptr(),
+                ptr::null_mut(),
+            );
+        }
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        unsafe {
+            DeregisterEventSource(self.handle);
+        }
+    }
+}
+
+fn wide(text: &str) -> Vec<u16> {
+    OsStr::new(text)
+        .encode_wide()
+        .chain(iter::once(0))
+        .collect()
+}
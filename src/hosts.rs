@@ -0,0 +1,93 @@
+use cpal::{Host, HostId};
+use std::iter;
+
+use crate::config::{Config, DeviceConfig};
+use crate::error::{Result, RouterError};
+
+/// The cpal hosts devices are looked up through: the platform default, plus
+/// every host a device names with `host`. They are open at the same time,
+/// so a route can capture from an ASIO input and play to a WASAPI output.
+pub struct AudioHosts {
+    default: Host,
+    others: Vec<Host>,
+}
+
+impl AudioHosts {
+    /// Opens the default host and the hosts the config's devices name.
+    /// Fails if a named host is not available on this machine.
+    pub fn open(config: &Config) -> Result<Self> {
+        let default = cpal::default_host();
+        let mut others: Vec<Host> = Vec::new();
+
+        for device_config in config.devices.values() {
+            let Some(name) = &device_config.host else {
+                continue;
+            };
+            let id = host_id(name)?;
+            if id == default.id() || others.iter().any(|host| host.id() == id) {
+                continue;
+            }
+            others.push(open_id(id)?);
+        }
+
+        Ok(AudioHosts { default, others })
+    }
+
+    pub fn default_host(&self) -> &Host {
+        &self.default
+    }
+
+    /// The host `device_config` is looked up through.
+    pub fn for_device(&self, device_config: &DeviceConfig) -> &Host {
+        device_config
+            .host
+            .as_deref()
+            .and_then(|name| host_id(name).ok())
+            .and_then(|id| self.all().find(|host| host.id() == id))
+            .unwrap_or(&self.default)
+    }
+
+    /// Every open host, the default first.
+    pub fn all(&self) -> impl Iterator<Item = &Host> {
+        iter::once(&self.default).chain(&self.others)
+    }
+}
+
+/// Opens the available host called `name`, ignoring case.
+pub fn open_host(name: &str) -> Result<Host> {
+    open_id(host_id(name)?)
+}
+
+fn open_id(id: HostId) -> Result<Host> {
+    cpal::host_from_id(id).map_err(|e| RouterError::HostUnavailable {
+        host: id.name().to_string(),
+        source: e.into(),
+    })
+}
+
+/// The available host called `name`, ignoring case.
+fn host_id(name: &str) -> Result<HostId> {
+    let available = cpal::available_hosts();
+    available
+        .iter()
+        .copied()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| RouterError::UnknownHost {
+            name: name.to_string(),
+            available: available.iter().map(|id| id.name().to_string()).collect(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_hosts_list_the_available_ones() {
+        let Err(RouterError::UnknownHost { name, available }) = open_host("no-such-host") else {
+            panic!("expected UnknownHost");
+        };
+        assert_eq!(name, "no-such-host");
+        assert!(!available.is_empty());
+    }
+}
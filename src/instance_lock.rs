@@ -0,0 +1,349 @@
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::error::RouterError;
+
+const LOCK_FILE_NAME: &str = "audio_router.lock";
+/// How many times to try the lock while other instances replace a stale one.
+const TAKEOVER_ATTEMPTS: usize = 20;
+const TAKEOVER_WAIT: Duration = Duration::from_millis(50);
+
+/// Marks this process as the running router, so a second instance refuses
+/// to start instead of failing on devices the first one holds. The lock
+/// file holds the owner's PID and is removed when the lock is dropped.
+pub struct InstanceLock {
+    path: PathBuf,
+}
+
+impl InstanceLock {
+    /// Takes the lock, replacing a lock file left by a process that is no
+    /// longer running. Fails with `RouterError::AlreadyRunning` if the owner
+    /// is alive. Returns `None`, after a warning, when the lock file cannot
+    /// be written, so a read-only install still runs.
+    pub fn acquire() -> Result<Option<Self>, RouterError> {
+        match Config::get_config_dir() {
+            Ok(dir) => Self::acquire_at(dir.join(LOCK_FILE_NAME)),
+            Err(e) => {
+                warn!("Running without an instance lock: {:#}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    fn acquire_at(path: PathBuf) -> Result<Option<Self>, RouterError> {
+        for _ in 0..TAKEOVER_ATTEMPTS {
+            match create(&path) {
+                Ok(()) => {
+                    debug!("Instance lock taken: {}", path.display());
+                    return Ok(Some(InstanceLock { path }));
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    warn!(
+                        "Running without an instance lock, {} could not be created: {}",
+                        path.display(),
+                        e
+                    );
+                    return Ok(None);
+                }
+            }
+
+            if let Ok(pid) = read_owner(&path) {
+                if owned_by_another_process(pid) {
+                    return Err(RouterError::AlreadyRunning { pid, path });
+                }
+            }
+
+            match remove_stale(&path) {
+                Ok(()) => {}
+                // Another instance is replacing the same stale lock.
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => thread::sleep(TAKEOVER_WAIT),
+                Err(e) => {
+                    warn!(
+                        "Running without an instance lock, {} could not be removed: {}",
+                        path.display(),
+                        e
+                    );
+                    return Ok(None);
+                }
+            }
+        }
+
+        // Another instance took the lock while this one waited.
+        match read_owner(&path) {
+            Ok(pid) => Err(RouterError::AlreadyRunning { pid, path }),
+            Err(e) => {
+                warn!("Running without an instance lock: {:#}", e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        match fs::remove_file(&self.path) {
+            Ok(()) => debug!("Instance lock released"),
+            Err(e) => warn!(
+                "Failed to remove instance lock {}: {}",
+                self.path.display(),
+                e
+            ),
+        }
+    }
+}
+
+/// Creates the lock file with this process's PID, failing if it exists.
+/// The PID is written to a temporary file that is then hard-linked into
+/// place, so another instance never sees the lock without its PID.
+fn create(path: &Path) -> std::io::Result<()> {
+    let pid = std::process::id();
+    let tmp_path = path.with_extension(format!("lock.{}.tmp", pid));
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp_path)?;
+    let linked = writeln!(file, "{}", pid)
+        .and_then(|()| file.sync_all())
+        .and_then(|()| fs::hard_link(&tmp_path, path));
+    drop(file);
+    let _ = fs::remove_file(&tmp_path);
+    linked
+}
+
+/// Removes the lock at `path` if its owner is no longer running. Only the
+/// instance holding `<path>.takeover`, created like the lock itself, may do
+/// this, and it checks the owner again while holding it. Otherwise two
+/// instances that found the same stale lock could each remove the lock the
+/// other just created, and both run. Fails with `AlreadyExists` while
+/// another instance holds the takeover file.
+fn remove_stale(path: &Path) -> std::io::Result<()> {
+    let takeover = path.with_extension("lock.takeover");
+    if let Err(e) = create(&takeover) {
+        if e.kind() == ErrorKind::AlreadyExists
+            && !read_owner(&takeover).is_ok_and(owned_by_another_process)
+        {
+            // Left by an instance that stopped halfway through a takeover.
+            let _ = fs::remove_file(&takeover);
+        }
+        return Err(e);
+    }
+
+    let removed = match read_owner(path) {
+        Ok(pid) if owned_by_another_process(pid) => Ok(()),
+        owner => {
+            match owner {
+                Ok(pid) => warn!(
+                    "Removing stale instance lock left by process {}: {}",
+                    pid,
+                    path.display()
+                ),
+                Err(e) => warn!("Removing unreadable instance lock: {:#}", e),
+            }
+            match fs::remove_file(path) {
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+                removed => removed,
+            }
+        }
+    };
+    let _ = fs::remove_file(&takeover);
+    removed
+}
+
+/// Whether `pid` is a running process other than this one. A lock holding
+/// this process's PID was left by an earlier process that had the same PID.
+fn owned_by_another_process(pid: u32) -> bool {
+    pid != std::process::id() && is_running(pid)
+}
+
+fn read_owner(path: &Path) -> Result<u32> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read instance lock: {}", path.display()))?;
+    contents
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid PID in instance lock: {}", path.display()))
+}
+
+#[cfg(target_os = "linux")]
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn is_running(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map_or(true, |status| status.success())
+}
+
+#[cfg(windows)]
+fn is_running(pid: u32) -> bool {
+    use winapi::shared::minwindef::{DWORD, FALSE};
+    use winapi::shared::winerror::ERROR_ACCESS_DENIED;
+    use winapi::um::errhandlingapi::GetLastError;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::minwinbase::STILL_ACTIVE;
+    use winapi::um::processthreadsapi::{GetExitCodeProcess, OpenProcess};
+    use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid);
+        if process.is_null() {
+            // A process we may not query, e.g. one run by another user,
+            // still exists.
+            return GetLastError() == ERROR_ACCESS_DENIED;
+        }
+        let mut exit_code: DWORD = 0;
+        let queried = GetExitCodeProcess(process, &mut exit_code);
+        CloseHandle(process);
+        queried == FALSE || exit_code == STILL_ACTIVE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Child, Command, Stdio};
+
+    fn lock_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "audio_router_test_{}_{}.lock",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn create_writes_the_pid_and_refuses_an_existing_lock() {
+        let path = lock_path("create");
+        create(&path).unwrap();
+        assert_eq!(read_owner(&path).unwrap(), std::process::id());
+
+        let error = create(&path).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(read_owner(&path).unwrap(), std::process::id());
+
+        fs::remove_file(&path).unwrap();
+        let leftovers = fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                name.starts_with("audio_router_test_create_") && name.ends_with(".tmp")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn this_process_is_running() {
+        assert!(is_running(std::process::id()));
+    }
+
+    /// A PID no process has.
+    const DEAD_PID: u32 = u32::MAX;
+
+    fn write_owner(path: &Path, pid: u32) {
+        fs::write(path, format!("{}\n", pid)).unwrap();
+    }
+
+    #[test]
+    fn a_stale_lock_is_replaced() {
+        let path = lock_path("stale");
+        write_owner(&path, DEAD_PID);
+
+        let lock = InstanceLock::acquire_at(path.clone()).unwrap().unwrap();
+        assert_eq!(read_owner(&path).unwrap(), std::process::id());
+        assert!(!path.with_extension("lock.takeover").exists());
+
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn a_takeover_left_by_a_stopped_process_is_cleared() {
+        let path = lock_path("stale_takeover");
+        let takeover = path.with_extension("lock.takeover");
+        write_owner(&path, DEAD_PID);
+        write_owner(&takeover, DEAD_PID);
+
+        let lock = InstanceLock::acquire_at(path.clone()).unwrap();
+        assert!(lock.is_some());
+        assert!(!takeover.exists());
+    }
+
+    #[test]
+    fn a_stale_lock_is_left_alone_during_another_takeover() {
+        let path = lock_path("busy_takeover");
+        let takeover = path.with_extension("lock.takeover");
+        write_owner(&path, DEAD_PID);
+        let other = OtherProcess::start();
+        write_owner(&takeover, other.pid());
+
+        let error = remove_stale(&path).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(read_owner(&path).unwrap(), DEAD_PID);
+        assert!(takeover.exists());
+
+        fs::remove_file(&takeover).unwrap();
+        remove_stale(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn a_running_owner_keeps_the_lock() {
+        let path = lock_path("running");
+        let other = OtherProcess::start();
+        write_owner(&path, other.pid());
+
+        let Err(RouterError::AlreadyRunning { pid, .. }) = InstanceLock::acquire_at(path.clone())
+        else {
+            panic!("expected AlreadyRunning");
+        };
+        assert_eq!(pid, other.pid());
+        assert_eq!(read_owner(&path).unwrap(), other.pid());
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// A running process other than this one, killed when dropped.
+    struct OtherProcess(Child);
+
+    impl OtherProcess {
+        fn start() -> Self {
+            let mut command = if cfg!(windows) {
+                let mut ping = Command::new("ping");
+                ping.args(["-n", "30", "127.0.0.1"]);
+                ping
+            } else {
+                let mut sleep = Command::new("sleep");
+                sleep.arg("30");
+                sleep
+            };
+            OtherProcess(command.stdout(Stdio::null()).spawn().unwrap())
+        }
+
+        fn pid(&self) -> u32 {
+            self.0.id()
+        }
+    }
+
+    impl Drop for OtherProcess {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Audio Router's routing engine, usable from other applications.
+//!
+//! ```no_run
+//! use std::sync::atomic::AtomicBool;
+//! use std::sync::Arc;
+//!
+//! use audio_router::{Config, Router, RouterError};
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let config = Config::from_yaml(&std::fs::read_to_string("router.yaml")?)?;
+//!     let running = Arc::new(AtomicBool::new(true));
+//!     // Store `false` in `running` from another thread to stop.
+//!     match Router::new(config).run(running) {
+//!         Err(RouterError::DeviceNotFound { device, .. }) => {
+//!             eprintln!("Plug in '{}' and try again", device)
+//!         }
+//!         result => result?,
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+pub mod audio;
+mod bus;
+pub mod config;
+mod device_watch;
+pub mod devices;
+mod dsp;
+#[cfg(windows)]
+mod endpoints;
+pub mod error;
+#[cfg(all(windows, feature = "service"))]
+mod event_log;
+pub mod hosts;
+mod instance_lock;
+pub mod live_view;
+pub mod logger;
+mod rt_log;
+mod rtp;
+pub mod shutdown;
+mod sinks;
+mod sources;
+mod state;
+pub mod status;
+pub mod wav;
+
+pub use config::Config;
+pub use error::RouterError;
+pub use live_view::{LiveView, RouteCommand};
+pub use shutdown::ShutdownReason;
+
+/// The crate version and the commit it was built from, e.g.
+/// `1.0.0 (3f2a9c1)`.
+pub const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("AUDIO_ROUTER_COMMIT"),
+    ")"
+);
+
+/// Runs the routes described by a `Config`.
+pub struct Router {
+    config: Config,
+    require_all_routes: bool,
+    live_view: Option<LiveView>,
+    on_ready: Option<ReadyCallback>,
+}
+
+/// Called once every route that could be opened is playing.
+pub type ReadyCallback = Box<dyn FnOnce() + Send>;
+
+impl Router {
+    pub fn new(config: Config) -> Self {
+        Router {
+            config,
+            require_all_routes: false,
+            live_view: None,
+            on_ready: None,
+        }
+    }
+
+    /// Fails with `RouterError::RoutesNotStarted` when any configured route
+    /// cannot be started, instead of continuing with the routes that could.
+    pub fn require_all_routes(mut self, require: bool) -> Self {
+        self.require_all_routes = require;
+        self
+    }
+
+    /// Sends status snapshots to `view` while routing and applies the
+    /// commands it sends back.
+    pub fn live_view(mut self, view: LiveView) -> Self {
+        self.live_view = Some(view);
+        self
+    }
+
+    /// Calls `ready` once the routes have started, before routing continues
+    /// until stopped. It is not called when startup fails.
+    pub fn on_ready(mut self, ready: impl FnOnce() + Send + 'static) -> Self {
+        self.on_ready = Some(Box::new(ready));
+        self
+    }
+
+    /// Opens every route and routes audio until `running` is set to `false`,
+    /// then shuts the routes down cleanly.
+    pub fn run(self, running: Arc<AtomicBool>) -> Result<(), RouterError> {
+        audio::run_audio_routing(
+            self.config,
+            running,
+            self.require_all_routes,
+            self.live_view,
+            self.on_ready,
+        )
+    }
+}
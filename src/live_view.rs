@@ -0,0 +1,21 @@
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
+
+use crate::status::ServiceStatus;
+
+/// A change to a running route, requested from a live view.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteCommand {
+    /// Sets the route's input gain as a linear multiplier.
+    SetGain { route: String, gain: f32 },
+    /// Mutes the route (its output plays silence) or unmutes it.
+    SetMuted { route: String, muted: bool },
+}
+
+/// Connects a display to the running routes: the router sends a status
+/// snapshot every `interval` and applies the commands sent back.
+pub struct LiveView {
+    pub interval: Duration,
+    pub status: Sender<ServiceStatus>,
+    pub commands: Receiver<RouteCommand>,
+}
@@ -1,31 +1,89 @@
-use anyhow::Result;
 use log::{Level, LevelFilter, Log, Metadata, Record};
-use std::fs::{File, OpenOptions};
-use std::io::Write;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::LoggingConfig;
+use crate::error::{Result, RouterError};
+#[cfg(all(windows, feature = "service"))]
+use crate::event_log::EventLog;
+
+/// Verbosity requested on the command line; see `set_verbosity`.
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+
+/// Raises the log level above the configured `logging.level`: 1 selects
+/// debug, 2 or more selects trace. Must be called before `FileLogger::init`.
+pub fn set_verbosity(verbosity: u8) {
+    VERBOSITY.store(verbosity, Ordering::Relaxed);
+}
+
+/// Whether records are also printed to stdout; see `set_console_echo`.
+static CONSOLE_ECHO: AtomicBool = AtomicBool::new(true);
+
+/// Stops or resumes printing records to stdout, e.g. while a full-screen view
+/// owns the terminal. The log file is written either way.
+pub fn set_console_echo(enabled: bool) {
+    CONSOLE_ECHO.store(enabled, Ordering::Relaxed);
+}
 
 pub struct FileLogger {
-    file: Mutex<File>,
+    file: Mutex<BufWriter<File>>,
+    flush_every_record: bool,
+    #[cfg(all(windows, feature = "service"))]
+    event_log: Option<EventLog>,
 }
 
 impl FileLogger {
-    pub fn new(log_path: PathBuf) -> Result<Self> {
+    pub fn new(log_path: PathBuf, flush_every_record: bool) -> Result<Self> {
+        if let Some(parent) = log_path.parent() {
+            fs::create_dir_all(parent).map_err(|source| RouterError::Io {
+                action: format!("Failed to create log directory: {}", parent.display()),
+                source,
+            })?;
+        }
         let file = OpenOptions::new()
             .create(true)
             .write(true)
             .truncate(true)
-            .open(&log_path)?;
+            .open(&log_path)
+            .map_err(|source| RouterError::Io {
+                action: format!("Failed to open log file: {}", log_path.display()),
+                source,
+            })?;
 
         Ok(FileLogger {
-            file: Mutex::new(file),
+            file: Mutex::new(BufWriter::new(file)),
+            flush_every_record,
+            #[cfg(all(windows, feature = "service"))]
+            event_log: None,
         })
     }
 
-    pub fn init(log_path: PathBuf, level: &str) -> Result<()> {
-        let logger = Box::new(FileLogger::new(log_path)?);
+    /// Also reports warnings and errors to the Windows Event Log.
+    #[cfg(all(windows, feature = "service"))]
+    fn with_event_log(mut self, enabled: bool) -> Self {
+        if enabled {
+            self.event_log = EventLog::open();
+        }
+        self
+    }
+
+    #[cfg(not(all(windows, feature = "service")))]
+    fn with_event_log(self, _enabled: bool) -> Self {
+        self
+    }
+
+    pub fn init(log_path: PathBuf, logging: &LoggingConfig) -> Result<()> {
+        let flush_interval = Duration::from_millis(logging.flush_interval_ms);
+        let logger = Box::new(
+            FileLogger::new(log_path, flush_interval.is_zero())?.with_event_log(logging.event_log),
+        );
 
-        let level_filter = match level.to_lowercase().as_str() {
+        let level_filter = match logging.level.to_lowercase().as_str() {
             "trace" => LevelFilter::Trace,
             "debug" => LevelFilter::Debug,
             "info" => LevelFilter::Info,
@@ -33,10 +91,28 @@ impl FileLogger {
             "error" => LevelFilter::Error,
             _ => LevelFilter::Info,
         };
+        let level_filter = match VERBOSITY.load(Ordering::Relaxed) {
+            0 => level_filter,
+            1 => level_filter.max(LevelFilter::Debug),
+            _ => LevelFilter::Trace,
+        };
 
         log::set_boxed_logger(logger)
             .map(|()| log::set_max_level(level_filter))
-            .map_err(|e| anyhow::anyhow!("Failed to initialize logger: {}", e))?;
+            .map_err(|e| RouterError::Logger(e.into()))?;
+
+        if !flush_interval.is_zero() {
+            thread::Builder::new()
+                .name("log-flush".to_string())
+                .spawn(move || loop {
+                    thread::sleep(flush_interval);
+                    log::logger().flush();
+                })
+                .map_err(|source| RouterError::Io {
+                    action: "Failed to start the log flush thread".to_string(),
+                    source,
+                })?;
+        }
 
         Ok(())
     }
@@ -61,16 +137,31 @@ impl Log for FileLogger {
 
             if let Ok(mut file) = self.file.lock() {
                 let _ = file.write_all(log_message.as_bytes());
-                let _ = file.flush();
+                if self.flush_every_record || record.level() == Level::Error {
+                    let _ = file.flush();
+                }
             }
 
-            println!("{}", log_message.trim_end());
+            if CONSOLE_ECHO.load(Ordering::Relaxed) {
+                println!("{}", log_message.trim_end());
+            }
+
+            #[cfg(all(windows, feature = "service"))]
+            if let Some(event_log) = &self.event_log {
+                event_log.report(
+                    record.level(),
+                    &format!("{}: {}", record.target(), record.args()),
+                );
+            }
         }
     }
 
     fn flush(&self) {
         if let Ok(mut file) = self.file.lock() {
-            let _ = file.flush();
+            if !file.buffer().is_empty() {
+                let _ = file.flush();
+                let _ = file.get_ref().sync_data();
+            }
         }
     }
 }
@@ -1,43 +1,91 @@
 use anyhow::{Context, Result};
-use log::info;
+use log::{info, warn};
 use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
-mod audio;
-mod config;
-mod devices;
-mod logger;
+#[cfg(unix)]
+mod daemon;
+mod tui;
 
-#[cfg(windows)]
+#[cfg(all(windows, feature = "service"))]
 mod service;
-#[cfg(windows)]
+#[cfg(all(windows, feature = "service"))]
 mod service_manager;
 
-use config::Config;
+use audio_router::{
+    audio, config, devices, hosts, logger, status, wav, Config, Router, ShutdownReason, VERSION,
+};
+
+const FORCE_QUIT_WINDOW: Duration = Duration::from_secs(3);
+/// Default largest per-sample difference `render --compare` accepts.
+const DEFAULT_RENDER_TOLERANCE: f32 = 1e-5;
 
 fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+    let (verbosity, args) = take_verbosity(env::args().collect());
+    logger::set_verbosity(verbosity);
+    let (profile, args) = take_profile(args);
+    if let Some(profile) = profile {
+        config::set_profile(profile);
+    }
+    let (config_files, args) = take_config_files(args);
+    if !config_files.is_empty() {
+        config::set_config_files(config_files);
+    }
 
     if args.len() > 1 {
         match args[1].as_str() {
-            #[cfg(windows)]
+            #[cfg(all(windows, feature = "service"))]
             "install" => {
                 return service_manager::install_service();
             }
-            #[cfg(windows)]
+            #[cfg(all(windows, feature = "service"))]
             "uninstall" => {
                 return service_manager::uninstall_service();
             }
-            #[cfg(windows)]
+            #[cfg(all(windows, feature = "service"))]
             "service" => {
                 return service::run_as_service();
             }
+            #[cfg(unix)]
+            "daemon" | "--daemon" => {
+                return daemon::run_as_daemon();
+            }
             "console" | "run" => {
-                return run_console_mode();
+                return run_console_mode(&args[2..]);
+            }
+            "list-hosts" => {
+                return list_hosts();
             }
             "list-devices" => {
-                return list_devices();
+                return list_devices(&args[2..]);
+            }
+            "match-devices" => {
+                return match_devices();
+            }
+            "config-dump" => {
+                return config_dump();
+            }
+            "set-gain" => {
+                return set_gain(&args[2..]);
+            }
+            "status" => {
+                print!("{}", status::read_status()?);
+                return Ok(());
+            }
+            "selftest" => {
+                return run_self_test(&args[2..]);
+            }
+            "render" => {
+                return render(&args[2..]);
+            }
+            "version" | "--version" | "-V" => {
+                println!("Audio Router {}", VERSION);
+                return Ok(());
             }
             _ => {
                 print_usage();
@@ -46,53 +94,266 @@ fn main() -> Result<()> {
         }
     }
 
-    run_console_mode()
+    run_console_mode(&[])
+}
+
+/// Strips `-v`, `-vv`, ... and `--verbose` from anywhere in the arguments so
+/// every subcommand accepts them, returning how many were given.
+fn take_verbosity(args: Vec<String>) -> (u8, Vec<String>) {
+    let mut verbosity = 0u8;
+    let args = args
+        .into_iter()
+        .enumerate()
+        .filter(|(i, arg)| {
+            if *i == 0 {
+                return true;
+            }
+            let count = match arg.as_str() {
+                "--verbose" => 1,
+                flag if flag.len() > 1
+                    && flag.starts_with('-')
+                    && flag[1..].chars().all(|c| c == 'v') =>
+                {
+                    flag.len() - 1
+                }
+                _ => return true,
+            };
+            verbosity = verbosity.saturating_add(count as u8);
+            false
+        })
+        .map(|(_, arg)| arg)
+        .collect();
+
+    (verbosity, args)
+}
+
+/// Strips `--profile <name>` from anywhere in the arguments.
+fn take_profile(mut args: Vec<String>) -> (Option<String>, Vec<String>) {
+    let Some(position) = args.iter().skip(1).position(|arg| arg == "--profile") else {
+        return (None, args);
+    };
+
+    let flag = position + 1;
+    if flag + 1 >= args.len() {
+        return (None, args);
+    }
+
+    let profile = args.remove(flag + 1);
+    args.remove(flag);
+    (Some(profile), args)
+}
+
+/// Strips every `--config <path>` from the arguments, keeping their order.
+fn take_config_files(args: Vec<String>) -> (Vec<PathBuf>, Vec<String>) {
+    let mut files = Vec::new();
+    let mut rest = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), rest.is_empty()) {
+            ("--config", false) => match args.next() {
+                Some(path) => files.push(PathBuf::from(path)),
+                None => rest.push(arg),
+            },
+            _ => rest.push(arg),
+        }
+    }
+
+    (files, rest)
 }
 
-fn run_console_mode() -> Result<()> {
+fn run_console_mode(args: &[String]) -> Result<()> {
+    let mut duration = None;
+    let mut live_table = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.as_slice().first()) {
+            ("--duration", Some(secs)) => {
+                duration = Some(Duration::from_secs(
+                    secs.parse()
+                        .with_context(|| format!("Invalid duration: {}", secs))?,
+                ));
+                args.next();
+            }
+            ("--tui", _) => live_table = true,
+            _ => {
+                print_usage();
+                return Ok(());
+            }
+        }
+    }
+
     let config = Config::load().context("Failed to load configuration")?;
 
-    let log_path = Config::get_config_dir()?.join("logs.txt");
-    logger::FileLogger::init(log_path.clone(), &config.logging.level)?;
+    let log_path = config.log_path()?;
+    logger::FileLogger::init(log_path.clone(), &config.logging)?;
 
+    info!("Audio Router {}", VERSION);
     info!("Audio routing service started (console mode)");
-    info!("Configuration loaded from config.yaml");
+    match &config.profile {
+        Some(profile) => info!("Configuration loaded (profile: {})", profile),
+        None if !config.files.is_empty() => info!(
+            "Configuration loaded from {}",
+            config
+                .files
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" + ")
+        ),
+        None => info!("Configuration loaded from config.yaml"),
+    }
     info!("Logging to: {}", log_path.display());
 
     info!("Device configuration:");
     for (alias, device_config) in &config.devices {
         info!(
             "  {} ({}): {}",
-            alias, device_config.device_type, device_config.name
+            alias,
+            device_config.device_type,
+            device_config.identifier()
         );
     }
 
+    if !config.buses.is_empty() {
+        info!("Bus configuration:");
+        for (name, bus) in &config.buses {
+            info!(
+                "  {}: {} channels, {} Hz, gain {}",
+                name, bus.channels, bus.sample_rate, bus.gain
+            );
+        }
+    }
+
     info!("Routing configuration:");
     for (route_name, route_config) in &config.routing {
-        info!(
-            "  {}: {} → {}",
-            route_name, route_config.from, route_config.to
-        );
+        if route_config.to_fallback.is_empty() {
+            info!(
+                "  {}: {} → {}",
+                route_name, route_config.from, route_config.to
+            );
+        } else {
+            info!(
+                "  {}: {} → {} (fallback: {})",
+                route_name,
+                route_config.from,
+                route_config.to,
+                route_config.to_fallback.join(", ")
+            );
+        }
     }
 
     let running = Arc::new(AtomicBool::new(true));
     let running_handle = running.clone();
+    // The first request to stop is the reason reported.
+    let stop_reason = Arc::new(OnceLock::new());
+    let stop_reason_handle = stop_reason.clone();
 
-    ctrlc::set_handler(move || {
-        info!("Shutdown requested (Ctrl+C)");
-        running_handle.store(false, Ordering::SeqCst);
+    let mut first_press: Option<Instant> = None;
+    ctrlc::set_handler(move || match first_press {
+        None => {
+            info!("Shutdown requested (Ctrl+C), press again to force quit");
+            first_press = Some(Instant::now());
+            let _ = stop_reason_handle.set(ShutdownReason::Interrupted);
+            running_handle.store(false, Ordering::SeqCst);
+        }
+        Some(pressed_at) if pressed_at.elapsed() <= FORCE_QUIT_WINDOW => {
+            warn!("Second Ctrl+C received, forcing shutdown");
+            log::logger().flush();
+            process::exit(1);
+        }
+        Some(_) => {
+            info!("Shutdown already in progress, press Ctrl+C again to force quit");
+            first_press = Some(Instant::now());
+        }
     })?;
 
-    info!("Press Ctrl+C to stop");
+    match duration {
+        Some(duration) => {
+            info!("Routing for {}s, then stopping", duration.as_secs());
+            let running = running.clone();
+            let stop_reason = stop_reason.clone();
+            thread::Builder::new()
+                .name("duration".to_string())
+                .spawn(move || {
+                    thread::sleep(duration);
+                    info!("Duration elapsed, shutting down");
+                    let _ = stop_reason.set(ShutdownReason::DurationElapsed);
+                    running.store(false, Ordering::SeqCst);
+                })?;
+        }
+        None => info!("Press Ctrl+C to stop"),
+    }
 
-    audio::run_audio_routing(config, running)?;
+    let mut router = Router::new(config).require_all_routes(duration.is_some());
+    let mut table = None;
+    if live_table {
+        let running = running.clone();
+        let stop_reason = stop_reason.clone();
+        let (view, thread) = tui::start(log_path, move || {
+            info!("Shutdown requested (q)");
+            let _ = stop_reason.set(ShutdownReason::Interrupted);
+            running.store(false, Ordering::SeqCst);
+        })?;
+        router = router.live_view(view);
+        table = Some(thread);
+    }
+
+    let result = router.run(running);
+    // The table restores the terminal once the router drops its view.
+    if let Some(table) = table {
+        let _ = table.join();
+    }
+
+    match &result {
+        Ok(()) => stop_reason
+            .get()
+            .copied()
+            .unwrap_or(ShutdownReason::Interrupted),
+        Err(e) => ShutdownReason::from(e),
+    }
+    .log();
 
     info!("Service stopped");
+    log::logger().flush();
+    Ok(result?)
+}
+
+fn list_hosts() -> Result<()> {
+    let default_host = cpal::default_host().id();
+
+    println!("Available audio hosts:");
+    println!("========================");
+
+    for host in cpal::available_hosts() {
+        let marker = if host == default_host {
+            " (default)"
+        } else {
+            ""
+        };
+        println!("{} [id: {:?}]{}", host.name(), host, marker);
+    }
+
     Ok(())
 }
 
-fn list_devices() -> Result<()> {
-    let host = cpal::default_host();
+fn list_devices(args: &[String]) -> Result<()> {
+    let (host, args) = match args {
+        [flag, name, rest @ ..] if flag == "--host" => (hosts::open_host(name)?, rest),
+        _ => (cpal::default_host(), args),
+    };
+
+    match args {
+        [] => {}
+        [flag] if flag == "--json" => {
+            println!("{}", devices::AudioDevices::list_available_json(&host)?);
+            return Ok(());
+        }
+        _ => {
+            print_usage();
+            return Ok(());
+        }
+    }
 
     println!("Available audio devices:");
     println!("========================");
@@ -102,26 +363,282 @@ fn list_devices() -> Result<()> {
     if devices.is_empty() {
         println!("No audio devices found!");
     } else {
-        for (i, device) in devices.iter().enumerate() {
-            println!("{}. {}", i + 1, device);
+        for (i, (name, id)) in devices.iter().enumerate() {
+            println!("{}. {}", i + 1, name);
+            if let Some(id) = id {
+                println!("   id: {}", id);
+            }
         }
     }
 
     Ok(())
 }
 
+fn match_devices() -> Result<()> {
+    let config = Config::load().context("Failed to load configuration")?;
+    let hosts = hosts::AudioHosts::open(&config)?;
+
+    println!(
+        "Configured devices ({} host):",
+        hosts.default_host().id().name()
+    );
+    println!("========================");
+
+    let matches = devices::AudioDevices::match_configured(&config, &hosts);
+    if matches.is_empty() {
+        println!("No hardware devices configured.");
+    }
+
+    for device in matches {
+        println!(
+            "{} ({}): {}",
+            device.alias, device.device_type, device.identifier
+        );
+        if let Some(host) = &device.host {
+            println!("  host: {}", host);
+        }
+
+        match &device.selected {
+            Some(name) => println!("  matched: {}", name),
+            None if device.candidates.is_empty() => println!("  not found"),
+            None => println!("  no device selected"),
+        }
+        if device.candidates.len() > 1 {
+            println!("  ambiguous, {} candidates:", device.candidates.len());
+            for name in &device.candidates {
+                println!("    {}", name);
+            }
+        }
+        if let Some(problem) = &device.problem {
+            println!("  problem: {}", problem);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the config exactly as the router will use it: after profile
+/// selection and environment variable expansion, with every default filled in.
+fn config_dump() -> Result<()> {
+    let config = Config::load().context("Failed to load configuration")?;
+
+    match &config.profile {
+        Some(profile) => println!("# Effective configuration (profile: {})", profile),
+        None => println!("# Effective configuration"),
+    }
+    for field in &config.unknown_fields {
+        println!("# Ignored unknown field: {}", field);
+    }
+    print!(
+        "{}",
+        serde_yaml::to_string(&config).context("Failed to serialize configuration")?
+    );
+    Ok(())
+}
+
+fn set_gain(args: &[String]) -> Result<()> {
+    let [alias, gain] = args else {
+        print_usage();
+        return Ok(());
+    };
+
+    let gain = parse_gain(gain).with_context(|| format!("Invalid gain value: {}", gain))?;
+
+    let mut config = Config::load().context("Failed to load configuration")?;
+    let device_config = config
+        .devices
+        .get_mut(alias)
+        .ok_or_else(|| anyhow::anyhow!("Device '{}' not found in config", alias))?;
+    device_config.gain = gain;
+
+    let written = config.save()?;
+
+    let files: Vec<String> = written
+        .iter()
+        .filter_map(|path| path.file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect();
+    println!(
+        "Set gain of '{}' to {} ({:.1} dB) in {}",
+        alias,
+        gain,
+        config::gain_to_db(gain),
+        if files.is_empty() {
+            "no file (unchanged)".to_string()
+        } else {
+            files.join(", ")
+        }
+    );
+    Ok(())
+}
+
+/// Parses a linear gain such as `1.5`, or a gain in dB such as `-6dB`.
+fn parse_gain(value: &str) -> Result<f32> {
+    let lower = value.trim().to_ascii_lowercase();
+    match lower.strip_suffix("db") {
+        Some(db) => Ok(config::db_to_gain(db.trim().parse()?)),
+        None => Ok(lower.parse()?),
+    }
+}
+
+fn run_self_test(args: &[String]) -> Result<()> {
+    let route_name = match args {
+        [] => None,
+        [flag, name] if flag == "--route" => Some(name.as_str()),
+        _ => {
+            print_usage();
+            return Ok(());
+        }
+    };
+
+    let config = Config::load().context("Failed to load configuration")?;
+
+    Ok(audio::run_self_test(&config, route_name)?)
+}
+
+/// Renders a WAV file through a route's processing, either writing the
+/// result or comparing it against a reference WAV within a tolerance.
+fn render(args: &[String]) -> Result<()> {
+    let mut route_name = None;
+    let mut channels = None;
+    let mut reference = None;
+    let mut tolerance = DEFAULT_RENDER_TOLERANCE;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--route" => route_name = args.next(),
+            "--compare" => reference = args.next(),
+            "--channels" => {
+                let value = args.next().map(String::as_str).unwrap_or_default();
+                channels = Some(
+                    value
+                        .parse::<u16>()
+                        .with_context(|| format!("Invalid channel count: {}", value))?,
+                );
+            }
+            "--tolerance" => {
+                let value = args.next().map(String::as_str).unwrap_or_default();
+                tolerance = value
+                    .parse()
+                    .with_context(|| format!("Invalid tolerance: {}", value))?;
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    let (Some(route_name), [input, rest @ ..]) = (route_name, paths.as_slice()) else {
+        print_usage();
+        return Ok(());
+    };
+    let output = match (rest, reference) {
+        ([output], None) => Some(output),
+        ([], Some(_)) => None,
+        _ => {
+            print_usage();
+            return Ok(());
+        }
+    };
+
+    let config = Config::load().context("Failed to load configuration")?;
+    let input = wav::Wav::read(Path::new(input))?;
+    let rendered = audio::render_route(&config, route_name, &input, channels)?;
+
+    if let Some(output) = output {
+        rendered.write(Path::new(output))?;
+        println!(
+            "Rendered {} frames through route '{}' to {}",
+            rendered.frames(),
+            route_name,
+            output
+        );
+        return Ok(());
+    }
+
+    let reference_path = reference.map(String::as_str).unwrap_or_default();
+    let reference = wav::Wav::read(Path::new(reference_path))?;
+    if !rendered.same_shape(&reference) {
+        return Err(anyhow::anyhow!(
+            "Output ({} Hz, {} channels, {} frames) does not match reference ({} Hz, {} channels, {} frames)",
+            rendered.sample_rate,
+            rendered.channels,
+            rendered.frames(),
+            reference.sample_rate,
+            reference.channels,
+            reference.frames()
+        ));
+    }
+
+    let (worst_frame, deviation) = rendered.max_deviation(&reference);
+    if deviation > tolerance {
+        return Err(anyhow::anyhow!(
+            "Output differs from {}: deviation {} at frame {} exceeds tolerance {}",
+            reference_path,
+            deviation,
+            worst_frame,
+            tolerance
+        ));
+    }
+
+    println!(
+        "Output matches {} (max deviation {}, tolerance {})",
+        reference_path, deviation, tolerance
+    );
+    Ok(())
+}
+
 fn print_usage() {
     println!("Audio Router - Audio routing service");
     println!();
     println!("Usage:");
     println!("  audio_router                  Run in console mode");
     println!("  audio_router console          Run in console mode");
+    println!("  audio_router console --duration <secs>");
+    println!("                                Route for a fixed time, then exit (fails if any");
+    println!("                                route does not start)");
+    println!("  audio_router console --tui    Show a live table of routes; keys mute them and");
+    println!("                                adjust their gain (combines with --duration)");
+    println!("  audio_router list-hosts       List available audio hosts");
     println!("  audio_router list-devices     List available audio devices");
+    println!("  audio_router list-devices --json");
+    println!("                                Describe devices and their capabilities as JSON");
+    println!("  audio_router list-devices --host <name> [--json]");
+    println!("                                List the devices of another audio host");
+    println!("  audio_router match-devices    Show which device each configured alias matches");
+    println!("  audio_router config-dump      Print the effective configuration with defaults");
+    println!("  audio_router set-gain <device> <gain>");
+    println!("                                Save a device's gain to config.yaml, as a");
+    println!("                                multiplier (1.5) or in dB (-6dB)");
+    println!("  audio_router status           Print the running router's status as JSON");
+    println!("  audio_router selftest         Play a test tone on each output device");
+    println!("  audio_router selftest --route <name>");
+    println!("                                Play a test tone on one route's output");
+    println!("  audio_router render --route <name> <input.wav> <output.wav> [--channels <n>]");
+    println!("                                Process a WAV file through a route offline");
+    println!("  audio_router render --route <name> <input.wav> --compare <reference.wav>");
+    println!("                      [--tolerance <t>] [--channels <n>]");
+    println!("                                Check a route's output against a reference WAV");
+    println!("  audio_router version          Print the version and the commit it was built from");
 
-    #[cfg(windows)]
+    #[cfg(all(windows, feature = "service"))]
     {
         println!("  audio_router install          Install as Windows service");
         println!("  audio_router uninstall        Uninstall Windows service");
         println!("  audio_router service          Run as Windows service (internal use)");
     }
+
+    #[cfg(unix)]
+    {
+        println!("  audio_router daemon           Run as a background daemon (systemd)");
+    }
+
+    println!();
+    println!("Options:");
+    println!("  -v, --verbose                 Log at debug level (-vv for trace), overriding");
+    println!("                                logging.level");
+    println!("  --profile <name>              Load config.<name>.yaml, or the <name> entry of");
+    println!("                                profiles in config.yaml");
+    println!("  --config <path>               Load this file instead of config.yaml; repeat to");
+    println!("                                merge later files over earlier ones");
 }
@@ -0,0 +1,127 @@
+use log::{log, Level};
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+/// Events one channel can hold before the drain thread catches up; further
+/// events are dropped.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// How often the drain thread turns queued events into log lines.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Something an audio callback reports. Events are plain codes, so reporting
+/// one never allocates, locks or does I/O.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RtEvent {
+    /// The output found the ring buffer empty for the first time.
+    FirstUnderrun,
+    /// The input dropped frames for the first time because the ring buffer
+    /// was full.
+    FirstOverflow,
+}
+
+impl RtEvent {
+    fn level(self) -> Level {
+        match self {
+            RtEvent::FirstUnderrun => Level::Info,
+            RtEvent::FirstOverflow => Level::Warn,
+        }
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            RtEvent::FirstUnderrun => {
+                "first underrun, filled with the underrun strategy (later underruns are only counted)"
+            }
+            RtEvent::FirstOverflow => {
+                "ring buffer full, dropping frames (later drops are only counted)"
+            }
+        }
+    }
+}
+
+/// The callback side of a lock-free single-producer, single-consumer event
+/// channel. A background thread drains every open channel and logs its
+/// events, so callbacks never touch the logger's mutex or the log file.
+pub struct RtLog {
+    producer: HeapProducer<RtEvent>,
+    closed: Arc<AtomicBool>,
+}
+
+impl RtLog {
+    /// Opens a channel whose events are logged as coming from `source`, e.g.
+    /// `Route 'mic' output`. Call this outside the callback.
+    pub fn open(source: String) -> Self {
+        let (producer, consumer) = HeapRb::new(CHANNEL_CAPACITY).split();
+        let closed = Arc::new(AtomicBool::new(false));
+
+        if let Ok(mut channels) = channels().lock() {
+            channels.push(Channel {
+                source,
+                consumer,
+                closed: closed.clone(),
+            });
+        }
+
+        RtLog { producer, closed }
+    }
+
+    /// Queues `event` for logging; safe to call from an audio callback.
+    pub fn report(&mut self, event: RtEvent) {
+        let _ = self.producer.push(event);
+    }
+}
+
+impl Drop for RtLog {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Release);
+    }
+}
+
+struct Channel {
+    source: String,
+    consumer: HeapConsumer<RtEvent>,
+    closed: Arc<AtomicBool>,
+}
+
+static CHANNELS: OnceLock<Mutex<Vec<Channel>>> = OnceLock::new();
+
+fn channels() -> &'static Mutex<Vec<Channel>> {
+    CHANNELS.get_or_init(|| {
+        let spawned = thread::Builder::new()
+            .name("rt-log".to_string())
+            .spawn(|| loop {
+                thread::sleep(DRAIN_INTERVAL);
+                drain();
+            });
+        if let Err(e) = spawned {
+            log::warn!("Failed to start the audio callback log thread: {}", e);
+        }
+        Mutex::new(Vec::new())
+    })
+}
+
+/// Logs every queued event and forgets channels whose callback side is gone.
+/// Runs periodically on the drain thread; call it directly to log pending
+/// events before shutting down.
+pub fn drain() {
+    let Some(channels) = CHANNELS.get() else {
+        return;
+    };
+    let Ok(mut channels) = channels.lock() else {
+        return;
+    };
+
+    channels.retain_mut(|channel| {
+        // Read before draining, so events queued just before the close are
+        // still logged.
+        let closed = channel.closed.load(Ordering::Acquire);
+        while let Some(event) = channel.consumer.pop() {
+            log!(event.level(), "{}: {}", channel.source, event.message());
+        }
+        !closed
+    });
+}
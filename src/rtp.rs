@@ -0,0 +1,109 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const HEADER_LEN: usize = 12;
+/// Keeps packets under a typical 1500 byte Ethernet MTU after IP/UDP headers.
+pub const MAX_PAYLOAD_LEN: usize = 1400;
+
+const RTP_VERSION: u8 = 2;
+/// Dynamic payload type used for L16 at rates/channel counts without a
+/// static assignment in RFC 3551.
+const DYNAMIC_PAYLOAD_TYPE: u8 = 96;
+
+/// Static RFC 3551 payload types exist only for 44.1 kHz L16.
+pub fn payload_type(sample_rate: u32, channels: u16) -> u8 {
+    match (sample_rate, channels) {
+        (44100, 2) => 10,
+        (44100, 1) => 11,
+        _ => DYNAMIC_PAYLOAD_TYPE,
+    }
+}
+
+/// Frames per packet for a packet duration, capped so the L16 payload fits
+/// in `MAX_PAYLOAD_LEN`.
+pub fn frames_per_packet(sample_rate: u32, channels: u16, packets_per_second: u32) -> usize {
+    let by_time = (sample_rate / packets_per_second).max(1) as usize;
+    let by_size = (MAX_PAYLOAD_LEN / (2 * channels.max(1) as usize)).max(1);
+    by_time.min(by_size)
+}
+
+/// Builds RTP packets with an L16 (big-endian 16-bit PCM) payload.
+pub struct Packetizer {
+    payload_type: u8,
+    sequence: u16,
+    timestamp: u32,
+    ssrc: u32,
+    channels: usize,
+}
+
+impl Packetizer {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+
+        Packetizer {
+            payload_type: payload_type(sample_rate, channels),
+            sequence: seed as u16,
+            timestamp: seed.rotate_left(16),
+            ssrc: seed ^ std::process::id(),
+            channels: channels.max(1) as usize,
+        }
+    }
+
+    /// Encodes interleaved samples into `packet`, replacing its contents.
+    pub fn packetize(&mut self, samples: &[f32], packet: &mut Vec<u8>) {
+        packet.clear();
+        packet.push(RTP_VERSION << 6);
+        packet.push(self.payload_type & 0x7f);
+        packet.extend_from_slice(&self.sequence.to_be_bytes());
+        packet.extend_from_slice(&self.timestamp.to_be_bytes());
+        packet.extend_from_slice(&self.ssrc.to_be_bytes());
+
+        for &sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+            packet.extend_from_slice(&value.to_be_bytes());
+        }
+
+        self.sequence = self.sequence.wrapping_add(1);
+        self.timestamp = self
+            .timestamp
+            .wrapping_add((samples.len() / self.channels) as u32);
+    }
+}
+
+/// Returns the sequence number and payload of an RTP packet, skipping CSRCs,
+/// header extensions and padding.
+pub fn parse(packet: &[u8]) -> Option<(u16, &[u8])> {
+    if packet.len() < HEADER_LEN || packet[0] >> 6 != RTP_VERSION {
+        return None;
+    }
+
+    let has_padding = packet[0] & 0x20 != 0;
+    let has_extension = packet[0] & 0x10 != 0;
+    let csrc_count = (packet[0] & 0x0f) as usize;
+    let sequence = u16::from_be_bytes([packet[2], packet[3]]);
+
+    let mut start = HEADER_LEN + csrc_count * 4;
+    if has_extension {
+        let words = packet.get(start + 2..start + 4)?;
+        start += 4 + u16::from_be_bytes([words[0], words[1]]) as usize * 4;
+    }
+
+    let mut end = packet.len();
+    if has_padding {
+        end = end.checked_sub(*packet.last()? as usize)?;
+    }
+
+    packet.get(start..end).map(|payload| (sequence, payload))
+}
+
+/// Decodes an L16 payload into `samples`, replacing its contents.
+pub fn decode_l16(payload: &[u8], samples: &mut Vec<f32>) {
+    samples.clear();
+    samples.extend(
+        payload
+            .chunks_exact(2)
+            .map(|bytes| i16::from_be_bytes([bytes[0], bytes[1]]) as f32 / i16::MAX as f32),
+    );
+}
@@ -1,8 +1,9 @@
 use anyhow::{Context, Result};
 use log::{error, info};
 use std::ffi::OsString;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 use windows_service::{
     define_windows_service,
@@ -10,12 +11,12 @@ use windows_service::{
         ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
         ServiceType,
     },
-    service_control_handler::{self, ServiceControlHandlerResult},
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
     service_dispatcher,
 };
 
-use crate::audio;
-use crate::config::Config;
+use crate::config::LoggingConfig;
+use crate::{Config, Router, ShutdownReason};
 
 const SERVICE_NAME: &str = "AudioRouter";
 const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
@@ -31,24 +32,25 @@ fn service_main(arguments: Vec<OsString>) {
     if let Err(e) = run_service(arguments) {
         error!("Service failed: {}", e);
     }
+    log::logger().flush();
 }
 
 fn run_service(_arguments: Vec<OsString>) -> Result<()> {
-    let config = Config::load().context("Failed to load configuration")?;
-
-    let log_path = Config::get_config_dir()?.join("logs.txt");
-    crate::logger::FileLogger::init(log_path.clone(), &config.logging.level)?;
-
-    info!("Audio Router Windows Service starting");
-
     let running = Arc::new(AtomicBool::new(true));
     let running_handle = running.clone();
+    let stop_reason = Arc::new(OnceLock::new());
+    let stop_reason_handle = stop_reason.clone();
 
     let event_handler = move |control_event| -> ServiceControlHandlerResult {
         match control_event {
             ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
             ServiceControl::Stop | ServiceControl::Shutdown => {
-                info!("Service stop requested");
+                let reason = match control_event {
+                    ServiceControl::Shutdown => ShutdownReason::SystemShutdown,
+                    _ => ShutdownReason::ServiceStop,
+                };
+                info!("Service stop requested ({})", reason);
+                let _ = stop_reason_handle.set(reason);
                 running_handle.store(false, Ordering::SeqCst);
                 ServiceControlHandlerResult::NoError
             }
@@ -56,9 +58,34 @@ fn run_service(_arguments: Vec<OsString>) -> Result<()> {
         }
     };
 
+    // Registered first, so every failure below can be reported to the
+    // service manager with its exit code.
     let status_handle = service_control_handler::register(SERVICE_NAME, event_handler)
         .map_err(|e| anyhow::anyhow!("Failed to register service control handler: {:?}", e))?;
 
+    let config = match Config::load().context("Failed to load configuration") {
+        Ok(config) => config,
+        Err(e) => {
+            init_fallback_logger();
+            return stop_with_error(&status_handle, ShutdownReason::ConfigError, e);
+        }
+    };
+
+    let logger = config
+        .log_path()
+        .and_then(|log_path| crate::logger::FileLogger::init(log_path, &config.logging))
+        .context("Failed to set up logging");
+    if let Err(e) = logger {
+        init_fallback_logger();
+        return stop_with_error(&status_handle, ShutdownReason::ConfigError, e);
+    }
+
+    info!("Audio Router {}", crate::VERSION);
+    info!("Audio Router Windows Service starting");
+    if let Some(profile) = &config.profile {
+        info!("Using config profile: {}", profile);
+    }
+
     status_handle.set_service_status(ServiceStatus {
         service_type: SERVICE_TYPE,
         current_state: ServiceState::Running,
@@ -71,22 +98,22 @@ fn run_service(_arguments: Vec<OsString>) -> Result<()> {
 
     info!("Service status set to Running");
 
-    match audio::run_audio_routing(config, running.clone()) {
+    match Router::new(config).run(running.clone()) {
         Ok(()) => {
             info!("Audio routing completed successfully");
+            stop_reason
+                .get()
+                .copied()
+                .unwrap_or(ShutdownReason::ServiceStop)
+                .log();
         }
         Err(e) => {
-            error!("Audio routing failed: {}", e);
-            status_handle.set_service_status(ServiceStatus {
-                service_type: SERVICE_TYPE,
-                current_state: ServiceState::Stopped,
-                controls_accepted: ServiceControlAccept::empty(),
-                exit_code: ServiceExitCode::ServiceSpecific(1),
-                checkpoint: 0,
-                wait_hint: Duration::default(),
-                process_id: None,
-            })?;
-            return Err(e);
+            let reason = ShutdownReason::from(&e);
+            return stop_with_error(
+                &status_handle,
+                reason,
+                anyhow::Error::from(e).context("Audio routing failed"),
+            );
         }
     }
 
@@ -103,3 +130,40 @@ fn run_service(_arguments: Vec<OsString>) -> Result<()> {
     info!("Service stopped");
     Ok(())
 }
+
+/// Logs `error` and its shutdown reason, then reports the service stopped
+/// with the reason's exit code as a service-specific code, so `sc query`
+/// and the service manager's event show what kind of failure it was.
+fn stop_with_error(
+    status_handle: &ServiceStatusHandle,
+    reason: ShutdownReason,
+    error: anyhow::Error,
+) -> Result<()> {
+    error!("{:#}", error);
+    reason.log();
+    status_handle.set_service_status(ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state: ServiceState::Stopped,
+        controls_accepted: ServiceControlAccept::empty(),
+        exit_code: ServiceExitCode::ServiceSpecific(reason.exit_code()),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    })?;
+    Err(error)
+}
+
+/// Logs to `logs.txt` next to the executable and to the Event Log when the
+/// configured logging cannot be used, so a broken config is still reported
+/// somewhere an administrator will look.
+fn init_fallback_logger() {
+    let logging = LoggingConfig {
+        level: "info".to_string(),
+        flush_interval_ms: 0,
+        event_log: true,
+        file: PathBuf::from("logs.txt"),
+    };
+    if let Ok(dir) = Config::get_config_dir() {
+        let _ = crate::logger::FileLogger::init(dir.join(&logging.file), &logging);
+    }
+}
@@ -0,0 +1,103 @@
+use log::{error, info};
+use std::fmt;
+
+use crate::error::RouterError;
+
+/// Why the router stopped. Every entry point logs it the same way, and the
+/// Windows service reports `exit_code` to the service manager.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// Ctrl+C in console mode.
+    Interrupted,
+    /// The service manager or a signal asked the service to stop.
+    ServiceStop,
+    /// The system is shutting down.
+    SystemShutdown,
+    /// `console --duration` ran out.
+    DurationElapsed,
+    /// The configuration could not be loaded, or describes routes that
+    /// cannot work.
+    ConfigError,
+    /// A configured device is missing or unusable.
+    DeviceError,
+    /// Streams could not be opened or no route could be started.
+    StreamError,
+}
+
+impl ShutdownReason {
+    /// Process or service exit code: 0 for a requested stop, otherwise one
+    /// code per kind of failure.
+    pub fn exit_code(self) -> u32 {
+        match self {
+            ShutdownReason::Interrupted
+            | ShutdownReason::ServiceStop
+            | ShutdownReason::SystemShutdown
+            | ShutdownReason::DurationElapsed => 0,
+            ShutdownReason::ConfigError => 2,
+            ShutdownReason::DeviceError => 3,
+            ShutdownReason::StreamError => 4,
+        }
+    }
+
+    pub fn is_failure(self) -> bool {
+        self.exit_code() != 0
+    }
+
+    /// Logs the reason in the one format post-mortems can search for.
+    pub fn log(self) {
+        if self.is_failure() {
+            error!("Shutdown reason: {} (exit code {})", self, self.exit_code());
+        } else {
+            info!("Shutdown reason: {}", self);
+        }
+    }
+}
+
+impl From<&RouterError> for ShutdownReason {
+    fn from(error: &RouterError) -> Self {
+        match error {
+            RouterError::NoRoutesConfigured
+            | RouterError::TooManyRoutes { .. }
+            | RouterError::RouteNotFound(_)
+            | RouterError::DeviceNotConfigured(_)
+            | RouterError::InvalidRoute { .. }
+            | RouterError::RoutingCycle(_)
+            | RouterError::ConfigNotFound(_)
+            | RouterError::ConfigParse { .. }
+            | RouterError::InvalidConfig(_)
+            | RouterError::Io { .. }
+            | RouterError::UnknownHost { .. }
+            | RouterError::InvalidWav { .. }
+            | RouterError::StatusNotFound(_)
+            | RouterError::Logger(_) => ShutdownReason::ConfigError,
+            RouterError::HostUnavailable { .. }
+            | RouterError::DeviceNotFound { .. }
+            | RouterError::DevicesNotFound { .. }
+            | RouterError::AmbiguousDevice { .. }
+            | RouterError::WrongDeviceType { .. }
+            | RouterError::NoOutputDevices
+            | RouterError::AlreadyRunning { .. } => ShutdownReason::DeviceError,
+            RouterError::StreamConfig { .. }
+            | RouterError::StreamBuild { .. }
+            | RouterError::StreamStart { .. }
+            | RouterError::Network { .. }
+            | RouterError::NoRoutesStarted
+            | RouterError::RoutesNotStarted { .. } => ShutdownReason::StreamError,
+        }
+    }
+}
+
+impl fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            ShutdownReason::Interrupted => "interrupted (Ctrl+C)",
+            ShutdownReason::ServiceStop => "stop requested",
+            ShutdownReason::SystemShutdown => "system shutdown",
+            ShutdownReason::DurationElapsed => "duration elapsed",
+            ShutdownReason::ConfigError => "configuration error",
+            ShutdownReason::DeviceError => "device error",
+            ShutdownReason::StreamError => "stream error",
+        };
+        f.write_str(reason)
+    }
+}
@@ -0,0 +1,308 @@
+use anyhow::{Context, Result};
+use cpal::traits::StreamTrait;
+use cpal::Stream;
+use log::{info, warn};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::bus::BusOutput;
+use crate::rtp::{self, Packetizer};
+
+/// UDP sinks aim to send one packet every 5 ms; packets that would not fit
+/// the MTU are made shorter and sent more often.
+const PACKETS_PER_SECOND: u32 = 200;
+
+/// Null sinks pull one block every 10 ms.
+const NULL_BLOCKS_PER_SECOND: u32 = 100;
+
+pub type OutputCallback = Box<dyn FnMut(&mut [f32]) + Send>;
+
+/// Where a route's output audio goes: a cpal playback stream, or a sink
+/// driven by its own thread that pulls from the same ring buffer.
+pub enum RouteOutput {
+    Device(Stream),
+    Udp(UdpSink),
+    Bus(BusOutput),
+    Null(NullSink),
+}
+
+impl RouteOutput {
+    pub fn play(&self) -> Result<()> {
+        match self {
+            RouteOutput::Device(stream) => stream.play()?,
+            RouteOutput::Udp(sink) => sink.start()?,
+            RouteOutput::Bus(link) => link.start(),
+            RouteOutput::Null(sink) => sink.start()?,
+        }
+        Ok(())
+    }
+
+    pub fn pause(&self) -> Result<()> {
+        match self {
+            RouteOutput::Device(stream) => stream.pause()?,
+            RouteOutput::Udp(sink) => sink.stop(),
+            RouteOutput::Bus(link) => link.stop(),
+            RouteOutput::Null(sink) => sink.stop(),
+        }
+        Ok(())
+    }
+}
+
+struct UdpWorker {
+    socket: UdpSocket,
+    target: SocketAddr,
+    packetizer: Packetizer,
+    callback: OutputCallback,
+}
+
+/// Sends a route's audio as RTP/L16 packets. The sender thread paces itself
+/// in real time, pulls each packet's samples through the output callback and
+/// owns all socket I/O.
+pub struct UdpSink {
+    name: String,
+    frames_per_packet: usize,
+    sample_rate: u32,
+    channels: usize,
+    running: Arc<AtomicBool>,
+    worker: Mutex<Option<UdpWorker>>,
+    thread: Mutex<Option<JoinHandle<UdpWorker>>>,
+}
+
+impl UdpSink {
+    pub fn new(
+        name: &str,
+        address: &str,
+        sample_rate: u32,
+        channels: u16,
+        callback: OutputCallback,
+    ) -> Result<Self> {
+        let target: SocketAddr = address
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve '{}'", address))?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("'{}' did not resolve to an address", address))?;
+
+        let bind_addr = if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        // Left unconnected so ICMP port-unreachable replies from a receiver
+        // that is not listening yet do not surface as send errors.
+        let socket = UdpSocket::bind(bind_addr).context("Failed to bind UDP socket")?;
+
+        Ok(UdpSink {
+            name: name.to_string(),
+            frames_per_packet: rtp::frames_per_packet(sample_rate, channels, PACKETS_PER_SECOND),
+            sample_rate,
+            channels: channels as usize,
+            running: Arc::new(AtomicBool::new(false)),
+            worker: Mutex::new(Some(UdpWorker {
+                socket,
+                target,
+                packetizer: Packetizer::new(sample_rate, channels),
+                callback,
+            })),
+            thread: Mutex::new(None),
+        })
+    }
+
+    fn start(&self) -> Result<()> {
+        let Some(mut worker) = self.worker.lock().ok().and_then(|mut w| w.take()) else {
+            return Ok(());
+        };
+
+        self.running.store(true, Ordering::SeqCst);
+        let running = self.running.clone();
+        let name = self.name.clone();
+        let frames = self.frames_per_packet;
+        let sample_rate = self.sample_rate;
+        let channels = self.channels;
+
+        let handle = thread::Builder::new()
+            .name(format!("sink-{}", self.name))
+            .spawn(move || {
+                let mut block = vec![0.0; frames * channels];
+                let mut packet = Vec::with_capacity(rtp::HEADER_LEN + block.len() * 2);
+                let started = Instant::now();
+                let mut sent: u64 = 0;
+                let mut failing = false;
+
+                while running.load(Ordering::SeqCst) {
+                    (worker.callback)(&mut block);
+                    worker.packetizer.packetize(&block, &mut packet);
+
+                    match worker.socket.send_to(&packet, worker.target) {
+                        Ok(_) if failing => {
+                            info!("UDP sink '{}' is sending again", name);
+                            failing = false;
+                        }
+                        Ok(_) => {}
+                        Err(e) if !failing => {
+                            warn!("UDP sink '{}' failed to send: {}", name, e);
+                            failing = true;
+                        }
+                        Err(_) => {}
+                    }
+
+                    sent += 1;
+                    let next_packet = started + send_time(sent, frames, sample_rate);
+                    if let Some(wait) = next_packet.checked_duration_since(Instant::now()) {
+                        thread::sleep(wait);
+                    }
+                }
+
+                worker
+            })?;
+
+        if let Ok(mut thread) = self.thread.lock() {
+            *thread = Some(handle);
+        }
+        Ok(())
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        let handle = self.thread.lock().ok().and_then(|mut t| t.take());
+        if let Some(worker) = handle.and_then(|h| h.join().ok()) {
+            if let Ok(mut slot) = self.worker.lock() {
+                *slot = Some(worker);
+            }
+        }
+    }
+}
+
+/// When packet `index` is due, counted from the first: each packet carries
+/// `frames` frames, so packets go out at the rate the audio plays whatever
+/// size they are. Computed from the start rather than added up, so rounding
+/// does not accumulate.
+fn send_time(index: u64, frames: usize, sample_rate: u32) -> Duration {
+    let nanos = index as u128 * frames as u128 * 1_000_000_000 / sample_rate.max(1) as u128;
+    Duration::from_nanos(nanos as u64)
+}
+
+impl Drop for UdpSink {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Throws a route's audio away. The sink thread pulls blocks through the
+/// output callback in real time, so the route's input and processing run
+/// exactly as they would for a device.
+pub struct NullSink {
+    name: String,
+    frames_per_block: usize,
+    channels: usize,
+    running: Arc<AtomicBool>,
+    callback: Mutex<Option<OutputCallback>>,
+    thread: Mutex<Option<JoinHandle<OutputCallback>>>,
+}
+
+impl NullSink {
+    pub fn new(name: &str, sample_rate: u32, channels: u16, callback: OutputCallback) -> Self {
+        NullSink {
+            name: name.to_string(),
+            frames_per_block: (sample_rate / NULL_BLOCKS_PER_SECOND).max(1) as usize,
+            channels: channels as usize,
+            running: Arc::new(AtomicBool::new(false)),
+            callback: Mutex::new(Some(callback)),
+            thread: Mutex::new(None),
+        }
+    }
+
+    fn start(&self) -> Result<()> {
+        let Some(mut callback) = self.callback.lock().ok().and_then(|mut c| c.take()) else {
+            return Ok(());
+        };
+
+        self.running.store(true, Ordering::SeqCst);
+        let running = self.running.clone();
+        let frames = self.frames_per_block;
+        let channels = self.channels;
+
+        let handle = thread::Builder::new()
+            .name(format!("sink-{}", self.name))
+            .spawn(move || {
+                let block_duration = Duration::from_secs(1) / NULL_BLOCKS_PER_SECOND;
+                let mut block = vec![0.0; frames * channels];
+                let mut next_block = Instant::now();
+
+                while running.load(Ordering::SeqCst) {
+                    callback(&mut block);
+
+                    next_block += block_duration;
+                    if let Some(wait) = next_block.checked_duration_since(Instant::now()) {
+                        thread::sleep(wait);
+                    }
+                }
+
+                callback
+            })?;
+
+        if let Ok(mut thread) = self.thread.lock() {
+            *thread = Some(handle);
+        }
+        Ok(())
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        let handle = self.thread.lock().ok().and_then(|mut t| t.take());
+        if let Some(callback) = handle.and_then(|h| h.join().ok()) {
+            if let Ok(mut slot) = self.callback.lock() {
+                *slot = Some(callback);
+            }
+        }
+    }
+}
+
+impl Drop for NullSink {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frames a sink sends in one second of packets.
+    fn frames_per_second(sample_rate: u32, channels: u16) -> u64 {
+        let frames = rtp::frames_per_packet(sample_rate, channels, PACKETS_PER_SECOND);
+        let second = Duration::from_secs(1);
+        let packets = (0..)
+            .take_while(|&index| send_time(index, frames, sample_rate) < second)
+            .count();
+        packets as u64 * frames as u64
+    }
+
+    #[test]
+    fn udp_sinks_send_frames_at_the_sample_rate() {
+        for (sample_rate, channels) in [(48000, 2), (44100, 2), (96000, 2), (48000, 4), (192000, 8)]
+        {
+            let sent = frames_per_second(sample_rate, channels) as i64;
+            let frames = rtp::frames_per_packet(sample_rate, channels, PACKETS_PER_SECOND) as i64;
+            assert!(
+                (sent - sample_rate as i64).abs() < frames,
+                "{} Hz, {} ch: {} frames per second",
+                sample_rate,
+                channels,
+                sent
+            );
+        }
+    }
+
+    #[test]
+    fn payloads_stay_under_the_mtu() {
+        for channels in [1, 2, 4, 8] {
+            let frames = rtp::frames_per_packet(96000, channels, PACKETS_PER_SECOND);
+            assert!(frames * channels as usize * 2 <= rtp::MAX_PAYLOAD_LEN);
+        }
+    }
+}
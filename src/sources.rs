@@ -0,0 +1,413 @@
+use anyhow::{Context, Result};
+use cpal::traits::StreamTrait;
+use cpal::Stream;
+use log::{debug, info, warn};
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::bus::BusInput;
+use crate::config::{TestSignalConfig, Waveform};
+use crate::rtp;
+
+/// Synthetic sources deliver audio in 10 ms blocks.
+const BLOCKS_PER_SECOND: u32 = 100;
+/// Packets held back before playout starts, absorbing network jitter.
+const JITTER_BUFFER_PACKETS: usize = 4;
+/// Playout interval used until the first packet reveals the sender's.
+const DEFAULT_PACKET_INTERVAL: Duration = Duration::from_millis(5);
+/// After this long without packets a UDP source rebuffers from scratch.
+const NETWORK_IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_DATAGRAM_LEN: usize = 4096;
+
+pub type InputCallback = Box<dyn FnMut(&[f32]) + Send>;
+
+/// Where a route's input audio comes from: a cpal capture stream, or a
+/// source driven by its own thread that feeds the same input callback.
+pub enum RouteInput {
+    Device(Stream),
+    Synthetic(SyntheticSource),
+    Udp(UdpSource),
+    Bus(BusInput),
+}
+
+impl RouteInput {
+    pub fn play(&self) -> Result<()> {
+        match self {
+            RouteInput::Device(stream) => stream.play()?,
+            RouteInput::Synthetic(source) => source.start()?,
+            RouteInput::Udp(source) => source.start()?,
+            RouteInput::Bus(link) => link.start(),
+        }
+        Ok(())
+    }
+
+    pub fn pause(&self) -> Result<()> {
+        match self {
+            RouteInput::Device(stream) => stream.pause()?,
+            RouteInput::Synthetic(source) => source.stop(),
+            RouteInput::Udp(source) => source.stop(),
+            RouteInput::Bus(link) => link.stop(),
+        }
+        Ok(())
+    }
+}
+
+struct SyntheticWorker {
+    generator: SignalGenerator,
+    callback: InputCallback,
+}
+
+pub struct SyntheticSource {
+    name: String,
+    running: Arc<AtomicBool>,
+    worker: Mutex<Option<SyntheticWorker>>,
+    thread: Mutex<Option<JoinHandle<SyntheticWorker>>>,
+}
+
+impl SyntheticSource {
+    pub fn new(name: &str, generator: SignalGenerator, callback: InputCallback) -> Self {
+        SyntheticSource {
+            name: name.to_string(),
+            running: Arc::new(AtomicBool::new(false)),
+            worker: Mutex::new(Some(SyntheticWorker {
+                generator,
+                callback,
+            })),
+            thread: Mutex::new(None),
+        }
+    }
+
+    fn start(&self) -> Result<()> {
+        let Some(mut worker) = self.worker.lock().ok().and_then(|mut w| w.take()) else {
+            return Ok(());
+        };
+
+        self.running.store(true, Ordering::SeqCst);
+        let running = self.running.clone();
+
+        let handle = thread::Builder::new()
+            .name(format!("source-{}", self.name))
+            .spawn(move || {
+                let block_duration = Duration::from_secs(1) / BLOCKS_PER_SECOND;
+                let mut block = vec![0.0; worker.generator.block_len()];
+                let mut next_block = Instant::now();
+
+                while running.load(Ordering::SeqCst) {
+                    worker.generator.fill(&mut block);
+                    (worker.callback)(&block);
+
+                    next_block += block_duration;
+                    if let Some(wait) = next_block.checked_duration_since(Instant::now()) {
+                        thread::sleep(wait);
+                    }
+                }
+
+                worker
+            })?;
+
+        if let Ok(mut thread) = self.thread.lock() {
+            *thread = Some(handle);
+        }
+        Ok(())
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        let handle = self.thread.lock().ok().and_then(|mut t| t.take());
+        if let Some(worker) = handle.and_then(|h| h.join().ok()) {
+            if let Ok(mut slot) = self.worker.lock() {
+                *slot = Some(worker);
+            }
+        }
+    }
+}
+
+impl Drop for SyntheticSource {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Receives RTP/L16 packets and feeds them to the input callback at a steady
+/// pace through a jitter buffer. Lost packets are replaced with silence. The
+/// socket is bound on start and released on stop, so a route rebuilt during
+/// recovery can take over the port.
+pub struct UdpSource {
+    name: String,
+    address: SocketAddr,
+    sample_rate: u32,
+    channels: usize,
+    running: Arc<AtomicBool>,
+    callback: Mutex<Option<InputCallback>>,
+    thread: Mutex<Option<JoinHandle<InputCallback>>>,
+}
+
+impl UdpSource {
+    pub fn new(
+        name: &str,
+        address: &str,
+        sample_rate: u32,
+        channels: u16,
+        callback: InputCallback,
+    ) -> Result<Self> {
+        let address = address
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve '{}'", address))?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("'{}' did not resolve to an address", address))?;
+
+        Ok(UdpSource {
+            name: name.to_string(),
+            address,
+            sample_rate,
+            channels: channels.max(1) as usize,
+            running: Arc::new(AtomicBool::new(false)),
+            callback: Mutex::new(Some(callback)),
+            thread: Mutex::new(None),
+        })
+    }
+
+    fn start(&self) -> Result<()> {
+        let Some(mut callback) = self.callback.lock().ok().and_then(|mut c| c.take()) else {
+            return Ok(());
+        };
+
+        let socket = match UdpSocket::bind(self.address) {
+            Ok(socket) => socket,
+            Err(e) => {
+                if let Ok(mut slot) = self.callback.lock() {
+                    *slot = Some(callback);
+                }
+                return Err(e).with_context(|| format!("Failed to bind {}", self.address));
+            }
+        };
+
+        self.running.store(true, Ordering::SeqCst);
+        let running = self.running.clone();
+        let name = self.name.clone();
+        let sample_rate = self.sample_rate;
+        let channels = self.channels;
+
+        let handle = thread::Builder::new()
+            .name(format!("source-{}", self.name))
+            .spawn(move || {
+                let mut datagram = vec![0u8; MAX_DATAGRAM_LEN];
+                let mut jitter = JitterBuffer::default();
+                let mut silence = Vec::new();
+                let mut packet_interval = DEFAULT_PACKET_INTERVAL;
+                let mut next_tick = Instant::now();
+                let mut last_packet: Option<Instant> = None;
+                let mut receive_failing = false;
+
+                while running.load(Ordering::SeqCst) {
+                    let wait = next_tick
+                        .saturating_duration_since(Instant::now())
+                        .max(Duration::from_millis(1));
+                    let _ = socket.set_read_timeout(Some(wait));
+
+                    match socket.recv(&mut datagram) {
+                        Ok(len) => {
+                            receive_failing = false;
+                            if let Some((sequence, payload)) = rtp::parse(&datagram[..len]) {
+                                let mut samples = Vec::with_capacity(payload.len() / 2);
+                                rtp::decode_l16(payload, &mut samples);
+                                let frames = (samples.len() / channels) as u32;
+                                if frames > 0 {
+                                    packet_interval = Duration::from_secs(1) * frames / sample_rate;
+                                    jitter.insert(sequence, samples);
+                                    last_packet = Some(Instant::now());
+                                }
+                            }
+                        }
+                        Err(e)
+                            if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                        Err(e) if !receive_failing => {
+                            warn!("UDP source '{}' failed to receive: {}", name, e);
+                            receive_failing = true;
+                        }
+                        Err(_) => {}
+                    }
+
+                    if Instant::now() < next_tick {
+                        continue;
+                    }
+                    next_tick += packet_interval;
+
+                    if last_packet.is_some_and(|at| at.elapsed() >= NETWORK_IDLE_TIMEOUT) {
+                        info!("UDP source '{}' stopped receiving, rebuffering", name);
+                        jitter.reset();
+                        last_packet = None;
+                    }
+
+                    if let Some(samples) = jitter.pop() {
+                        silence.resize(samples.len(), 0.0);
+                        callback(&samples);
+                    } else if jitter.is_playing() {
+                        callback(&silence);
+                    }
+                }
+
+                if jitter.lost > 0 {
+                    debug!("UDP source '{}' lost {} packets", name, jitter.lost);
+                }
+                callback
+            })?;
+
+        if let Ok(mut thread) = self.thread.lock() {
+            *thread = Some(handle);
+        }
+        info!("Listening for RTP on {}", self.address);
+        Ok(())
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        let handle = self.thread.lock().ok().and_then(|mut t| t.take());
+        if let Some(callback) = handle.and_then(|h| h.join().ok()) {
+            if let Ok(mut slot) = self.callback.lock() {
+                *slot = Some(callback);
+            }
+        }
+    }
+}
+
+impl Drop for UdpSource {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Reorders packets by sequence number. Playout starts once
+/// `JITTER_BUFFER_PACKETS` are queued; from then on every tick releases the
+/// next sequence number, or skips it as lost when later packets are waiting.
+#[derive(Default)]
+struct JitterBuffer {
+    packets: BTreeMap<u64, Vec<f32>>,
+    next_sequence: Option<u64>,
+    playing: bool,
+    lost: u64,
+}
+
+impl JitterBuffer {
+    const RESYNC_DISTANCE: u64 = JITTER_BUFFER_PACKETS as u64 * 2;
+
+    fn insert(&mut self, sequence: u16, samples: Vec<f32>) {
+        // Sequence numbers are extended to 64 bits relative to the next one
+        // due, starting one wrap in so earlier packets stay representable.
+        let next = *self
+            .next_sequence
+            .get_or_insert(u64::from(sequence) + (1 << 16));
+        let offset = sequence.wrapping_sub(next as u16) as i16;
+        let Some(key) = next.checked_add_signed(i64::from(offset)) else {
+            return;
+        };
+        if key < next {
+            if next - key > Self::RESYNC_DISTANCE {
+                // Far behind the playout position: the sender restarted.
+                self.reset();
+                self.insert(sequence, samples);
+            }
+            return;
+        }
+
+        self.packets.insert(key, samples);
+
+        // Sender clock running faster than ours: drop the oldest audio
+        // rather than letting latency grow without bound.
+        while self.packets.len() > JITTER_BUFFER_PACKETS * 2 {
+            if let Some((key, _)) = self.packets.pop_first() {
+                self.next_sequence = Some(key + 1);
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<Vec<f32>> {
+        if !self.playing {
+            if self.packets.len() < JITTER_BUFFER_PACKETS {
+                return None;
+            }
+            self.playing = true;
+            self.next_sequence = self.packets.keys().next().copied();
+        }
+
+        // Nothing queued: hold the position so a late stream resumes in order.
+        let (&first, _) = self.packets.first_key_value()?;
+        let mut next = self.next_sequence.unwrap_or(first);
+        if first > next + Self::RESYNC_DISTANCE {
+            self.lost += first - next;
+            next = first;
+        }
+        self.next_sequence = Some(next + 1);
+
+        let packet = self.packets.remove(&next);
+        if packet.is_none() {
+            self.lost += 1;
+        }
+        packet
+    }
+
+    fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    fn reset(&mut self) {
+        self.packets.clear();
+        self.next_sequence = None;
+        self.playing = false;
+    }
+}
+
+/// Generates a deterministic interleaved test signal.
+pub struct SignalGenerator {
+    config: TestSignalConfig,
+    sample_rate: u32,
+    phase: f32,
+    noise_state: u32,
+}
+
+impl SignalGenerator {
+    pub fn new(config: TestSignalConfig, sample_rate: u32) -> Self {
+        SignalGenerator {
+            config,
+            sample_rate,
+            phase: 0.0,
+            noise_state: 0x1234_5678,
+        }
+    }
+
+    fn block_len(&self) -> usize {
+        (self.sample_rate / BLOCKS_PER_SECOND).max(1) as usize * self.config.channels as usize
+    }
+
+    pub fn fill(&mut self, block: &mut [f32]) {
+        let channels = self.config.channels.max(1) as usize;
+        let phase_step = 2.0 * PI * self.config.frequency / self.sample_rate as f32;
+
+        for frame in block.chunks_mut(channels) {
+            let value = match self.config.waveform {
+                Waveform::Sine => {
+                    let value = self.phase.sin();
+                    self.phase = (self.phase + phase_step) % (2.0 * PI);
+                    value
+                }
+                Waveform::Noise => self.next_noise(),
+            };
+            frame.fill(value * self.config.amplitude);
+        }
+    }
+
+    fn next_noise(&mut self) -> f32 {
+        self.noise_state ^= self.noise_state << 13;
+        self.noise_state ^= self.noise_state >> 17;
+        self.noise_state ^= self.noise_state << 5;
+        (self.noise_state >> 8) as f32 / (1u32 << 23) as f32 - 1.0
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::Config;
+
+const STATE_FILE_NAME: &str = "state.json";
+
+/// Runtime adjustments layered on top of `config.yaml`, kept in `state.json`
+/// when `persist_runtime_state` is enabled so they survive restarts.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RuntimeState {
+    #[serde(default)]
+    pub routes: BTreeMap<String, RouteState>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RouteState {
+    pub gain: f32,
+    #[serde(default)]
+    pub muted: bool,
+}
+
+impl RuntimeState {
+    /// Loads the saved state; a missing file yields an empty state.
+    pub fn load() -> Result<Self> {
+        let path = state_path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read state from: {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse state file: {}", path.display()))
+    }
+
+    /// Writes the state atomically (temp file + rename).
+    pub fn save(&self) -> Result<()> {
+        let path = state_path()?;
+        let tmp_path = path.with_extension("json.tmp");
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize state")?;
+        fs::write(&tmp_path, json)
+            .with_context(|| format!("Failed to write state to: {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Failed to replace state file: {}", path.display()))?;
+        Ok(())
+    }
+}
+
+fn state_path() -> Result<PathBuf> {
+    Ok(Config::get_config_dir()?.join(STATE_FILE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gain_and_mute_survive_a_save_and_load() {
+        let state = RuntimeState {
+            routes: BTreeMap::from([
+                (
+                    "mic".to_string(),
+                    RouteState {
+                        gain: 0.5,
+                        muted: true,
+                    },
+                ),
+                (
+                    "desktop \"main\"".to_string(),
+                    RouteState {
+                        gain: 1.25,
+                        muted: false,
+                    },
+                ),
+            ]),
+        };
+
+        let json = serde_json::to_string_pretty(&state).unwrap();
+        let loaded: RuntimeState = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.routes, state.routes);
+    }
+
+    #[test]
+    fn state_files_without_mute_load_unmuted() {
+        let loaded: RuntimeState =
+            serde_json::from_str(r#"{ "routes": { "mic": { "gain": 2.0 } } }"#).unwrap();
+        assert_eq!(
+            loaded.routes["mic"],
+            RouteState {
+                gain: 2.0,
+                muted: false
+            }
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::error::{Result, RouterError};
+
+const STATUS_FILE_NAME: &str = "status.json";
+
+/// Counters shared between a route's audio callbacks and the status writer.
+#[derive(Default)]
+pub struct RouteStats {
+    clipped: AtomicU64,
+    peak_bits: AtomicU32,
+    /// Sum of squared samples (`f64` bits) and sample count since the
+    /// previous `take_rms`.
+    square_sum_bits: AtomicU64,
+    level_samples: AtomicU64,
+    underruns: AtomicU64,
+    overruns: AtomicU64,
+    /// Frames in the latest input and output callback, which is what the
+    /// host actually delivers whatever buffer size was requested.
+    input_frames: AtomicU64,
+    output_frames: AtomicU64,
+    /// Lifetime sample counts: every sample the input offered to the ring
+    /// buffer, including ones dropped because it was full, and every sample
+    /// the output asked for, including ones filled in because it was empty.
+    /// They are not reset after warmup.
+    samples_pushed: AtomicU64,
+    samples_popped: AtomicU64,
+    pub input_callbacks: CallbackTiming,
+    pub output_callbacks: CallbackTiming,
+}
+
+/// Time spent inside one side's audio callbacks, recorded only with
+/// `audio.profile_callbacks` and reset each time it is reported.
+#[derive(Default)]
+pub struct CallbackTiming {
+    count: AtomicU64,
+    total_ns: AtomicU64,
+    max_ns: AtomicU64,
+}
+
+impl CallbackTiming {
+    pub fn record(&self, elapsed: Duration) {
+        let nanos = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_ns.fetch_add(nanos, Ordering::Relaxed);
+        self.max_ns.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// Returns the average and maximum callback time since the previous
+    /// call, or `None` if no callback ran.
+    pub fn take(&self) -> Option<(Duration, Duration)> {
+        let count = self.count.swap(0, Ordering::Relaxed);
+        let total = self.total_ns.swap(0, Ordering::Relaxed);
+        let max = self.max_ns.swap(0, Ordering::Relaxed);
+        if count == 0 {
+            return None;
+        }
+        Some((
+            Duration::from_nanos(total / count),
+            Duration::from_nanos(max),
+        ))
+    }
+}
+
+/// Per-callback accumulator, committed to `RouteStats` once per block so the
+/// audio callback touches the shared atomics only a handful of times.
+#[derive(Default)]
+pub struct BlockStats {
+    pub clipped: u64,
+    pub dropped: u64,
+    pub pushed: u64,
+    pub peak: f32,
+    pub square_sum: f64,
+    pub samples: u64,
+}
+
+impl BlockStats {
+    pub fn observe(&mut self, sample: f32) {
+        self.peak = self.peak.max(sample.abs());
+        self.square_sum += (sample * sample) as f64;
+        self.samples += 1;
+    }
+}
+
+impl RouteStats {
+    pub fn record_block(&self, block: &BlockStats) {
+        if block.clipped > 0 {
+            self.clipped.fetch_add(block.clipped, Ordering::Relaxed);
+        }
+        if block.dropped > 0 {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+        self.record_pushed(block.pushed);
+        // Non-negative f32 bit patterns order the same as the floats they encode.
+        self.peak_bits
+            .fetch_max(block.peak.to_bits(), Ordering::Relaxed);
+        if block.samples > 0 {
+            let _ =
+                self.square_sum_bits
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                        Some((f64::from_bits(bits) + block.square_sum).to_bits())
+                    });
+            self.level_samples
+                .fetch_add(block.samples, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_clipped(&self, count: u64) {
+        self.clipped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_pushed(&self, samples: u64) {
+        self.samples_pushed.fetch_add(samples, Ordering::Relaxed);
+    }
+
+    pub fn record_popped(&self, samples: u64) {
+        self.samples_popped.fetch_add(samples, Ordering::Relaxed);
+    }
+
+    pub fn samples_pushed(&self) -> u64 {
+        self.samples_pushed.load(Ordering::Relaxed)
+    }
+
+    pub fn samples_popped(&self) -> u64 {
+        self.samples_popped.load(Ordering::Relaxed)
+    }
+
+    pub fn record_underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_input_frames(&self, frames: usize) {
+        self.input_frames.store(frames as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_output_frames(&self, frames: usize) {
+        self.output_frames.store(frames as u64, Ordering::Relaxed);
+    }
+
+    /// Frames per input and output callback, or `None` until both sides
+    /// have run.
+    pub fn callback_frames(&self) -> Option<(u64, u64)> {
+        let input = self.input_frames.load(Ordering::Relaxed);
+        let output = self.output_frames.load(Ordering::Relaxed);
+        (input > 0 && output > 0).then_some((input, output))
+    }
+
+    pub fn clipped(&self) -> u64 {
+        self.clipped.load(Ordering::Relaxed)
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    pub fn overruns(&self) -> u64 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Zeroes the counters and levels, discarding what was recorded while
+    /// the route was settling.
+    pub fn reset(&self) {
+        self.clipped.store(0, Ordering::Relaxed);
+        self.underruns.store(0, Ordering::Relaxed);
+        self.overruns.store(0, Ordering::Relaxed);
+        self.peak_bits.store(0, Ordering::Relaxed);
+        self.take_rms();
+    }
+
+    /// Adds the counts `previous` recorded to these, so a route rebuilt
+    /// after a device failure keeps its lifetime sample totals and its clip,
+    /// underrun and overrun counts.
+    pub fn carry_over(&self, previous: &RouteStats) {
+        for (counter, previous) in [
+            (&self.clipped, &previous.clipped),
+            (&self.underruns, &previous.underruns),
+            (&self.overruns, &previous.overruns),
+            (&self.samples_pushed, &previous.samples_pushed),
+            (&self.samples_popped, &previous.samples_popped),
+        ] {
+            counter.fetch_add(previous.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the peak level since the previous call and resets it.
+    pub fn take_peak(&self) -> f32 {
+        f32::from_bits(self.peak_bits.swap(0, Ordering::Relaxed))
+    }
+
+    /// Returns the RMS level since the previous call and resets it, or
+    /// `None` if no audio passed through the route.
+    pub fn take_rms(&self) -> Option<f32> {
+        let samples = self.level_samples.swap(0, Ordering::Relaxed);
+        let square_sum = f64::from_bits(self.square_sum_bits.swap(0, Ordering::Relaxed));
+        (samples > 0).then(|| (square_sum / samples as f64).sqrt() as f32)
+    }
+}
+
+#[derive(Serialize)]
+pub struct RouteStatus {
+    pub name: String,
+    pub from: String,
+    pub to: String,
+    pub from_device: String,
+    pub to_device: String,
+    pub gain: f32,
+    pub enabled: bool,
+    pub muted: bool,
+    pub peak: f32,
+    pub clipped: u64,
+    pub underruns: u64,
+    pub overruns: u64,
+    pub samples_pushed: u64,
+    pub samples_popped: u64,
+    #[serde(rename = "buffered_samples")]
+    pub buffered: usize,
+}
+
+pub struct ServiceStatus {
+    pub uptime: Duration,
+    /// Routes are still within `audio.warmup_ms` of starting.
+    pub stabilizing: bool,
+    pub routes: Vec<RouteStatus>,
+}
+
+/// The layout of `status.json`.
+#[derive(Serialize)]
+struct StatusFile<'a> {
+    updated_at: String,
+    uptime_secs: u64,
+    state: &'static str,
+    routes: &'a [RouteStatus],
+}
+
+impl ServiceStatus {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        let state = if self.stabilizing {
+            "stabilizing"
+        } else {
+            "active"
+        };
+        serde_json::to_string_pretty(&StatusFile {
+            updated_at: chrono::Local::now().to_rfc3339(),
+            uptime_secs: self.uptime.as_secs(),
+            state,
+            routes: &self.routes,
+        })
+    }
+}
+
+pub fn status_path() -> Result<PathBuf> {
+    Ok(Config::get_config_dir()?.join(STATUS_FILE_NAME))
+}
+
+/// Writes the status atomically (temp file + rename) so readers never see a
+/// partially written file.
+pub fn write_status(path: &Path, status: &ServiceStatus) -> Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    let json = status.to_json().map_err(|e| RouterError::Io {
+        action: "Failed to serialize status".to_string(),
+        source: e.into(),
+    })?;
+    fs::write(&tmp_path, json).map_err(|source| RouterError::Io {
+        action: format!("Failed to write status to: {}", tmp_path.display()),
+        source,
+    })?;
+    fs::rename(&tmp_path, path).map_err(|source| RouterError::Io {
+        action: format!("Failed to replace status file: {}", path.display()),
+        source,
+    })
+}
+
+pub fn read_status() -> Result<String> {
+    let path = status_path()?;
+
+    if !path.exists() {
+        return Err(RouterError::StatusNotFound(path));
+    }
+
+    fs::read_to_string(&path).map_err(|source| RouterError::Io {
+        action: format!("Failed to read status from: {}", path.display()),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovered_routes_keep_their_counts() {
+        let previous = RouteStats::default();
+        previous.record_pushed(1000);
+        previous.record_popped(900);
+        previous.record_clipped(3);
+        previous.record_underrun();
+
+        let recovered = RouteStats::default();
+        // The rebuilt route's prefill.
+        recovered.record_pushed(64);
+        recovered.carry_over(&previous);
+
+        assert_eq!(recovered.samples_pushed(), 1064);
+        assert_eq!(recovered.samples_popped(), 900);
+        assert_eq!(recovered.clipped(), 3);
+        assert_eq!(recovered.underruns(), 1);
+        assert_eq!(recovered.overruns(), 0);
+    }
+}
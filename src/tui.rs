@@ -0,0 +1,465 @@
+use anyhow::{Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use log::warn;
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Cell, Paragraph, Row, Table, TableState};
+use ratatui::{Frame, Terminal};
+use std::io::{self, Stdout};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use audio_router::status::{RouteStatus, ServiceStatus};
+use audio_router::{logger, LiveView, RouteCommand, VERSION};
+
+/// How often the router sends the view a snapshot.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the view waits for a snapshot before handling keys again.
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Meters span this many dB below full scale.
+const METER_FLOOR_DB: f32 = -60.0;
+const METER_WIDTH: u16 = 24;
+/// Gain change per key press.
+const GAIN_STEP_DB: f32 = 1.0;
+/// Gain `+` starts from when a route's gain is 0.
+const MIN_AUDIBLE_GAIN: f32 = 0.001;
+
+const ROUTE_WIDTH: u16 = 18;
+const PATH_WIDTH: u16 = 28;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Up,
+    Down,
+    Mute,
+    GainUp,
+    GainDown,
+    Quit,
+}
+
+/// Starts the live route table. Returns the `LiveView` to hand the router
+/// and the view's thread, which restores the terminal and exits once the
+/// router stops sending snapshots. `quit` is called when `q` or Ctrl+C is
+/// pressed.
+pub fn start(
+    log_path: PathBuf,
+    quit: impl Fn() + Send + 'static,
+) -> Result<(LiveView, JoinHandle<()>)> {
+    let (status_tx, status_rx) = mpsc::channel();
+    let (command_tx, command_rx) = mpsc::channel();
+
+    let mut screen = Screen::enter().context("Failed to set up the terminal")?;
+    let view = thread::Builder::new()
+        .name("tui".to_string())
+        .spawn(move || {
+            let mut table = RouteTable {
+                log_path,
+                status: None,
+                selected: 0,
+            };
+            if let Err(e) = table.run(&mut screen.terminal, &status_rx, &command_tx, &quit) {
+                warn!("Live view stopped: {}", e);
+            }
+            drop(screen);
+        })?;
+
+    Ok((
+        LiveView {
+            interval: REFRESH_INTERVAL,
+            status: status_tx,
+            commands: command_rx,
+        },
+        view,
+    ))
+}
+
+struct RouteTable {
+    log_path: PathBuf,
+    status: Option<ServiceStatus>,
+    selected: usize,
+}
+
+impl RouteTable {
+    fn run(
+        &mut self,
+        terminal: &mut Terminal<impl Backend<Error = io::Error>>,
+        status_rx: &Receiver<ServiceStatus>,
+        commands: &Sender<RouteCommand>,
+        quit: &dyn Fn(),
+    ) -> io::Result<()> {
+        loop {
+            let mut changed = match status_rx.recv_timeout(KEY_POLL_INTERVAL) {
+                Ok(status) => {
+                    self.status = Some(status);
+                    true
+                }
+                Err(RecvTimeoutError::Timeout) => false,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            };
+
+            while event::poll(Duration::ZERO)? {
+                let key = match event::read()? {
+                    Event::Key(key) => key_for(key),
+                    Event::Resize(..) => {
+                        changed = true;
+                        None
+                    }
+                    _ => None,
+                };
+                match key {
+                    Some(Key::Quit) => quit(),
+                    Some(key) => {
+                        if let Some(command) = self.handle_key(key) {
+                            let _ = commands.send(command);
+                        }
+                        changed = true;
+                    }
+                    None => {}
+                }
+            }
+
+            if changed {
+                terminal.draw(|frame| self.draw(frame))?;
+            }
+        }
+    }
+
+    /// Moves the selection, or returns the command a key asks for on the
+    /// selected route. The change is shown at once, so repeated presses
+    /// build on each other before the next snapshot arrives.
+    fn handle_key(&mut self, key: Key) -> Option<RouteCommand> {
+        let routes = &mut self.status.as_mut()?.routes;
+        if routes.is_empty() {
+            return None;
+        }
+        self.selected = self.selected.min(routes.len() - 1);
+        let route = &mut routes[self.selected];
+
+        match key {
+            Key::Up => {
+                self.selected = self.selected.saturating_sub(1);
+                None
+            }
+            Key::Down => {
+                self.selected = (self.selected + 1).min(routes.len() - 1);
+                None
+            }
+            Key::Mute => {
+                route.muted = !route.muted;
+                Some(RouteCommand::SetMuted {
+                    route: route.name.clone(),
+                    muted: route.muted,
+                })
+            }
+            Key::GainUp | Key::GainDown => {
+                let step = if key == Key::GainUp {
+                    GAIN_STEP_DB
+                } else {
+                    -GAIN_STEP_DB
+                };
+                route.gain = route.gain.max(MIN_AUDIBLE_GAIN) * 10f32.powf(step / 20.0);
+                Some(RouteCommand::SetGain {
+                    route: route.name.clone(),
+                    gain: route.gain,
+                })
+            }
+            Key::Quit => None,
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [title_area, table_area, help_area] = Layout::vertical([
+            Constraint::Length(2),
+            Constraint::Fill(1),
+            Constraint::Length(2),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Paragraph::new(vec![
+                Line::from("Up/Down select   m mute   +/- gain 1 dB   q quit"),
+                Line::from(format!("Log: {}", self.log_path.display())),
+            ]),
+            help_area,
+        );
+
+        let Some(status) = &self.status else {
+            frame.render_widget(Paragraph::new("Starting routes..."), title_area);
+            return;
+        };
+
+        let uptime = status.uptime.as_secs();
+        frame.render_widget(
+            Paragraph::new(format!(
+                "Audio Router {} | up {}:{:02}:{:02} | {}",
+                VERSION,
+                uptime / 3600,
+                uptime / 60 % 60,
+                uptime % 60,
+                if status.stabilizing {
+                    "stabilizing"
+                } else {
+                    "active"
+                }
+            )),
+            title_area,
+        );
+
+        if status.routes.is_empty() {
+            frame.render_widget(Paragraph::new("  No routes running"), table_area);
+            return;
+        }
+
+        let header = Row::new([
+            Cell::from("Route"),
+            Cell::from("From -> To"),
+            right("Gain"),
+            Cell::from("Level"),
+            Cell::from(""),
+            right("Under"),
+            right("Over"),
+        ])
+        .style(Style::new().add_modifier(Modifier::BOLD));
+        let widths = [
+            Constraint::Length(ROUTE_WIDTH),
+            Constraint::Length(PATH_WIDTH),
+            Constraint::Length(9),
+            Constraint::Length(METER_WIDTH + 2),
+            Constraint::Length(9),
+            Constraint::Length(7),
+            Constraint::Length(7),
+            Constraint::Length(5),
+        ];
+        let table = Table::new(status.routes.iter().map(route_row), widths)
+            .header(header)
+            .highlight_symbol("> ")
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut state = TableState::new().with_selected(Some(self.selected));
+        frame.render_stateful_widget(table, table_area, &mut state);
+    }
+}
+
+fn route_row(route: &RouteStatus) -> Row<'_> {
+    let level_db = to_db(route.peak);
+    let filled = ((level_db - METER_FLOOR_DB) / -METER_FLOOR_DB * METER_WIDTH as f32)
+        .clamp(0.0, METER_WIDTH as f32) as usize;
+    let state = if route.muted {
+        "MUTED"
+    } else if !route.enabled {
+        "off"
+    } else {
+        ""
+    };
+
+    Row::new([
+        Cell::from(route.name.as_str()),
+        Cell::from(format!("{} -> {}", route.from, route.to)),
+        right(format_db(to_db(route.gain))),
+        Cell::from(format!(
+            "[{}{}]",
+            "#".repeat(filled),
+            "-".repeat(METER_WIDTH as usize - filled)
+        )),
+        right(format_db(level_db)),
+        right(route.underruns.to_string()),
+        right(route.overruns.to_string()),
+        Cell::from(state),
+    ])
+}
+
+fn right<'a>(text: impl Into<String>) -> Cell<'a> {
+    Cell::from(Line::from(text.into()).right_aligned())
+}
+
+fn to_db(linear: f32) -> f32 {
+    if linear > 0.0 {
+        20.0 * linear.log10()
+    } else {
+        f32::NEG_INFINITY
+    }
+}
+
+fn format_db(db: f32) -> String {
+    if db <= METER_FLOOR_DB {
+        "-inf dB".to_string()
+    } else {
+        format!("{:+.1} dB", db)
+    }
+}
+
+/// The key a key press stands for. Raw mode delivers Ctrl+C as a key press
+/// rather than a signal, so it quits like `q`. Windows also reports key
+/// releases, which are ignored.
+fn key_for(event: KeyEvent) -> Option<Key> {
+    if event.kind != KeyEventKind::Press {
+        return None;
+    }
+    match event.code {
+        KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => Some(Key::Quit),
+        KeyCode::Up | KeyCode::Char('k') => Some(Key::Up),
+        KeyCode::Down | KeyCode::Char('j') => Some(Key::Down),
+        KeyCode::Char('m' | ' ') => Some(Key::Mute),
+        KeyCode::Char('+' | '=') => Some(Key::GainUp),
+        KeyCode::Char('-' | '_') => Some(Key::GainDown),
+        KeyCode::Char('q' | 'Q') => Some(Key::Quit),
+        _ => None,
+    }
+}
+
+/// Owns the terminal while the view runs: raw mode so keys arrive as they
+/// are pressed, the alternate screen with the cursor hidden, and no log
+/// lines printed over the table. Everything is restored when dropped.
+struct Screen {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+}
+
+impl Screen {
+    fn enter() -> io::Result<Self> {
+        let terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        terminal::enable_raw_mode()?;
+        logger::set_console_echo(false);
+        // From here on, dropping the screen undoes whatever was set up.
+        let mut screen = Screen { terminal };
+        execute!(
+            screen.terminal.backend_mut(),
+            EnterAlternateScreen,
+            Clear(ClearType::All)
+        )?;
+        screen.terminal.hide_cursor()?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(self.terminal.backend_mut(), LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+        let _ = terminal::disable_raw_mode();
+        logger::set_console_echo(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    fn route(name: &str, gain: f32) -> RouteStatus {
+        RouteStatus {
+            name: name.to_string(),
+            from: "mic".to_string(),
+            to: "speakers".to_string(),
+            from_device: String::new(),
+            to_device: String::new(),
+            gain,
+            enabled: true,
+            muted: false,
+            peak: 0.5,
+            clipped: 0,
+            underruns: 3,
+            overruns: 0,
+            samples_pushed: 0,
+            samples_popped: 0,
+            buffered: 0,
+        }
+    }
+
+    fn table(routes: Vec<RouteStatus>) -> RouteTable {
+        RouteTable {
+            log_path: PathBuf::from("audio_router.log"),
+            status: Some(ServiceStatus {
+                uptime: Duration::from_secs(3725),
+                stabilizing: false,
+                routes,
+            }),
+            selected: 0,
+        }
+    }
+
+    #[test]
+    fn keys_map_to_actions() {
+        let press = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        assert_eq!(key_for(press(KeyCode::Up)), Some(Key::Up));
+        assert_eq!(key_for(press(KeyCode::Char('j'))), Some(Key::Down));
+        assert_eq!(key_for(press(KeyCode::Char(' '))), Some(Key::Mute));
+        assert_eq!(key_for(press(KeyCode::Char('='))), Some(Key::GainUp));
+        assert_eq!(key_for(press(KeyCode::Char('q'))), Some(Key::Quit));
+        assert_eq!(key_for(press(KeyCode::Char('c'))), None);
+        assert_eq!(
+            key_for(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            Some(Key::Quit)
+        );
+
+        let mut release = press(KeyCode::Char('m'));
+        release.kind = KeyEventKind::Release;
+        assert_eq!(key_for(release), None);
+    }
+
+    #[test]
+    fn keys_act_on_the_selected_route() {
+        let mut table = table(vec![route("a", 1.0), route("b", 0.0)]);
+
+        assert_eq!(table.handle_key(Key::Up), None);
+        assert_eq!(table.selected, 0);
+        table.handle_key(Key::Down);
+        table.handle_key(Key::Down);
+        assert_eq!(table.selected, 1);
+
+        assert_eq!(
+            table.handle_key(Key::Mute),
+            Some(RouteCommand::SetMuted {
+                route: "b".to_string(),
+                muted: true
+            })
+        );
+        let Some(RouteCommand::SetGain { route, gain }) = table.handle_key(Key::GainUp) else {
+            panic!("expected SetGain");
+        };
+        assert_eq!(route, "b");
+        assert!((gain - MIN_AUDIBLE_GAIN * 10f32.powf(0.05)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn keys_before_the_first_snapshot_do_nothing() {
+        let mut table = table(Vec::new());
+        table.status = None;
+        assert_eq!(table.handle_key(Key::Mute), None);
+    }
+
+    #[test]
+    fn the_table_shows_each_route() {
+        let mut muted = route("music", 0.5);
+        muted.muted = true;
+        let table = table(vec![route("voice", 1.0), muted]);
+
+        let mut terminal = Terminal::new(TestBackend::new(110, 10)).unwrap();
+        terminal.draw(|frame| table.draw(frame)).unwrap();
+        let lines: Vec<String> = terminal
+            .backend()
+            .buffer()
+            .content()
+            .chunks(110)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect())
+            .collect();
+
+        assert!(lines[0].contains("up 1:02:05 | active"), "{}", lines[0]);
+        assert!(lines[2].contains("Route"), "{}", lines[2]);
+        assert!(lines[3].starts_with("> voice"), "{}", lines[3]);
+        assert!(lines[3].contains("+0.0 dB"), "{}", lines[3]);
+        assert!(
+            lines[3].contains("[#####################---]"),
+            "{}",
+            lines[3]
+        );
+        assert!(lines[4].contains("mic -> speakers"), "{}", lines[4]);
+        assert!(lines[4].contains("-6.0 dB"), "{}", lines[4]);
+        assert!(lines[4].trim_end().ends_with("MUTED"), "{}", lines[4]);
+        assert!(lines[9].contains("Log: audio_router.log"), "{}", lines[9]);
+    }
+}
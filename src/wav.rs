@@ -0,0 +1,161 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::{Result, RouterError};
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Interleaved audio read from or written to a WAV file, as `f32` samples in
+/// the -1.0..1.0 range.
+pub struct Wav {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<f32>,
+}
+
+impl Wav {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    /// Whether `other` has the same sample rate, channel count and length.
+    pub fn same_shape(&self, other: &Wav) -> bool {
+        (self.sample_rate, self.channels, self.samples.len())
+            == (other.sample_rate, other.channels, other.samples.len())
+    }
+
+    /// The largest per-sample difference from `other` and the frame it
+    /// occurs in. Both must have the same shape.
+    pub fn max_deviation(&self, other: &Wav) -> (usize, f32) {
+        let (worst_index, deviation) = self
+            .samples
+            .iter()
+            .zip(&other.samples)
+            .map(|(a, b)| (a - b).abs())
+            .enumerate()
+            .fold(
+                (0, 0.0f32),
+                |worst, (i, d)| if d > worst.1 { (i, d) } else { worst },
+            );
+        (worst_index / self.channels.max(1) as usize, deviation)
+    }
+
+    /// Reads 16/24/32-bit integer PCM or 32-bit float WAV files.
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).map_err(|source| RouterError::Io {
+            action: format!("Failed to read WAV: {}", path.display()),
+            source,
+        })?;
+        Self::parse(&bytes).map_err(|reason| RouterError::InvalidWav {
+            path: path.to_path_buf(),
+            reason,
+        })
+    }
+
+    /// Parses a WAV file's bytes, or says why they are not one.
+    fn parse(bytes: &[u8]) -> std::result::Result<Self, String> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err("not a RIFF/WAVE file".to_string());
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut offset = 12;
+        while offset + 8 <= bytes.len() {
+            let id = &bytes[offset..offset + 4];
+            let size = u32::from_le_bytes([
+                bytes[offset + 4],
+                bytes[offset + 5],
+                bytes[offset + 6],
+                bytes[offset + 7],
+            ]) as usize;
+            let body = &bytes[offset + 8..(offset + 8 + size).min(bytes.len())];
+            match id {
+                b"fmt " => format = Some(body),
+                b"data" => data = Some(body),
+                _ => {}
+            }
+            // Chunks are padded to an even length.
+            offset += 8 + size + (size & 1);
+        }
+
+        let format = format.ok_or_else(|| "missing fmt chunk".to_string())?;
+        let data = data.ok_or_else(|| "missing data chunk".to_string())?;
+        if format.len() < 16 {
+            return Err("fmt chunk too short".to_string());
+        }
+
+        let mut tag = u16::from_le_bytes([format[0], format[1]]);
+        let channels = u16::from_le_bytes([format[2], format[3]]);
+        let sample_rate = u32::from_le_bytes([format[4], format[5], format[6], format[7]]);
+        let bits = u16::from_le_bytes([format[14], format[15]]);
+        if tag == FORMAT_EXTENSIBLE && format.len() >= 26 {
+            tag = u16::from_le_bytes([format[24], format[25]]);
+        }
+        if channels == 0 {
+            return Err("zero channels".to_string());
+        }
+
+        let samples = match (tag, bits) {
+            (FORMAT_PCM, 16) => data
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+                .collect(),
+            (FORMAT_PCM, 24) => data
+                .chunks_exact(3)
+                .map(|b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2_147_483_648.0)
+                .collect(),
+            (FORMAT_PCM, 32) => data
+                .chunks_exact(4)
+                .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0)
+                .collect(),
+            (FORMAT_FLOAT, 32) => data
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+            _ => {
+                return Err(format!(
+                    "unsupported format {} with {} bits per sample",
+                    tag, bits
+                ))
+            }
+        };
+
+        Ok(Wav {
+            sample_rate,
+            channels,
+            samples,
+        })
+    }
+
+    /// Writes a 32-bit float WAV, so no precision is lost to quantization.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let data_len = (self.samples.len() * 4) as u32;
+        let block_align = self.channels * 4;
+        let mut bytes = Vec::with_capacity(44 + data_len as usize);
+
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&FORMAT_FLOAT.to_le_bytes());
+        bytes.extend_from_slice(&self.channels.to_le_bytes());
+        bytes.extend_from_slice(&self.sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(self.sample_rate * block_align as u32).to_le_bytes());
+        bytes.extend_from_slice(&block_align.to_le_bytes());
+        bytes.extend_from_slice(&32u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        for sample in &self.samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        fs::write(path, bytes).map_err(|source| RouterError::Io {
+            action: format!("Failed to write WAV: {}", path.display()),
+            source,
+        })
+    }
+}
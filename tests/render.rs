@@ -0,0 +1,64 @@
+//! Renders a committed input WAV through a route and checks the result
+//! against a committed reference, so DSP changes can't slip through
+//! unnoticed. `audio_router render` regenerates the reference after an
+//! intentional change; see `tests/data/render/config.yaml`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use audio_router::audio::render_route;
+use audio_router::wav::Wav;
+use audio_router::Config;
+
+const ROUTE: &str = "mic_to_speakers";
+
+/// Same default as `render --compare`.
+const TOLERANCE: f32 = 1e-5;
+
+fn data_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/render")
+}
+
+fn load_config() -> Config {
+    let yaml = fs::read_to_string(data_dir().join("config.yaml")).unwrap();
+    Config::from_yaml(&yaml).unwrap()
+}
+
+fn read_wav(name: &str) -> Wav {
+    Wav::read(&data_dir().join(name)).unwrap()
+}
+
+#[test]
+fn route_output_matches_the_reference() {
+    let rendered = render_route(&load_config(), ROUTE, &read_wav("input.wav"), None).unwrap();
+    let reference = read_wav("reference.wav");
+
+    assert!(
+        rendered.same_shape(&reference),
+        "rendered {} Hz x{} ({} frames), reference {} Hz x{} ({} frames)",
+        rendered.sample_rate,
+        rendered.channels,
+        rendered.frames(),
+        reference.sample_rate,
+        reference.channels,
+        reference.frames()
+    );
+    let (frame, deviation) = rendered.max_deviation(&reference);
+    assert!(
+        deviation <= TOLERANCE,
+        "deviation {} at frame {} exceeds {}",
+        deviation,
+        frame,
+        TOLERANCE
+    );
+}
+
+#[test]
+fn a_processing_change_is_caught() {
+    let mut config = load_config();
+    config.routing.get_mut(ROUTE).unwrap().eq[0].gain_db += 0.5;
+
+    let rendered = render_route(&config, ROUTE, &read_wav("input.wav"), None).unwrap();
+    let (_, deviation) = rendered.max_deviation(&read_wav("reference.wav"));
+    assert!(deviation > TOLERANCE, "deviation {}", deviation);
+}